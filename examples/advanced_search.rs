@@ -56,8 +56,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     tree.insert(&post2)?;
-    // Waiting so the new blog post is indexed.
-    std::thread::sleep(std::time::Duration::from_secs(1));
+    // Waiting until the new blog post is indexed.
+    search_engine.wait_until_indexed()?;
 
     // Creating queries for the search engine.
     // Creating a range query for dates is a little cumbersome.
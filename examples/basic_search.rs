@@ -48,20 +48,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     tree.insert(&post2)?;
-    // Waiting so the new blog post is indexed.
-    std::thread::sleep(std::time::Duration::from_secs(1));
+    // Waiting until the new blog post is indexed.
+    search_engine.wait_until_indexed()?;
 
     // Printing the results of the searches
     println!("Searching for Blog Posts matching the query \"life\"");
     let results = search_engine.search("life", 10)?;
     for result in results.iter() {
-        println!("Found Blog Post with score {}:\n{:#?}", result.0, result.1);
+        println!(
+            "Found Blog Post with score {}:\n{:#?}",
+            result.score, result.value
+        );
     }
 
     println!("\nSearching for Blog Posts matching the query \"happy\"");
     let results = search_engine.search("happy", 10)?;
     for result in results.iter() {
-        println!("Found Blog Post with score {}:\n{:#?}", result.0, result.1);
+        println!(
+            "Found Blog Post with score {}:\n{:#?}",
+            result.score, result.value
+        );
     }
 
     Ok(())
@@ -1,29 +1,43 @@
 //! Convert one typed [Tree][crate::Tree] into another.
+//!
+//! The conversion is streaming, checkpointed and resumable. The converted entries
+//! only replace the original tree atomically if it was opened through a [Db][crate::Db],
+//! see [custom_serde::convert][crate::custom_serde::convert] for details.
+//!
 //! # Example
 //! ```
-//! pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use typed_sled::{convert::convert, Tree};
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     let db = sled::Config::new().temporary(true).open().unwrap();
-//!  
+//!
 //!     {
 //!         let old_tree: Tree<u32, u32> = Tree::open(&db, "test_tree");
-//!  
+//!
 //!         old_tree.insert(&1, &2)?;
 //!         old_tree.insert(&3, &4)?;
 //!         old_tree.flush()?;
 //!     }
-//!  
-//!     convert::<u32, u32, u64, u64>(&db, "test_tree");
+//!
+//!     let stats = convert::<u32, u32, u64, u64>(&db, "test_tree")?;
+//!     assert_eq!(stats.converted, 2);
 //!     let tree: Tree<u64, u64> = Tree::open(&db, "test_tree");
 //!     assert_eq!(tree.get(&1)?.unwrap(), 2);
 //!     assert_eq!(tree.get(&3)?.unwrap(), 4);
 //!     Ok(())
 //! }
 //! ```
-use crate::{Tree, KV};
+use crate::custom_serde::convert::convert_with_chunk_size as convert_custom;
+pub use crate::custom_serde::convert::{ConvertStats, DEFAULT_CHUNK_SIZE};
+use crate::custom_serde::serialize::OrderedSerDe;
+use crate::{Result, KV};
 use std::convert::Into;
 
-/// Convert `Tree<KOld, VOld>` to `Tree<KNew, VNew>`
-pub fn convert<KOld, VOld, KNew, VNew>(db: &sled::Db, tree: &str)
+/// Convert `Tree<KOld, VOld>` to `Tree<KNew, VNew>`.
+///
+/// The tree is only replaced atomically if it was opened through a [Db][crate::Db],
+/// see the [module documentation][self].
+pub fn convert<KOld, VOld, KNew, VNew>(db: &sled::Db, tree: &str) -> Result<ConvertStats>
 where
     KOld: Into<KNew>,
     VOld: Into<VNew>,
@@ -32,26 +46,31 @@ where
     KNew: KV,
     VNew: KV,
 {
-    let mut kvs = Vec::new();
-
-    {
-        let tree: Tree<KOld, VOld> = Tree::open(db, tree);
-
-        for kv_pair in tree.iter() {
-            kvs.push(kv_pair.unwrap());
-        }
-    }
-
-    db.drop_tree(tree).unwrap();
-    let tree: Tree<KNew, VNew> = Tree::open(db, tree);
+    convert_with_chunk_size::<KOld, VOld, KNew, VNew>(db, tree, DEFAULT_CHUNK_SIZE)
+}
 
-    for kv_pair in kvs.drain(..) {
-        tree.insert(&kv_pair.0.into(), &kv_pair.1.into()).unwrap();
-    }
+/// Like [convert], but with a custom number of entries per chunk.
+pub fn convert_with_chunk_size<KOld, VOld, KNew, VNew>(
+    db: &sled::Db,
+    tree: &str,
+    chunk_size: usize,
+) -> Result<ConvertStats>
+where
+    KOld: Into<KNew>,
+    VOld: Into<VNew>,
+    KOld: KV,
+    VOld: KV,
+    KNew: KV,
+    VNew: KV,
+{
+    // Tree<K, V> uses the same encoding as custom_serde::Tree<K, V, OrderedSerDe>.
+    convert_custom::<KOld, VOld, KNew, VNew, OrderedSerDe, OrderedSerDe>(db, tree, chunk_size)
 }
 
 #[test]
 fn test_convert() {
+    use crate::Tree;
+
     let config = sled::Config::new().temporary(true);
     let db = config.open().unwrap();
 
@@ -63,7 +82,7 @@ fn test_convert() {
         old_tree.flush().unwrap();
     }
 
-    convert::<u32, u32, u64, u64>(&db, "test_tree");
+    convert::<u32, u32, u64, u64>(&db, "test_tree").unwrap();
     let tree: Tree<u64, u64> = Tree::open(&db, "test_tree");
     assert_eq!(tree.get(&1).unwrap().unwrap(), 2);
     assert_eq!(tree.get(&3).unwrap().unwrap(), 4);
@@ -1,69 +1,582 @@
-//! Convert one typed [Tree][crate::Tree] into another.
+//! Convert one typed [Tree][crate::custom_serde::Tree] into another.
+//!
+//! The conversion streams the entries of the tree into a temporary tree in
+//! chunks of bounded size. Every chunk is written atomically together with a
+//! checkpoint, so a conversion that was interrupted, for example by a crash,
+//! continues where it stopped when [convert] is called again with the same types.
+//! The tree must not be written to while it is being converted.
+//!
+//! # Replacing the tree
+//!
+//! If the tree was opened through a [Db][crate::Db], the converted entries replace
+//! the original ones atomically: the registry entry of the tree is switched to the
+//! converted tree in a single transaction, before the original tree is dropped.
+//! Until then, the tree can be read as usual. Trees opened through the `Db` after
+//! the conversion see the converted entries, trees opened before keep pointing to
+//! the dropped original tree and have to be opened again.
+//!
+//! sled can't rename trees, so other trees can't be replaced atomically. Once all
+//! entries are converted, the original tree is dropped and the converted entries
+//! are copied back from the temporary tree, in checkpointed chunks as well. While
+//! the entries are copied back, readers see a missing or partially filled tree. If
+//! the conversion is interrupted during this step, the tree stays partially filled
+//! until [convert] is called again, which finishes copying.
+//!
 //! # Example
 //! ```
-//! pub fn main() -> Result<(), Box<dyn std::error::Error>> {
-//!  let db = sled::Config::new().temporary(true).open().unwrap();
+//! use typed_sled::custom_serde::{convert::convert, serialize::BincodeSerDe, Tree};
 //!
-//!  {
-//!      let old_tree: Tree<u32, u32> = Tree::open(&db, "test_tree");
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let db = sled::Config::new().temporary(true).open().unwrap();
 //!
-//!      old_tree.insert(&1, &2)?;
-//!      old_tree.insert(&3, &4)?;
-//!      old_tree.flush()?;
-//!  }
+//!     {
+//!         let old_tree: Tree<u32, u32, BincodeSerDe> = Tree::open(&db, "test_tree");
 //!
-//!  convert::<u32, u32, u64, u64>(&db, "test_tree");
-//!  let tree: Tree<u64, u64> = Tree::open(&db, "test_tree");
-//!  assert_eq!(tree.get(&1)?.unwrap(), 2);
-//!  assert_eq!(tree.get(&3)?.unwrap(), 4);
-//! Ok(()) }
+//!         old_tree.insert(&1, &2)?;
+//!         old_tree.insert(&3, &4)?;
+//!         old_tree.flush()?;
+//!     }
+//!
+//!     let stats = convert::<u32, u32, u64, u64, BincodeSerDe, BincodeSerDe>(&db, "test_tree")?;
+//!     assert_eq!(stats.converted, 2);
+//!     let tree: Tree<u64, u64, BincodeSerDe> = Tree::open(&db, "test_tree");
+//!     assert_eq!(tree.get(&1)?.unwrap(), 2);
+//!     assert_eq!(tree.get(&3)?.unwrap(), 4);
+//!     Ok(())
+//! }
 //! ```
-use crate::custom_serde::serialize::{Key, Value};
-use crate::custom_serde::Tree;
+use crate::custom_serde::serialize::{Deserializer, Key, SerDe, Serializer, Value};
+use crate::db::{generate_physical_name, Registration, TreeMeta, REGISTRY_TREE};
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+use sled::IVec;
 use std::convert::Into;
+use std::ops::Bound;
+
+/// The number of entries converted per chunk by [convert].
+pub const DEFAULT_CHUNK_SIZE: usize = 1024;
+
+/// Name of the tree storing the checkpoints of unfinished conversions.
+pub const CHECKPOINT_TREE: &str = "__typed_sled_convert";
+
+/// Statistics about a finished conversion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConvertStats {
+    /// The number of converted entries.
+    pub converted: u64,
+    /// The number of entries that couldn't be (de)serialized. Their raw
+    /// keys and values are moved into the tree named by [failed_tree_name].
+    pub failed: u64,
+}
 
-/// Convert `Tree<KOld, VOld, SerDeOld>` to `Tree<KNew, VNew, SerDeNew>`
-pub fn convert<KOld, VOld, KNew, VNew, SerDeOld, SerDeNew>(db: &sled::Db, tree: &str)
+/// The name of the temporary tree used while converting `tree`, if it
+/// wasn't opened through a [Db][crate::Db].
+pub fn temporary_tree_name(tree: &str) -> String {
+    format!("__typed_sled_convert_tmp_{}", tree)
+}
+
+/// The name of the tree that keeps the entries of `tree` which failed to convert.
+pub fn failed_tree_name(tree: &str) -> String {
+    format!("__typed_sled_convert_failed_{}", tree)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Phase {
+    /// Converting the entries of the source tree into the target tree.
+    Converting,
+    /// Copying the target tree back, the source tree has been dropped.
+    CopyingBack,
+    /// The registry points to the target tree, dropping the source tree.
+    DroppingSource,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Checkpoint {
+    phase: Phase,
+    /// The sled tree holding the entries before the conversion.
+    source: String,
+    /// The sled tree the converted entries are written to.
+    target: String,
+    /// Whether the tree is registered in a [Db][crate::Db] and replaced by
+    /// switching its registry entry, instead of copying the entries back.
+    registered: bool,
+    /// The last key of the source tree of the current phase that has been copied.
+    last_key: Option<Vec<u8>>,
+    converted: u64,
+    failed: u64,
+}
+
+/// Convert `Tree<KOld, VOld, SerDeOld>` to `Tree<KNew, VNew, SerDeNew>`.
+///
+/// The tree is only replaced atomically if it was opened through a [Db][crate::Db],
+/// see the [module documentation][self] for details.
+pub fn convert<KOld, VOld, KNew, VNew, SerDeOld, SerDeNew>(
+    db: &sled::Db,
+    tree: &str,
+) -> Result<ConvertStats>
 where
     Key<KOld, VOld, SerDeOld>: Into<KNew>,
     Value<KOld, VOld, SerDeOld>: Into<VNew>,
-    SerDeOld: crate::custom_serde::serialize::SerDe<KOld, VOld>,
-    SerDeNew: crate::custom_serde::serialize::SerDe<KNew, VNew>,
+    SerDeOld: SerDe<KOld, VOld>,
+    SerDeNew: SerDe<KNew, VNew>,
 {
-    let mut kvs = Vec::new();
+    convert_with_chunk_size::<KOld, VOld, KNew, VNew, SerDeOld, SerDeNew>(
+        db,
+        tree,
+        DEFAULT_CHUNK_SIZE,
+    )
+}
 
-    {
-        let tree: Tree<KOld, VOld, SerDeOld> = Tree::open(db, tree);
+/// Like [convert], but with a custom number of entries per chunk.
+pub fn convert_with_chunk_size<KOld, VOld, KNew, VNew, SerDeOld, SerDeNew>(
+    db: &sled::Db,
+    tree: &str,
+    chunk_size: usize,
+) -> Result<ConvertStats>
+where
+    Key<KOld, VOld, SerDeOld>: Into<KNew>,
+    Value<KOld, VOld, SerDeOld>: Into<VNew>,
+    SerDeOld: SerDe<KOld, VOld>,
+    SerDeNew: SerDe<KNew, VNew>,
+{
+    let meta = TreeMeta::new::<KNew, VNew, SerDeNew>();
+    convert_raw(db, tree, chunk_size, meta, |key, value| {
+        let value = SerDeOld::DV::deserialize(value).ok()?.into();
+        let key = SerDeOld::DK::deserialize(key).ok()?.into();
+        Some((
+            SerDeNew::SK::serialize(&key).ok()?.as_ref().to_vec(),
+            SerDeNew::SV::serialize(&value).ok()?.as_ref().to_vec(),
+        ))
+    })
+}
+
+// The conversion engine working on raw bytes. `f` returns `None` for entries that fail to
+// convert, `meta` describes the converted tree and replaces its registry entry, if any.
+pub(crate) fn convert_raw<F>(
+    db: &sled::Db,
+    tree: &str,
+    chunk_size: usize,
+    meta: TreeMeta,
+    mut f: F,
+) -> Result<ConvertStats>
+where
+    F: FnMut(IVec, IVec) -> Option<(Vec<u8>, Vec<u8>)>,
+{
+    let chunk_size = chunk_size.max(1);
+    let checkpoints = db.open_tree(CHECKPOINT_TREE)?;
+    let failed = db.open_tree(failed_tree_name(tree))?;
 
-        for kv_pair in tree.iter() {
-            kvs.push(kv_pair.unwrap());
+    let mut checkpoint = match checkpoints.get(tree)? {
+        Some(bytes) => crate::decode_value(CHECKPOINT_TREE.as_bytes(), tree.as_bytes(), &bytes)?,
+        None => match registration(db, tree)? {
+            Some(registration) => Checkpoint {
+                phase: Phase::Converting,
+                source: registration.physical,
+                target: generate_physical_name(db)?,
+                registered: true,
+                last_key: None,
+                converted: 0,
+                failed: 0,
+            },
+            None => Checkpoint {
+                phase: Phase::Converting,
+                source: tree.to_owned(),
+                target: temporary_tree_name(tree),
+                registered: false,
+                last_key: None,
+                converted: 0,
+                failed: 0,
+            },
+        },
+    };
+    let target = db.open_tree(&checkpoint.target)?;
+
+    if checkpoint.phase == Phase::Converting {
+        let source = db.open_tree(&checkpoint.source)?;
+        loop {
+            let mut converted = sled::Batch::default();
+            let mut failures = sled::Batch::default();
+            let mut len = 0;
+            for res in source
+                .range::<&[u8], _>(start_after(&checkpoint.last_key))
+                .take(chunk_size)
+            {
+                let (key, value) = res?;
+                match f(key.clone(), value.clone()) {
+                    Some((new_key, new_value)) => {
+                        converted.insert(new_key, new_value);
+                        checkpoint.converted += 1;
+                    }
+                    None => {
+                        failures.insert(key.clone(), value);
+                        checkpoint.failed += 1;
+                    }
+                }
+                checkpoint.last_key = Some(key.to_vec());
+                len += 1;
+            }
+            if len == 0 {
+                break;
+            }
+            let checkpoint_bytes = crate::encode_value(&checkpoint)?;
+            (&target, &failed, &checkpoints)
+                .transaction(|(target, failed, checkpoints)| {
+                    target.apply_batch(&converted)?;
+                    failed.apply_batch(&failures)?;
+                    checkpoints.insert(tree, checkpoint_bytes.as_slice())?;
+                    Ok::<_, ConflictableTransactionError<crate::Error>>(())
+                })
+                .map_err(transaction_error)?;
+        }
+        target.flush()?;
+        failed.flush()?;
+
+        checkpoint.last_key = None;
+        if checkpoint.registered {
+            checkpoint.phase = Phase::DroppingSource;
+            switch_registration(db, tree, &checkpoint, meta)?;
+        } else {
+            checkpoint.phase = Phase::CopyingBack;
+            checkpoints.insert(tree, crate::encode_value(&checkpoint)?)?;
+            checkpoints.flush()?;
         }
     }
 
-    db.drop_tree(tree).unwrap();
-    let tree: Tree<KNew, VNew, SerDeNew> = Tree::open(db, tree);
+    if checkpoint.phase == Phase::CopyingBack {
+        // Nothing has been copied back yet, so the tree might still hold the old
+        // entries, also if an earlier run was interrupted right after switching phases.
+        if checkpoint.last_key.is_none() {
+            db.drop_tree(&checkpoint.source)?;
+        }
+        let source = db.open_tree(&checkpoint.source)?;
+        loop {
+            let mut batch = sled::Batch::default();
+            let mut len = 0;
+            for res in target
+                .range::<&[u8], _>(start_after(&checkpoint.last_key))
+                .take(chunk_size)
+            {
+                let (key, value) = res?;
+                checkpoint.last_key = Some(key.to_vec());
+                batch.insert(key, value);
+                len += 1;
+            }
+            if len == 0 {
+                break;
+            }
+            let checkpoint_bytes = crate::encode_value(&checkpoint)?;
+            (&source, &checkpoints)
+                .transaction(|(source, checkpoints)| {
+                    source.apply_batch(&batch)?;
+                    checkpoints.insert(tree, checkpoint_bytes.as_slice())?;
+                    Ok::<_, ConflictableTransactionError<crate::Error>>(())
+                })
+                .map_err(transaction_error)?;
+        }
+        source.flush()?;
+        db.drop_tree(&checkpoint.target)?;
+    } else {
+        // The registry points to the target tree, the source tree is no longer used.
+        db.drop_tree(&checkpoint.source)?;
+    }
 
-    for kv_pair in kvs.drain(..) {
-        tree.insert(&kv_pair.0.into(), &kv_pair.1.into()).unwrap();
+    if failed.is_empty() {
+        db.drop_tree(failed_tree_name(tree))?;
     }
-}
+    checkpoints.remove(tree)?;
+    checkpoints.flush()?;
 
-#[test]
-fn test_convert() {
-    use super::serialize::BincodeSerDe;
-    let config = sled::Config::new().temporary(true);
-    let db = config.open().unwrap();
+    Ok(ConvertStats {
+        converted: checkpoint.converted,
+        failed: checkpoint.failed,
+    })
+}
 
+// Returns the registry entry of `tree` if it was opened through a Db.
+fn registration(db: &sled::Db, tree: &str) -> Result<Option<Registration>> {
+    if !db
+        .tree_names()
+        .iter()
+        .any(|name| name == REGISTRY_TREE.as_bytes())
     {
+        return Ok(None);
+    }
+    db.open_tree(REGISTRY_TREE)?
+        .get(tree)?
+        .map(|bytes| crate::decode_value(REGISTRY_TREE.as_bytes(), tree.as_bytes(), &bytes))
+        .transpose()
+}
+
+// Atomically points the registry entry of `tree` to the target tree and stores the checkpoint.
+fn switch_registration(
+    db: &sled::Db,
+    tree: &str,
+    checkpoint: &Checkpoint,
+    meta: TreeMeta,
+) -> Result<()> {
+    let registry = db.open_tree(REGISTRY_TREE)?;
+    let checkpoints = db.open_tree(CHECKPOINT_TREE)?;
+    let checkpoint_bytes = crate::encode_value(checkpoint)?;
+    (&registry, &checkpoints)
+        .transaction(|(registry, checkpoints)| {
+            let current: Registration = match registry.get(tree)? {
+                Some(bytes) => {
+                    crate::decode_value(REGISTRY_TREE.as_bytes(), tree.as_bytes(), &bytes)
+                        .map_err(ConflictableTransactionError::Abort)?
+                }
+                None => return Err(ConflictableTransactionError::Abort(moved(tree))),
+            };
+            if current.physical != checkpoint.source {
+                return Err(ConflictableTransactionError::Abort(moved(tree)));
+            }
+            // The schema version of the tree is kept, only the types changed.
+            let registration = Registration {
+                meta: meta
+                    .clone()
+                    .with_schema_version(current.meta.schema_version),
+                physical: checkpoint.target.clone(),
+            };
+            let bytes =
+                crate::encode_value(&registration).map_err(ConflictableTransactionError::Abort)?;
+            registry.insert(tree, bytes)?;
+            checkpoints.insert(tree, checkpoint_bytes.as_slice())?;
+            Ok(())
+        })
+        .map_err(transaction_error)?;
+    registry.flush()?;
+    checkpoints.flush()?;
+    Ok(())
+}
+
+fn moved(tree: &str) -> crate::Error {
+    sled::Error::Unsupported(format!(
+        "the tree {:?} was dropped or renamed while it was converted",
+        tree
+    ))
+    .into()
+}
+
+fn start_after(last_key: &Option<Vec<u8>>) -> (Bound<&[u8]>, Bound<&[u8]>) {
+    match last_key {
+        Some(key) => (Bound::Excluded(key.as_slice()), Bound::Unbounded),
+        None => (Bound::Unbounded, Bound::Unbounded),
+    }
+}
+
+fn transaction_error(e: TransactionError<crate::Error>) -> crate::Error {
+    match e {
+        TransactionError::Storage(e) => e.into(),
+        TransactionError::Abort(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_serde::serialize::BincodeSerDe;
+    use crate::custom_serde::Tree;
+
+    #[test]
+    fn test_convert() {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().unwrap();
+
+        {
+            let old_tree: Tree<u32, u32, BincodeSerDe> = Tree::open(&db, "test_tree");
+
+            old_tree.insert(&1, &2).unwrap();
+            old_tree.insert(&3, &4).unwrap();
+            old_tree.flush().unwrap();
+        }
+
+        let stats =
+            convert::<u32, u32, u64, u64, BincodeSerDe, BincodeSerDe>(&db, "test_tree").unwrap();
+        assert_eq!(
+            stats,
+            ConvertStats {
+                converted: 2,
+                failed: 0
+            }
+        );
+        let tree: Tree<u64, u64, BincodeSerDe> = Tree::open(&db, "test_tree");
+        assert_eq!(tree.get(&1).unwrap().unwrap(), 2);
+        assert_eq!(tree.get(&3).unwrap().unwrap(), 4);
+        assert_eq!(db.tree_names().len(), 3);
+    }
+
+    #[test]
+    fn test_convert_resumes() {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().unwrap();
+
+        let old_tree: Tree<u32, u32, BincodeSerDe> = Tree::open(&db, "test_tree");
+        for i in 0..100 {
+            old_tree.insert(&i, &i).unwrap();
+        }
+        // Can't be deserialized as u32.
+        db.open_tree("test_tree")
+            .unwrap()
+            .insert(b"bad", &[1])
+            .unwrap();
+
+        // Interrupt the conversion after 30 entries.
+        let mut calls = 0;
+        let interrupted = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let meta = TreeMeta::new::<u64, u64, BincodeSerDe>();
+            convert_raw(&db, "test_tree", 10, meta, |key, value| {
+                calls += 1;
+                if calls > 30 {
+                    panic!("interrupted");
+                }
+                let key: u32 = crate::deserialize(&key).ok()?;
+                let value: u32 = crate::deserialize(&value).ok()?;
+                Some((
+                    crate::serialize(&u64::from(key)).unwrap(),
+                    crate::serialize(&u64::from(value)).unwrap(),
+                ))
+            })
+        }));
+        assert!(interrupted.is_err());
+        assert_eq!(
+            db.open_tree(temporary_tree_name("test_tree"))
+                .unwrap()
+                .len(),
+            30
+        );
+
+        let stats = convert_with_chunk_size::<u32, u32, u64, u64, BincodeSerDe, BincodeSerDe>(
+            &db,
+            "test_tree",
+            10,
+        )
+        .unwrap();
+        assert_eq!(
+            stats,
+            ConvertStats {
+                converted: 100,
+                failed: 1
+            }
+        );
+        let tree: Tree<u64, u64, BincodeSerDe> = Tree::open(&db, "test_tree");
+        assert_eq!(tree.len(), 100);
+        for i in 0..100 {
+            assert_eq!(tree.get(&i).unwrap(), Some(i));
+        }
+        let failed = db.open_tree(failed_tree_name("test_tree")).unwrap();
+        assert_eq!(failed.get(b"bad").unwrap().unwrap(), [1]);
+    }
+
+    #[test]
+    fn test_convert_resumes_before_drop() {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().unwrap();
+
         let old_tree: Tree<u32, u32, BincodeSerDe> = Tree::open(&db, "test_tree");
+        let temporary: Tree<u64, u64, BincodeSerDe> =
+            Tree::open(&db, temporary_tree_name("test_tree"));
+        for i in 0..10 {
+            old_tree.insert(&i, &i).unwrap();
+            temporary.insert(&u64::from(i), &u64::from(i)).unwrap();
+        }
+        // Interrupted after switching to copying back, but before the tree was dropped.
+        let checkpoint = Checkpoint {
+            phase: Phase::CopyingBack,
+            source: "test_tree".to_owned(),
+            target: temporary_tree_name("test_tree"),
+            registered: false,
+            last_key: None,
+            converted: 10,
+            failed: 0,
+        };
+        db.open_tree(CHECKPOINT_TREE)
+            .unwrap()
+            .insert("test_tree", crate::encode_value(&checkpoint).unwrap())
+            .unwrap();
 
-        old_tree.insert(&1, &2).unwrap();
-        old_tree.insert(&3, &4).unwrap();
-        old_tree.flush().unwrap();
+        let stats = convert_with_chunk_size::<u32, u32, u64, u64, BincodeSerDe, BincodeSerDe>(
+            &db,
+            "test_tree",
+            3,
+        )
+        .unwrap();
+        assert_eq!(stats.converted, 10);
+        let tree: Tree<u64, u64, BincodeSerDe> = Tree::open(&db, "test_tree");
+        assert_eq!(tree.len(), 10);
+        for res in tree.iter() {
+            let (key, value) = res.unwrap();
+            assert_eq!(key, value);
+        }
     }
 
-    convert::<u32, u32, u64, u64, BincodeSerDe, BincodeSerDe>(&db, "test_tree");
-    let tree: Tree<u64, u64, BincodeSerDe> = Tree::open(&db, "test_tree");
-    assert_eq!(tree.get(&1).unwrap().unwrap(), 2);
-    assert_eq!(tree.get(&3).unwrap().unwrap(), 4);
+    #[test]
+    fn test_convert_registered() {
+        let db = crate::Db::new(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+
+        let old_tree = db
+            .open_custom_tree_with_schema::<u32, u32, BincodeSerDe>("test_tree", 2)
+            .unwrap();
+        for i in 0..100 {
+            old_tree.insert(&i, &i).unwrap();
+        }
+
+        // Interrupt the conversion after 30 entries.
+        let mut calls = 0;
+        let interrupted = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let meta = TreeMeta::new::<u64, u64, BincodeSerDe>();
+            convert_raw(db.inner(), "test_tree", 10, meta, |key, value| {
+                calls += 1;
+                if calls > 30 {
+                    panic!("interrupted");
+                }
+                let key: u32 = crate::deserialize(&key).ok()?;
+                let value: u32 = crate::deserialize(&value).ok()?;
+                Some((
+                    crate::serialize(&u64::from(key)).unwrap(),
+                    crate::serialize(&u64::from(value)).unwrap(),
+                ))
+            })
+        }));
+        assert!(interrupted.is_err());
+        // The original tree is still complete.
+        let old_tree = db
+            .open_custom_tree_with_schema::<u32, u32, BincodeSerDe>("test_tree", 2)
+            .unwrap();
+        assert_eq!(old_tree.len(), 100);
+        assert_eq!(old_tree.get(&99).unwrap(), Some(99));
+
+        let stats = convert_with_chunk_size::<u32, u32, u64, u64, BincodeSerDe, BincodeSerDe>(
+            db.inner(),
+            "test_tree",
+            10,
+        )
+        .unwrap();
+        assert_eq!(stats.converted, 100);
+        assert_eq!(
+            db.tree_meta("test_tree").unwrap(),
+            Some(TreeMeta::new::<u64, u64, BincodeSerDe>().with_schema_version(2))
+        );
+        let tree = db
+            .open_custom_tree_with_schema::<u64, u64, BincodeSerDe>("test_tree", 2)
+            .unwrap();
+        assert_eq!(tree.len(), 100);
+        for i in 0..100 {
+            assert_eq!(tree.get(&i).unwrap(), Some(i));
+        }
+        // The original sled tree was dropped.
+        assert!(!db
+            .inner()
+            .tree_names()
+            .iter()
+            .any(|name| name == "test_tree".as_bytes()));
+
+        // Converting again switches to another tree and drops the current one.
+        convert::<u64, u64, u64, u64, BincodeSerDe, BincodeSerDe>(db.inner(), "test_tree").unwrap();
+        let tree = db
+            .open_custom_tree_with_schema::<u64, u64, BincodeSerDe>("test_tree", 2)
+            .unwrap();
+        assert_eq!(tree.len(), 100);
+        // The default tree, the registry, the checkpoints and the converted tree.
+        assert_eq!(db.inner().tree_names().len(), 4);
+    }
 }
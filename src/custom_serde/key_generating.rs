@@ -22,32 +22,58 @@
 //!     Ok(())
 //! }
 //! ```
-use crate::custom_serde::serialize::{self, Value};
-use crate::custom_serde::{Batch, Tree};
-use sled::transaction::{ConflictableTransactionResult, TransactionResult};
-use sled::Result;
+use crate::custom_serde::serialize::{self, Deserializer, Key, Lazy, Value};
+use crate::custom_serde::{Batch, TransactionalTree, Tree};
+use crate::key_generating::key_after;
+pub use crate::key_generating::{PersistentCounter, DEFAULT_BLOCK_SIZE};
+use crate::transaction::{ConflictableTransactionResult, TransactionResult, TransactionTarget};
+use crate::{Result, SerdeError};
 use std::ops::Deref;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 /// Wraps a type that implements KeyGenerating and uses it to
 /// generate the keys for a [Tree][crate::custom_serde::Tree].
 ///
 /// See CounterTree for a specific example of how to use this type.
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct KeyGeneratingTree<KG: KeyGenerating<V, SerDe>, V, SerDe> {
     key_generator: KG,
     inner: Tree<KG::Key, V, SerDe>,
 }
 
+// With derive(Clone) the tree is only Clone if V and SerDe are, which
+// the lazy SerDes aren't.
+impl<KG: KeyGenerating<V, SerDe> + Clone, V, SerDe> Clone for KeyGeneratingTree<KG, V, SerDe> {
+    fn clone(&self) -> Self {
+        Self {
+            key_generator: self.key_generator.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
 impl<KG: KeyGenerating<V, SerDe>, V, SerDe> KeyGeneratingTree<KG, V, SerDe> {
+    /// Open a tree and initialize its key generator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if sled fails to open the tree or the key generator fails to
+    /// initialize. Use [try_open][Self::try_open] to handle the error instead.
     pub fn open<T: AsRef<str>>(db: &sled::Db, id: T) -> Self {
-        let tree = Tree::open(db, id);
-        let key_generator = KG::initialize(&tree);
+        Self::try_open(db, id).unwrap()
+    }
 
-        Self {
+    /// Open a tree and initialize its key generator like [open][Self::open],
+    /// but return an error instead of panicking.
+    pub fn try_open<T: AsRef<str>>(db: &sled::Db, id: T) -> Result<Self> {
+        let tree = Tree::try_open(db, id)?;
+        let key_generator = KG::initialize(db, &tree)?;
+
+        Ok(Self {
             key_generator,
             inner: tree,
-        }
+        })
     }
 
     /// Insert a generated key to a new value, returning the key and the last value if it was set.
@@ -55,7 +81,7 @@ impl<KG: KeyGenerating<V, SerDe>, V, SerDe> KeyGeneratingTree<KG, V, SerDe> {
     where
         SerDe: serialize::SerDe<KG::Key, V>,
     {
-        let key = self.key_generator.next_key();
+        let key = self.key_generator.next_key()?;
         let res = self.inner.insert(&key, value);
         res.map(|opt_v| (key, opt_v))
     }
@@ -76,7 +102,8 @@ impl<KG: KeyGenerating<V, SerDe>, V, SerDe> KeyGeneratingTree<KG, V, SerDe> {
         self.inner.insert(key, value)
     }
 
-    pub fn next_key(&self) -> KG::Key {
+    /// Generate a key without inserting a value, see [insert_with_key][Self::insert_with_key].
+    pub fn next_key(&self) -> Result<KG::Key> {
         self.key_generator.next_key()
     }
 
@@ -90,20 +117,20 @@ impl<KG: KeyGenerating<V, SerDe>, V, SerDe> KeyGeneratingTree<KG, V, SerDe> {
     where
         SerDe: serialize::SerDe<KG::Key, V>,
     {
-        let key = self.key_generator.next_key();
+        let key = self.key_generator.next_key()?;
         let value = f(&key);
         let res = self.insert_with_key(&key, &value);
         res.map(|opt_v| (key, opt_v))
     }
 
-    pub fn transaction<F, A, E>(&self, f: F) -> TransactionResult<A, E>
+    pub fn transaction<F, A>(&self, f: F) -> TransactionResult<A>
     where
-        F: Fn(&KeyGeneratingTransactionalTree<KG, V, SerDe>) -> ConflictableTransactionResult<A, E>,
+        F: Fn(&KeyGeneratingTransactionalTree<KG, V, SerDe>) -> ConflictableTransactionResult<A>,
     {
         self.inner.transaction(|transactional_tree| {
             f(&KeyGeneratingTransactionalTree {
                 key_generator: self.key_generator(),
-                inner: transactional_tree,
+                inner: TransactionalTree::new(transactional_tree.inner, transactional_tree.tree),
             })
         })
     }
@@ -112,7 +139,7 @@ impl<KG: KeyGenerating<V, SerDe>, V, SerDe> KeyGeneratingTree<KG, V, SerDe> {
         &self.key_generator
     }
 
-    pub fn new_batch(&self) -> KeyGeneratingBatch<KG, V, SerDe> {
+    pub fn new_batch(&self) -> KeyGeneratingBatch<'_, KG, V, SerDe> {
         KeyGeneratingBatch {
             key_generator: self.key_generator(),
             inner: Batch::default(),
@@ -136,12 +163,14 @@ impl<KG: KeyGenerating<V, SerDe>, V, SerDe> Deref for KeyGeneratingTree<KG, V, S
 /// for a [Tree][crate::custom_serde::Tree].
 ///
 /// See CounterTree for a specific example of how to use this trait.
-pub trait KeyGenerating<V, SerDe> {
+pub trait KeyGenerating<V, SerDe>: Sized {
     type Key;
 
-    fn initialize(tree: &Tree<Self::Key, V, SerDe>) -> Self;
+    /// Initialize the key generator of `tree`, which was opened in `db`.
+    fn initialize(db: &sled::Db, tree: &Tree<Self::Key, V, SerDe>) -> Result<Self>;
 
-    fn next_key(&self) -> Self::Key;
+    /// Generate the next key. Keys which were generated once must never be generated again.
+    fn next_key(&self) -> Result<Self::Key>;
 }
 
 #[derive(Clone, Debug)]
@@ -151,14 +180,14 @@ pub struct KeyGeneratingBatch<'a, KG: KeyGenerating<V, SerDe>, V, SerDe> {
 }
 
 impl<'a, KG: KeyGenerating<V, SerDe, Key = K>, K, V, SerDe> KeyGeneratingBatch<'a, KG, V, SerDe> {
-    pub fn insert(&mut self, value: &V)
+    pub fn insert(&mut self, value: &V) -> Result<()>
     where
         SerDe: serialize::SerDe<KG::Key, V>,
     {
-        self.inner.insert(&self.key_generator.next_key(), value);
+        self.inner.insert(&self.key_generator.next_key()?, value)
     }
 
-    pub fn remove(&mut self, key: &K)
+    pub fn remove(&mut self, key: &K) -> Result<()>
     where
         SerDe: serialize::SerDe<KG::Key, V>,
     {
@@ -167,66 +196,270 @@ impl<'a, KG: KeyGenerating<V, SerDe, Key = K>, K, V, SerDe> KeyGeneratingBatch<'
 }
 
 /// A [Tree][crate::custom_serde::Tree] with automatically generated and continuously increasing u64 keys.
-pub type CounterTree<V> = KeyGeneratingTree<Counter, V, serialize::BincodeSerDe>;
+pub type CounterTree<V, SerDe = serialize::BincodeSerDe> = KeyGeneratingTree<Counter, V, SerDe>;
+/// A [CounterTree] with lazily deserialized keys and values.
+pub type CounterTreeLazy<V> = CounterTree<V, serialize::BincodeSerDeLazy>;
+/// A [CounterTree] with lazily deserialized keys.
+pub type CounterTreeLazyK<V> = CounterTree<V, serialize::BincodeSerDeLazyK>;
+/// A [CounterTree] with lazily deserialized values.
+pub type CounterTreeLazyV<V> = CounterTree<V, serialize::BincodeSerDeLazyV>;
 
-#[derive(Debug)]
-pub struct Counter(AtomicU64);
+/// A deserialized key which can be converted back to the `u64` of a [Counter] or [PersistentCounter].
+pub trait CounterKey {
+    fn to_u64(&self) -> std::result::Result<u64, SerdeError>;
+}
+
+impl CounterKey for u64 {
+    fn to_u64(&self) -> std::result::Result<u64, SerdeError> {
+        Ok(*self)
+    }
+}
+
+impl CounterKey for Lazy<u64> {
+    fn to_u64(&self) -> std::result::Result<u64, SerdeError> {
+        Ok(self.try_deserialize()?)
+    }
+}
 
-impl<V> KeyGenerating<V, serialize::BincodeSerDe> for Counter
+/// Generates the keys of a [CounterTree], continuing after the largest key of the tree.
+///
+/// The keys of a [SerDe][serialize::SerDe] aren't necessarily ordered, so unless
+/// [ORDERED_KEYS][serialize::SerDe::ORDERED_KEYS] is set, e.g. for
+/// [OrderedSerDe][serialize::OrderedSerDe], initializing the counter iterates over
+/// all keys of the tree. The counter only lives in memory and
+/// is shared by the clones of the tree, so the keys after the largest key are generated
+/// again once the tree is reopened, even if they were removed before. Use
+/// [PersistentCounter] if keys must never be reused.
+#[derive(Debug, Clone)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl<V, SerDe> KeyGenerating<V, SerDe> for Counter
 where
-    V: serde::Serialize + serde::de::DeserializeOwned,
+    SerDe: serialize::SerDe<u64, V>,
+    Key<u64, V, SerDe>: CounterKey,
 {
     type Key = u64;
 
-    fn initialize(tree: &Tree<Self::Key, V, serialize::BincodeSerDe>) -> Self {
-        if let Some((key, _)) = tree
-            .last()
-            .expect("KeyGenerating Counter failed to access sled Tree.")
-        {
-            Counter(AtomicU64::new(key + 1))
-        } else {
-            Counter(AtomicU64::new(0))
-        }
+    fn initialize(_db: &sled::Db, tree: &Tree<Self::Key, V, SerDe>) -> Result<Self> {
+        Ok(Counter(Arc::new(AtomicU64::new(next_key_after_largest(
+            tree,
+        )?))))
     }
 
-    fn next_key(&self) -> Self::Key {
-        self.0.fetch_add(1, Ordering::Relaxed)
+    fn next_key(&self) -> Result<Self::Key> {
+        Ok(self.0.fetch_add(1, Ordering::Relaxed))
     }
 }
 
-pub struct KeyGeneratingTransactionalTree<'a, KG: KeyGenerating<V, SerDe>, V, SerDe> {
-    key_generator: &'a KG,
-    inner: &'a crate::custom_serde::TransactionalTree<'a, KG::Key, V, SerDe>,
+/// A [Tree][crate::custom_serde::Tree] with automatically generated u64 keys which are never reused,
+/// see [PersistentCounter].
+pub type PersistentCounterTree<
+    V,
+    SerDe = serialize::BincodeSerDe,
+    const BLOCK_SIZE: u64 = DEFAULT_BLOCK_SIZE,
+> = KeyGeneratingTree<PersistentCounter<BLOCK_SIZE>, V, SerDe>;
+
+impl<V, SerDe, const BLOCK_SIZE: u64> KeyGenerating<V, SerDe> for PersistentCounter<BLOCK_SIZE>
+where
+    SerDe: serialize::SerDe<u64, V>,
+    Key<u64, V, SerDe>: CounterKey,
+{
+    type Key = u64;
+
+    fn initialize(db: &sled::Db, tree: &Tree<Self::Key, V, SerDe>) -> Result<Self> {
+        Self::open(db, tree.name(), || next_key_after_largest(tree))
+    }
+
+    fn next_key(&self) -> Result<Self::Key> {
+        self.next()
+    }
+}
+
+fn next_key_after_largest<V, SerDe>(tree: &Tree<u64, V, SerDe>) -> Result<u64>
+where
+    SerDe: serialize::SerDe<u64, V>,
+    Key<u64, V, SerDe>: CounterKey,
+{
+    let name = tree.name();
+    let decode = |key_bytes: sled::IVec| {
+        <SerDe as serialize::SerDe<u64, V>>::DK::deserialize(key_bytes.clone())
+            .and_then(|key| key.to_u64())
+            .map_err(|e| crate::Error::deserialize(&name, &key_bytes, e))
+    };
+    let largest = if <SerDe as serialize::SerDe<u64, V>>::ORDERED_KEYS {
+        tree.inner
+            .last()?
+            .map(|(key_bytes, _)| decode(key_bytes))
+            .transpose()?
+    } else {
+        let mut largest = None;
+        for key_bytes in tree.inner.iter().keys() {
+            largest = largest.max(Some(decode(key_bytes?)?));
+        }
+        largest
+    };
+    largest.map_or(Ok(0), |key| key_after(&name, key))
+}
+
+/// The view of a [KeyGeneratingTree] inside of a transaction.
+///
+/// `'a` is the lifetime of the transaction and `'g` the lifetime of the key generator.
+pub struct KeyGeneratingTransactionalTree<'a, 'g, KG: KeyGenerating<V, SerDe>, V, SerDe> {
+    key_generator: &'g KG,
+    inner: TransactionalTree<'a, KG::Key, V, SerDe>,
 }
 
-impl<'a, KG: KeyGenerating<V, SerDe>, V, SerDe> KeyGeneratingTransactionalTree<'a, KG, V, SerDe> {
+impl<'a, 'g, KG: KeyGenerating<V, SerDe>, V, SerDe>
+    KeyGeneratingTransactionalTree<'a, 'g, KG, V, SerDe>
+{
+    /// Generate a key, e.g. to insert a value which refers to it with [insert_with_key][Self::insert_with_key].
+    /// Keys generated by a transaction which is retried or aborted aren't reused.
+    pub fn next_key(
+        &self,
+    ) -> std::result::Result<KG::Key, crate::transaction::UnabortableTransactionError> {
+        Ok(self.key_generator.next_key()?)
+    }
+
     pub fn insert(
         &self,
         value: &V,
     ) -> std::result::Result<
         Option<Value<KG::Key, V, SerDe>>,
-        sled::transaction::UnabortableTransactionError,
+        crate::transaction::UnabortableTransactionError,
+    >
+    where
+        SerDe: serialize::SerDe<KG::Key, V>,
+    {
+        self.inner.insert(&self.key_generator.next_key()?, value)
+    }
+
+    /// Insert a value at a key returned by [next_key][Self::next_key].
+    pub fn insert_with_key(
+        &self,
+        key: &KG::Key,
+        value: &V,
+    ) -> std::result::Result<
+        Option<Value<KG::Key, V, SerDe>>,
+        crate::transaction::UnabortableTransactionError,
     >
     where
         SerDe: serialize::SerDe<KG::Key, V>,
     {
-        self.inner.insert(&self.key_generator.next_key(), value)
+        self.inner.insert(key, value)
     }
 
     pub fn apply_batch(
         &self,
         batch: &KeyGeneratingBatch<KG, V, SerDe>,
-    ) -> std::result::Result<(), sled::transaction::UnabortableTransactionError> {
+    ) -> std::result::Result<(), crate::transaction::UnabortableTransactionError> {
         self.inner.apply_batch(&batch.inner)
     }
 }
 
-impl<'a, KG: KeyGenerating<V, SerDe>, V, SerDe> Deref
-    for KeyGeneratingTransactionalTree<'a, KG, V, SerDe>
+impl<'a, 'g, KG: KeyGenerating<V, SerDe>, V, SerDe> Deref
+    for KeyGeneratingTransactionalTree<'a, 'g, KG, V, SerDe>
 {
-    type Target = crate::custom_serde::TransactionalTree<'a, KG::Key, V, SerDe>;
+    type Target = TransactionalTree<'a, KG::Key, V, SerDe>;
 
     fn deref(&self) -> &Self::Target {
-        self.inner
+        &self.inner
+    }
+}
+
+impl<'g, KG: KeyGenerating<V, SerDe>, V, SerDe> TransactionTarget
+    for &'g KeyGeneratingTree<KG, V, SerDe>
+{
+    type View<'a> = KeyGeneratingTransactionalTree<'a, 'g, KG, V, SerDe>;
+
+    fn sled_tree(&self) -> &sled::Tree {
+        &self.inner.inner
+    }
+
+    fn view<'a>(
+        &self,
+        tree: &'a sled::transaction::TransactionalTree,
+        name: &'a [u8],
+    ) -> Self::View<'a> {
+        KeyGeneratingTransactionalTree {
+            key_generator: &self.key_generator,
+            inner: TransactionalTree::new(tree, name),
+        }
+    }
+}
+
+#[test]
+fn test_counters_continue_after_largest_key() {
+    let config = sled::Config::new().temporary(true);
+    let db = config.open().unwrap();
+
+    // bincode encodes 256 as [0, 1, 0, ..], so it isn't the last key of the tree.
+    let tree = CounterTree::<u32>::open(&db, "test_tree");
+    for i in 0..=256 {
+        tree.insert(&i).unwrap();
     }
+    assert_eq!(
+        CounterTree::<u32>::open(&db, "test_tree")
+            .next_key()
+            .unwrap(),
+        257
+    );
+
+    let tree = PersistentCounterTree::<u32, serialize::BincodeSerDe, 2>::open(&db, "test_tree");
+    assert_eq!(tree.next_key().unwrap(), 257);
+    let tree = PersistentCounterTree::<u32, serialize::BincodeSerDe, 2>::open(&db, "test_tree");
+    assert_eq!(tree.next_key().unwrap(), 259);
+
+    // Ordered keys are continued after the last key of the tree.
+    let tree = CounterTree::<u32, serialize::OrderedSerDe>::open(&db, "ordered");
+    for i in 0..=256 {
+        tree.insert(&i).unwrap();
+    }
+    let tree = CounterTree::<u32, serialize::OrderedSerDe>::open(&db, "ordered");
+    assert_eq!(tree.next_key().unwrap(), 257);
+
+    Tree::<u64, u32, serialize::OrderedSerDe>::open(&db, "full")
+        .insert(&u64::MAX, &0)
+        .unwrap();
+    assert!(matches!(
+        CounterTree::<u32, serialize::OrderedSerDe>::try_open(&db, "full"),
+        Err(crate::Error::KeysExhausted { .. })
+    ));
+}
+
+#[test]
+fn test_counters_with_lazy_serde() {
+    let config = sled::Config::new().temporary(true);
+    let db = config.open().unwrap();
+
+    let tree = CounterTreeLazy::<u32>::open(&db, "lazy");
+    let clone = tree.clone();
+    assert_eq!(tree.insert(&1).unwrap().0, 0);
+    assert_eq!(clone.insert(&2).unwrap().0, 1);
+    assert_eq!(tree.get(&1).unwrap().unwrap().deserialize(), 2);
+    assert_eq!(
+        CounterTreeLazy::<u32>::open(&db, "lazy")
+            .next_key()
+            .unwrap(),
+        2
+    );
+
+    let tree = CounterTreeLazyK::<u32>::open(&db, "lazy_k");
+    tree.insert(&1).unwrap();
+    assert_eq!(
+        CounterTreeLazyK::<u32>::open(&db, "lazy_k")
+            .next_key()
+            .unwrap(),
+        1
+    );
+    let tree = CounterTreeLazyV::<u32>::open(&db, "lazy_v");
+    tree.insert(&1).unwrap();
+    assert_eq!(
+        CounterTreeLazyV::<u32>::open(&db, "lazy_v")
+            .next_key()
+            .unwrap(),
+        1
+    );
+
+    let tree = PersistentCounterTree::<u32, serialize::BincodeSerDeLazy>::open(&db, "lazy");
+    assert_eq!(tree.next_key().unwrap(), 2);
 }
@@ -6,7 +6,9 @@
 //! must implement the trait [SerDe][crate::custom_serde::serialize::SerDe] which defines how (de)serialization takes place.
 //!
 //! The [Tree<K, V>][crate::Tree] is equivalent to
-//! [Tree<K, V, BincodeSerDe>][crate::custom_serde::Tree] from this module.
+//! [Tree<K, V, OrderedSerDe>][crate::custom_serde::Tree] from this module.
+//! Note that the bincode based `SerDe`s encode keys in little-endian, so iteration
+//! and range queries over those trees do not follow the `Ord` implementation of the key.
 //!
 //! The following features are supported for the custom (de)serialization Tree:
 //! * [key_generating][self::key_generating]: Create `Tree`s with automatically generated keys.
@@ -44,13 +46,15 @@
 //!
 //! [sled]: https://docs.rs/sled/latest/sled/
 use crate::custom_serde::serialize::{Deserializer, Key, Serializer, Value};
+use crate::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+    TransactionResult, UnabortableTransactionError,
+};
+use crate::{Error, Result};
 use core::fmt;
 use core::iter::{DoubleEndedIterator, Iterator};
 use core::ops::{Bound, RangeBounds};
-use sled::{
-    transaction::{ConflictableTransactionResult, TransactionResult},
-    IVec, Result,
-};
+use sled::IVec;
 use std::marker::PhantomData;
 
 pub mod serialize;
@@ -61,8 +65,8 @@ pub mod convert;
 #[cfg(feature = "key-generating")]
 pub mod key_generating;
 
-// #[cfg(feature = "search")]
-// pub mod search;
+#[cfg(feature = "search")]
+pub mod search;
 
 // pub trait Bin = DeserializeOwned + Serialize + Clone + Send + Sync;
 
@@ -155,10 +159,34 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     ///     );
     ///     Ok(())
     /// }
-    //     /// ```
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if sled fails to open the tree. Use [try_open][Tree::try_open]
+    /// to handle the error instead.
     pub fn open<T: AsRef<str>>(db: &sled::Db, id: T) -> Self {
+        Self::try_open(db, id).unwrap()
+    }
+
+    /// Initialize a typed tree like [open][Tree::open], but return an
+    /// error instead of panicking if sled fails to open the tree.
+    pub fn try_open<T: AsRef<str>>(db: &sled::Db, id: T) -> Result<Self> {
+        Ok(Self {
+            inner: db.open_tree(id.as_ref())?,
+            _key: PhantomData,
+            _value: PhantomData,
+            _serde: PhantomData,
+        })
+    }
+
+    /// Wrap a `sled::Tree` whose entries are encoded with `SerDe`.
+    ///
+    /// Unlike [Db::open_custom_tree][crate::Db::open_custom_tree] this doesn't
+    /// check whether the tree was written with different types.
+    pub fn from_sled(inner: sled::Tree) -> Self {
         Self {
-            inner: db.open_tree(id.as_ref()).unwrap(),
+            inner,
             _key: PhantomData,
             _value: PhantomData,
             _serde: PhantomData,
@@ -170,34 +198,33 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .insert(
-                SerDe::SK::serialize(key).as_ref(),
-                SerDe::SV::serialize(value).as_ref(),
-            )
-            .map(|opt| opt.map(|old_value| SerDe::DV::deserialize(old_value)))
+        let key_bytes = encode_key::<K, V, SerDe>(key)?;
+        let old_value = self.inner.insert(
+            key_bytes.as_ref(),
+            encode_value::<K, V, SerDe>(value)?.as_ref(),
+        )?;
+        self.decode_opt_value(key_bytes.as_ref(), old_value)
     }
 
     /// Perform a multi-key serializable transaction.
-    pub fn transaction<F, A, E>(&self, f: F) -> TransactionResult<A, E>
+    pub fn transaction<F, A>(&self, f: F) -> TransactionResult<A>
     where
-        F: Fn(&TransactionalTree<K, V, SerDe>) -> ConflictableTransactionResult<A, E>,
+        F: Fn(&TransactionalTree<K, V, SerDe>) -> ConflictableTransactionResult<A>,
     {
-        self.inner.transaction(|sled_transactional_tree| {
-            f(&TransactionalTree {
-                inner: sled_transactional_tree,
-                _key: PhantomData,
-                _value: PhantomData,
-                _serde: PhantomData,
+        let name = self.inner.name();
+        self.inner
+            .transaction(|sled_transactional_tree| {
+                f(&TransactionalTree::new(sled_transactional_tree, &name))
+                    .map_err(ConflictableTransactionError::into_sled)
             })
-        })
+            .map_err(TransactionError::from_sled)
     }
 
     /// Create a new batched update that can be atomically applied.
     ///
     /// It is possible to apply a Batch in a transaction as well, which is the way you can apply a Batch to multiple Trees atomically.
     pub fn apply_batch(&self, batch: Batch<K, V, SerDe>) -> Result<()> {
-        self.inner.apply_batch(batch.inner)
+        Ok(self.inner.apply_batch(batch.inner)?)
     }
 
     /// Retrieve a value from the Tree if it exists.
@@ -205,9 +232,7 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .get(SerDe::SK::serialize(key))
-            .map(|opt| opt.map(|v| SerDe::DV::deserialize(v)))
+        self.get_from_raw(encode_key::<K, V, SerDe>(key)?)
     }
 
     /// Retrieve a value from the Tree if it exists. The key must be in serialized form.
@@ -215,9 +240,8 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .get(key_bytes.as_ref())
-            .map(|opt| opt.map(|v| SerDe::DV::deserialize(v)))
+        let value = self.inner.get(key_bytes.as_ref())?;
+        self.decode_opt_value(key_bytes.as_ref(), value)
     }
 
     /// Deserialize a key and retrieve it's value from the Tree if it exists.
@@ -229,14 +253,8 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner.get(key_bytes.as_ref()).map(|opt| {
-            opt.map(|v| {
-                (
-                    SerDe::DK::deserialize(sled::IVec::from(key_bytes.as_ref())),
-                    SerDe::DV::deserialize(v),
-                )
-            })
-        })
+        let value = self.inner.get(key_bytes.as_ref())?;
+        self.decode_opt_kv(value.map(|v| (sled::IVec::from(key_bytes.as_ref()), v)))
     }
 
     /// Delete a value, returning the old value if it existed.
@@ -244,9 +262,9 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .remove(SerDe::SK::serialize(key).as_ref())
-            .map(|opt| opt.map(|v| SerDe::DV::deserialize(v)))
+        let key_bytes = encode_key::<K, V, SerDe>(key)?;
+        let old_value = self.inner.remove(key_bytes.as_ref())?;
+        self.decode_opt_value(key_bytes.as_ref(), old_value)
     }
 
     /// Compare and swap. Capable of unique creation, conditional modification, or deletion. If old is None, this will only set the value if it doesn't exist yet. If new is None, will delete the value if old is correct. If both old and new are Some, will modify the value if old is correct.
@@ -263,61 +281,68 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .compare_and_swap(
-                SerDe::SK::serialize(key),
-                old.map(|old| SerDe::SV::serialize(old))
-                    .as_ref()
-                    .map(|old| old.as_ref()),
-                new.map(|new| SerDe::SV::serialize(new))
-                    .as_ref()
-                    .map(|new| new.as_ref()),
-            )
-            .map(|cas_res| {
-                cas_res.map_err(|cas_err| CompareAndSwapError {
-                    current: cas_err.current.map(|b| SerDe::DV::deserialize(b)),
-                    proposed: cas_err.proposed.map(|b| SerDe::DV::deserialize(b)),
-                })
-            })
+        let key_bytes = encode_key::<K, V, SerDe>(key)?;
+        let old = old.map(encode_value::<K, V, SerDe>).transpose()?;
+        let new = new.map(encode_value::<K, V, SerDe>).transpose()?;
+        let cas_res = self.inner.compare_and_swap(
+            key_bytes.as_ref(),
+            old.as_ref().map(|old| old.as_ref()),
+            new.as_ref().map(|new| new.as_ref()),
+        )?;
+        match cas_res {
+            Ok(()) => Ok(Ok(())),
+            Err(cas_err) => Ok(Err(CompareAndSwapError {
+                current: self.decode_opt_value(key_bytes.as_ref(), cas_err.current)?,
+                proposed: self.decode_opt_value(key_bytes.as_ref(), cas_err.proposed)?,
+            })),
+        }
     }
 
     /// Fetch the value, apply a function to it and return the result.
+    ///
+    /// If the stored value can't be deserialized or the new value can't be serialized,
+    /// the stored value is left untouched and the error is returned.
     pub fn update_and_fetch<F>(&self, key: &K, mut f: F) -> Result<Option<Value<K, V, SerDe>>>
     where
         SerDe: serialize::SerDe<K, V>,
         F: FnMut(Option<Value<K, V, SerDe>>) -> Option<V>,
     {
-        self.inner
-            .update_and_fetch(SerDe::SK::serialize(key), |opt_value| {
-                f(opt_value.map(|v| SerDe::DV::deserialize(sled::IVec::from(v)))).map(|value| {
-                    // TODO: Maybe add Into<IVec> to SerDe::SV::Bytes
-                    let bytes = SerDe::SV::serialize(&value);
-                    let mut v = Vec::with_capacity(bytes.as_ref().len());
-                    v.extend(bytes.as_ref());
-                    v
-                })
-            })
-            .map(|res| res.map(|v| SerDe::DV::deserialize(v)))
+        let key_bytes = encode_key::<K, V, SerDe>(key)?;
+        let name = self.inner.name();
+        let mut error = None;
+        let res = self
+            .inner
+            .update_and_fetch(key_bytes.as_ref(), |opt_value| {
+                update::<K, V, SerDe, F>(&name, key_bytes.as_ref(), opt_value, &mut f, &mut error)
+            })?;
+        match error {
+            Some(e) => Err(e),
+            None => self.decode_opt_value(key_bytes.as_ref(), res),
+        }
     }
 
     /// Fetch the value, apply a function to it and return the previous value.
+    ///
+    /// If the stored value can't be deserialized or the new value can't be serialized,
+    /// the stored value is left untouched and the error is returned.
     // not sure if implemented correctly (different trait bound for F)
     pub fn fetch_and_update<F>(&self, key: &K, mut f: F) -> Result<Option<Value<K, V, SerDe>>>
     where
         SerDe: serialize::SerDe<K, V>,
         F: FnMut(Option<Value<K, V, SerDe>>) -> Option<V>,
     {
-        self.inner
-            .fetch_and_update(SerDe::SK::serialize(key), |opt_value| {
-                f(opt_value.map(|v| SerDe::DV::deserialize(sled::IVec::from(v)))).map(|value| {
-                    // TODO: Maybe add Into<IVec> to SerDe::SV::Bytes
-                    let bytes = SerDe::SV::serialize(&value);
-                    let mut v = Vec::with_capacity(bytes.as_ref().len());
-                    v.extend(bytes.as_ref());
-                    v
-                })
-            })
-            .map(|res| res.map(|v| SerDe::DV::deserialize(v)))
+        let key_bytes = encode_key::<K, V, SerDe>(key)?;
+        let name = self.inner.name();
+        let mut error = None;
+        let res = self
+            .inner
+            .fetch_and_update(key_bytes.as_ref(), |opt_value| {
+                update::<K, V, SerDe, F>(&name, key_bytes.as_ref(), opt_value, &mut f, &mut error)
+            })?;
+        match error {
+            Some(e) => Err(e),
+            None => self.decode_opt_value(key_bytes.as_ref(), res),
+        }
     }
 
     /// Subscribe to `Event`s that happen to keys that have
@@ -329,11 +354,15 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     /// to block. There is a buffer of 1024 items per
     ///  `Subscriber`. This can be used to build reactive
     /// and replicated systems.
-    pub fn watch_prefix(&self, prefix: &K) -> Subscriber<K, V, SerDe>
+    pub fn watch_prefix(&self, prefix: &K) -> Result<Subscriber<K, V, SerDe>>
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        Subscriber::from_sled(self.inner.watch_prefix(SerDe::SK::serialize(prefix)))
+        Ok(Subscriber::from_sled(
+            self.inner
+                .watch_prefix(encode_key::<K, V, SerDe>(prefix)?.as_ref()),
+            self.inner.name(),
+        ))
     }
 
     /// Subscribe to  all`Event`s. Events for particular keys are
@@ -348,7 +377,7 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        Subscriber::from_sled(self.inner.watch_prefix(vec![]))
+        Subscriber::from_sled(self.inner.watch_prefix(vec![]), self.inner.name())
     }
 
     /// Synchronously flushes all dirty IO buffers and calls
@@ -362,7 +391,7 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     /// realistic sustained workloads running on realistic
     /// hardware.
     pub fn flush(&self) -> Result<usize> {
-        self.inner.flush()
+        Ok(self.inner.flush()?)
     }
 
     /// Asynchronously flushes all dirty IO buffers
@@ -376,7 +405,7 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     /// using it on realistic sustained workloads
     /// running on realistic hardware.
     pub async fn flush_async(&self) -> Result<usize> {
-        self.inner.flush_async().await
+        Ok(self.inner.flush_async().await?)
     }
 
     /// Returns `true` if the `Tree` contains a value for
@@ -385,7 +414,7 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner.contains_key(SerDe::SK::serialize(key))
+        Ok(self.inner.contains_key(encode_key::<K, V, SerDe>(key)?)?)
     }
 
     /// Retrieve the key and value before the provided key,
@@ -394,9 +423,8 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .get_lt(SerDe::SK::serialize(key))
-            .map(|res| res.map(|(k, v)| (SerDe::DK::deserialize(k), SerDe::DV::deserialize(v))))
+        let kv = self.inner.get_lt(encode_key::<K, V, SerDe>(key)?)?;
+        self.decode_opt_kv(kv)
     }
 
     /// Retrieve the next key and value from the `Tree` after the
@@ -405,9 +433,8 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .get_gt(SerDe::SK::serialize(key))
-            .map(|res| res.map(|(k, v)| (SerDe::DK::deserialize(k), SerDe::DV::deserialize(v))))
+        let kv = self.inner.get_gt(encode_key::<K, V, SerDe>(key)?)?;
+        self.decode_opt_kv(kv)
     }
 
     /// Merge state directly into a given key's value using the
@@ -422,17 +449,99 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     /// Merge operators are shared by all instances of a particular
     /// `Tree`. Different merge operators may be set on different
     /// `Tree`s.
+    ///
+    /// Returns [Error::Deserialize] without merging if the stored value
+    /// can't be deserialized, as a typed merge operator would drop the value.
     pub fn merge(&self, key: &K, value: &V) -> Result<Option<Value<K, V, SerDe>>>
     where
         SerDe: serialize::SerDe<K, V>,
+    {
+        self.merge_operand(key, value)
+    }
+
+    /// Merge an operand of a different type than the values into a given key's value,
+    /// see [merge][Tree::merge] and
+    /// [set_typed_merge_operator_with_operand][Tree::set_typed_merge_operator_with_operand].
+    /// The operand is serialized with the `SerDe<K, O>` implementation of `SerDe`.
+    pub fn merge_operand<O>(&self, key: &K, operand: &O) -> Result<Option<Value<K, V, SerDe>>>
+    where
+        SerDe: serialize::SerDe<K, V> + serialize::SerDe<K, O>,
+    {
+        let key_bytes = encode_key::<K, V, SerDe>(key)?;
+        let operand_bytes = encode_value::<K, O, SerDe>(operand)?;
+        self.decode_opt_value(key_bytes.as_ref(), self.inner.get(key_bytes.as_ref())?)?;
+        let old_value = self
+            .inner
+            .merge(key_bytes.as_ref(), operand_bytes.as_ref())?;
+        self.decode_opt_value(key_bytes.as_ref(), old_value)
+    }
+
+    /// Sets a merge operator for use with the `merge` function.
+    ///
+    /// Merge state directly into a given key's value using the
+    /// configured merge operator. This allows state to be written
+    /// into a value directly, without any read-modify-write steps.
+    /// Merge operators can be used to implement arbitrary data
+    /// structures.
+    ///
+    /// The key and the values are deserialized with `SerDe::DK` and `SerDe::DV`
+    /// and the merged value is serialized with `SerDe::SV`. sled doesn't allow
+    /// merge operators to fail, so if any of them can't be (de)serialized, the
+    /// stored value is left untouched. [merge][Tree::merge] checks the stored
+    /// value beforehand, so this only happens if the value is replaced
+    /// concurrently or the operand type doesn't match the merge operator.
+    ///
+    /// # Panics
+    ///
+    /// Calling `merge` will panic if no merge operator has been
+    /// configured.
+    pub fn set_typed_merge_operator(
+        &self,
+        merge_operator: impl MergeOperator<K, V, V, SerDe> + 'static,
+    ) where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        self.set_typed_merge_operator_with_operand(merge_operator)
+    }
+
+    /// Sets a merge operator like [set_typed_merge_operator][Tree::set_typed_merge_operator],
+    /// which merges operands of type `O` into the values, e.g. increments into a counter.
+    /// Merge operands with [merge_operand][Tree::merge_operand].
+    ///
+    /// The operands are deserialized with the `SerDe<K, O>` implementation of `SerDe`.
+    pub fn set_typed_merge_operator_with_operand<O>(
+        &self,
+        merge_operator: impl MergeOperator<K, V, O, SerDe> + 'static,
+    ) where
+        SerDe: serialize::SerDe<K, V> + serialize::SerDe<K, O>,
     {
         self.inner
-            .merge(SerDe::SK::serialize(key), SerDe::SV::serialize(value))
-            .map(|res| res.map(|old_v| SerDe::DV::deserialize(old_v)))
+            .set_merge_operator(move |key: &[u8], old_v: Option<&[u8]>, operand: &[u8]| {
+                let merge = || -> core::result::Result<Option<Vec<u8>>, crate::SerdeError> {
+                    let opt_v = merge_operator(
+                        <SerDe as serialize::SerDe<K, V>>::DK::deserialize(IVec::from(key))?,
+                        old_v
+                            .map(|v| {
+                                <SerDe as serialize::SerDe<K, V>>::DV::deserialize(IVec::from(v))
+                            })
+                            .transpose()?,
+                        <SerDe as serialize::SerDe<K, O>>::DV::deserialize(IVec::from(operand))?,
+                    );
+                    opt_v
+                        .map(|v| {
+                            <SerDe as serialize::SerDe<K, V>>::SV::serialize(&v)
+                                .map(|bytes| bytes.as_ref().to_vec())
+                        })
+                        .transpose()
+                };
+                merge().unwrap_or_else(|_| old_v.map(|v| v.to_vec()))
+            });
     }
 
-    /// For now this maps directly to sled::Tree::set_merge_operator,
+    /// This maps directly to sled::Tree::set_merge_operator,
     /// meaning you will have to handle (de)serialization yourself.
+    /// Use [set_typed_merge_operator][Tree::set_typed_merge_operator]
+    /// for a merge operator working on the types of the tree.
     ///
     /// Sets a merge operator for use with the `merge` function.
     ///
@@ -453,58 +562,32 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     /// Create a double-ended iterator over the tuples of keys and
     /// values in this tree.
     pub fn iter(&self) -> Iter<K, V, SerDe> {
-        Iter::from_sled(self.inner.iter())
+        Iter::from_sled(self.inner.iter(), self.inner.name())
     }
 
     /// Create a double-ended iterator over tuples of keys and values,
     /// where the keys fall within the specified range.
-    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<K, V, SerDe>
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Result<Iter<K, V, SerDe>>
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        match (range.start_bound(), range.end_bound()) {
-            (Bound::Unbounded, Bound::Unbounded) => {
-                Iter::from_sled(self.inner.range::<&[u8], _>(..))
-            }
-            (Bound::Unbounded, Bound::Excluded(b)) => {
-                Iter::from_sled(self.inner.range(..SerDe::SK::serialize(b)))
-            }
-            (Bound::Unbounded, Bound::Included(b)) => {
-                Iter::from_sled(self.inner.range(..=SerDe::SK::serialize(b)))
-            }
-            // FIX: This is not excluding lower bound.
-            (Bound::Excluded(b), Bound::Unbounded) => {
-                Iter::from_sled(self.inner.range(SerDe::SK::serialize(b)..))
-            }
-            (Bound::Excluded(b), Bound::Excluded(bb)) => Iter::from_sled(
-                self.inner
-                    .range(SerDe::SK::serialize(b)..SerDe::SK::serialize(bb)),
-            ),
-            (Bound::Excluded(b), Bound::Included(bb)) => Iter::from_sled(
-                self.inner
-                    .range(SerDe::SK::serialize(b)..=SerDe::SK::serialize(bb)),
-            ),
-            (Bound::Included(b), Bound::Unbounded) => {
-                Iter::from_sled(self.inner.range(SerDe::SK::serialize(b)..))
-            }
-            (Bound::Included(b), Bound::Excluded(bb)) => Iter::from_sled(
-                self.inner
-                    .range(SerDe::SK::serialize(b)..SerDe::SK::serialize(bb)),
-            ),
-            (Bound::Included(b), Bound::Included(bb)) => Iter::from_sled(
-                self.inner
-                    .range(SerDe::SK::serialize(b)..=SerDe::SK::serialize(bb)),
-            ),
-        }
+        let iter = self.inner.range((
+            encode_bound::<K, V, SerDe>(range.start_bound())?,
+            encode_bound::<K, V, SerDe>(range.end_bound())?,
+        ));
+        Ok(Iter::from_sled(iter, self.inner.name()))
     }
 
     /// Create an iterator over tuples of keys and values,
     /// where the all the keys starts with the given prefix.
-    pub fn scan_prefix(&self, prefix: &K) -> Iter<K, V, SerDe>
+    pub fn scan_prefix(&self, prefix: &K) -> Result<Iter<K, V, SerDe>>
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        Iter::from_sled(self.inner.scan_prefix(SerDe::SK::serialize(prefix)))
+        Ok(Iter::from_sled(
+            self.inner.scan_prefix(encode_key::<K, V, SerDe>(prefix)?),
+            self.inner.name(),
+        ))
     }
 
     /// Returns the first key and value in the `Tree`, or
@@ -513,9 +596,8 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .first()
-            .map(|res| res.map(|(k, v)| (SerDe::DK::deserialize(k), SerDe::DV::deserialize(v))))
+        let kv = self.inner.first()?;
+        self.decode_opt_kv(kv)
     }
 
     /// Returns the last key and value in the `Tree`, or
@@ -524,9 +606,8 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .last()
-            .map(|res| res.map(|(k, v)| (SerDe::DK::deserialize(k), SerDe::DV::deserialize(v))))
+        let kv = self.inner.last()?;
+        self.decode_opt_kv(kv)
     }
 
     /// Atomically removes the maximum item in the `Tree` instance.
@@ -534,9 +615,8 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .pop_max()
-            .map(|res| res.map(|(k, v)| (SerDe::DK::deserialize(k), SerDe::DV::deserialize(v))))
+        let kv = self.inner.pop_max()?;
+        self.decode_opt_kv(kv)
     }
 
     /// Atomically removes the minimum item in the `Tree` instance.
@@ -544,9 +624,8 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .pop_min()
-            .map(|res| res.map(|(k, v)| (SerDe::DK::deserialize(k), SerDe::DV::deserialize(v))))
+        let kv = self.inner.pop_min()?;
+        self.decode_opt_kv(kv)
     }
 
     /// Returns the number of elements in this tree.
@@ -563,7 +642,7 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     ///
     /// Note that this is not atomic.
     pub fn clear(&self) -> Result<()> {
-        self.inner.clear()
+        Ok(self.inner.clear()?)
     }
 
     /// Returns the name of the tree.
@@ -577,72 +656,190 @@ impl<K, V, SerDe> Tree<K, V, SerDe> {
     /// This is O(N) and locks the underlying tree
     /// for the duration of the entire scan.
     pub fn checksum(&self) -> Result<u32> {
-        self.inner.checksum()
+        Ok(self.inner.checksum()?)
     }
+
+    fn decode_opt_value(
+        &self,
+        key_bytes: &[u8],
+        value: Option<IVec>,
+    ) -> Result<Option<Value<K, V, SerDe>>>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        value
+            .map(|v| decode_value::<K, V, SerDe>(&self.inner.name(), key_bytes, v))
+            .transpose()
+    }
+
+    fn decode_opt_kv(
+        &self,
+        kv: Option<(IVec, IVec)>,
+    ) -> Result<Option<(Key<K, V, SerDe>, Value<K, V, SerDe>)>>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        kv.map(|(k, v)| decode_kv::<K, V, SerDe>(&self.inner.name(), k, v))
+            .transpose()
+    }
+}
+
+/// Runs the user supplied function of `update_and_fetch` and `fetch_and_update`.
+/// sled doesn't allow the function to fail, so on errors the stored value is
+/// returned unchanged and the error is stored in `error`.
+fn update<K, V, SerDe, F>(
+    tree: &[u8],
+    key_bytes: &[u8],
+    opt_value: Option<&[u8]>,
+    f: &mut F,
+    error: &mut Option<Error>,
+) -> Option<Vec<u8>>
+where
+    SerDe: serialize::SerDe<K, V>,
+    F: FnMut(Option<Value<K, V, SerDe>>) -> Option<V>,
+{
+    // sled may call the function multiple times, only the last call counts.
+    *error = None;
+    let res = opt_value
+        .map(|v| decode_value::<K, V, SerDe>(tree, key_bytes, sled::IVec::from(v)))
+        .transpose()
+        .and_then(|old| {
+            f(old)
+                .map(|value| {
+                    // TODO: Maybe add Into<IVec> to SerDe::SV::Bytes
+                    encode_value::<K, V, SerDe>(&value).map(|bytes| bytes.as_ref().to_vec())
+                })
+                .transpose()
+        });
+    match res {
+        Ok(new) => new,
+        Err(e) => {
+            *error = Some(e);
+            opt_value.map(|v| v.to_vec())
+        }
+    }
+}
+
+fn encode_key<K, V, SerDe: serialize::SerDe<K, V>>(
+    key: &K,
+) -> Result<<SerDe::SK as Serializer<K>>::Bytes> {
+    SerDe::SK::serialize(key).map_err(Error::Serialize)
+}
+
+fn encode_bound<K, V, SerDe: serialize::SerDe<K, V>>(
+    bound: Bound<&K>,
+) -> Result<Bound<<SerDe::SK as Serializer<K>>::Bytes>> {
+    Ok(match bound {
+        Bound::Included(key) => Bound::Included(encode_key::<K, V, SerDe>(key)?),
+        Bound::Excluded(key) => Bound::Excluded(encode_key::<K, V, SerDe>(key)?),
+        Bound::Unbounded => Bound::Unbounded,
+    })
+}
+
+fn encode_value<K, V, SerDe: serialize::SerDe<K, V>>(
+    value: &V,
+) -> Result<<SerDe::SV as Serializer<V>>::Bytes> {
+    SerDe::SV::serialize(value).map_err(Error::Serialize)
+}
+
+fn decode_value<K, V, SerDe: serialize::SerDe<K, V>>(
+    tree: &[u8],
+    key_bytes: &[u8],
+    value: IVec,
+) -> Result<Value<K, V, SerDe>> {
+    SerDe::DV::deserialize(value).map_err(|e| Error::deserialize(tree, key_bytes, e))
+}
+
+fn decode_kv<K, V, SerDe: serialize::SerDe<K, V>>(
+    tree: &[u8],
+    key: IVec,
+    value: IVec,
+) -> Result<(Key<K, V, SerDe>, Value<K, V, SerDe>)> {
+    let value = decode_value::<K, V, SerDe>(tree, &key, value)?;
+    let key = SerDe::DK::deserialize(key.clone()).map_err(|e| Error::deserialize(tree, &key, e))?;
+    Ok((key, value))
 }
 
 pub struct TransactionalTree<'a, K, V, SerDe> {
     inner: &'a sled::transaction::TransactionalTree,
+    tree: &'a [u8],
     _key: PhantomData<fn() -> K>,
     _value: PhantomData<fn() -> V>,
     _serde: PhantomData<fn(SerDe)>,
 }
 
+impl<K, V, SerDe> crate::transaction::TransactionTarget for &Tree<K, V, SerDe> {
+    type View<'a> = TransactionalTree<'a, K, V, SerDe>;
+
+    fn sled_tree(&self) -> &sled::Tree {
+        &self.inner
+    }
+
+    fn view<'a>(
+        &self,
+        tree: &'a sled::transaction::TransactionalTree,
+        name: &'a [u8],
+    ) -> Self::View<'a> {
+        TransactionalTree::new(tree, name)
+    }
+}
+
 impl<'a, K, V, SerDe> TransactionalTree<'a, K, V, SerDe> {
+    fn new(inner: &'a sled::transaction::TransactionalTree, tree: &'a [u8]) -> Self {
+        Self {
+            inner,
+            tree,
+            _key: PhantomData,
+            _value: PhantomData,
+            _serde: PhantomData,
+        }
+    }
+
     pub fn insert(
         &self,
         key: &K,
         value: &V,
-    ) -> std::result::Result<
-        Option<Value<K, V, SerDe>>,
-        sled::transaction::UnabortableTransactionError,
-    >
+    ) -> std::result::Result<Option<Value<K, V, SerDe>>, UnabortableTransactionError>
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .insert(
-                SerDe::SK::serialize(key).as_ref(),
-                SerDe::SV::serialize(value).as_ref(),
-            )
-            .map(|opt| opt.map(|v| SerDe::DV::deserialize(v)))
+        let key_bytes = encode_key::<K, V, SerDe>(key)?;
+        let old_value = self.inner.insert(
+            key_bytes.as_ref(),
+            encode_value::<K, V, SerDe>(value)?.as_ref(),
+        )?;
+        self.decode_opt_value(key_bytes.as_ref(), old_value)
     }
 
     pub fn remove(
         &self,
         key: &K,
-    ) -> std::result::Result<
-        Option<Value<K, V, SerDe>>,
-        sled::transaction::UnabortableTransactionError,
-    >
+    ) -> std::result::Result<Option<Value<K, V, SerDe>>, UnabortableTransactionError>
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .remove(SerDe::SK::serialize(key).as_ref())
-            .map(|opt| opt.map(|v| SerDe::DV::deserialize(v)))
+        let key_bytes = encode_key::<K, V, SerDe>(key)?;
+        let old_value = self.inner.remove(key_bytes.as_ref())?;
+        self.decode_opt_value(key_bytes.as_ref(), old_value)
     }
 
     pub fn get(
         &self,
         key: &K,
-    ) -> std::result::Result<
-        Option<Value<K, V, SerDe>>,
-        sled::transaction::UnabortableTransactionError,
-    >
+    ) -> std::result::Result<Option<Value<K, V, SerDe>>, UnabortableTransactionError>
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner
-            .get(SerDe::SK::serialize(key))
-            .map(|opt| opt.map(|v| SerDe::DV::deserialize(v)))
+        let key_bytes = encode_key::<K, V, SerDe>(key)?;
+        let value = self.inner.get(key_bytes.as_ref())?;
+        self.decode_opt_value(key_bytes.as_ref(), value)
     }
 
     pub fn apply_batch(
         &self,
         batch: &Batch<K, V, SerDe>,
-    ) -> std::result::Result<(), sled::transaction::UnabortableTransactionError> {
-        self.inner.apply_batch(&batch.inner)
+    ) -> std::result::Result<(), UnabortableTransactionError> {
+        Ok(self.inner.apply_batch(&batch.inner)?)
     }
 
     pub fn flush(&self) {
@@ -650,12 +847,42 @@ impl<'a, K, V, SerDe> TransactionalTree<'a, K, V, SerDe> {
     }
 
     pub fn generate_id(&self) -> Result<u64> {
-        self.inner.generate_id()
+        Ok(self.inner.generate_id()?)
     }
+
+    fn decode_opt_value(
+        &self,
+        key_bytes: &[u8],
+        value: Option<IVec>,
+    ) -> std::result::Result<Option<Value<K, V, SerDe>>, UnabortableTransactionError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        Ok(value
+            .map(|v| decode_value::<K, V, SerDe>(self.tree, key_bytes, v))
+            .transpose()?)
+    }
+}
+
+/// Merge operator for [Tree::set_typed_merge_operator_with_operand], which merges an operand of
+/// type `O` into the existing value of a key. Use `O = V` for [Tree::set_typed_merge_operator].
+pub trait MergeOperator<K, V, O, SerDe>:
+    Fn(Key<K, V, SerDe>, Option<Value<K, V, SerDe>>, Value<K, O, SerDe>) -> Option<V>
+where
+    SerDe: serialize::SerDe<K, V> + serialize::SerDe<K, O>,
+{
+}
+
+impl<K, V, O, SerDe, F> MergeOperator<K, V, O, SerDe> for F
+where
+    SerDe: serialize::SerDe<K, V> + serialize::SerDe<K, O>,
+    F: Fn(Key<K, V, SerDe>, Option<Value<K, V, SerDe>>, Value<K, O, SerDe>) -> Option<V>,
+{
 }
 
 pub struct Iter<K, V, SerDe> {
     inner: sled::Iter,
+    tree: IVec,
     _key: PhantomData<fn() -> K>,
     _value: PhantomData<fn() -> V>,
     _serde: PhantomData<fn(SerDe)>,
@@ -665,30 +892,33 @@ impl<K, V, SerDe: serialize::SerDe<K, V>> Iterator for Iter<K, V, SerDe> {
     type Item = Result<(Key<K, V, SerDe>, Value<K, V, SerDe>)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner
-            .next()
-            .map(|res| res.map(|(k, v)| (SerDe::DK::deserialize(k), SerDe::DV::deserialize(v))))
+        self.inner.next().map(|res| {
+            let (k, v) = res?;
+            decode_kv::<K, V, SerDe>(&self.tree, k, v)
+        })
     }
 
     fn last(mut self) -> Option<Self::Item> {
-        self.inner
-            .next_back()
-            .map(|res| res.map(|(k, v)| (SerDe::DK::deserialize(k), SerDe::DV::deserialize(v))))
+        self.next_back()
     }
 }
 
 impl<K, V, SerDe: serialize::SerDe<K, V>> DoubleEndedIterator for Iter<K, V, SerDe> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.inner
-            .next_back()
-            .map(|res| res.map(|(k, v)| (SerDe::DK::deserialize(k), SerDe::DV::deserialize(v))))
+        self.inner.next_back().map(|res| {
+            let (k, v) = res?;
+            decode_kv::<K, V, SerDe>(&self.tree, k, v)
+        })
     }
 }
 
 impl<K, V, SerDe> Iter<K, V, SerDe> {
-    pub fn from_sled(iter: sled::Iter) -> Self {
+    /// Wrap a `sled::Iter` over the tree with the given name.
+    /// The name is only used for error reporting.
+    pub fn from_sled(iter: sled::Iter, tree: IVec) -> Self {
         Iter {
             inner: iter,
+            tree,
             _key: PhantomData,
             _value: PhantomData,
             _serde: PhantomData,
@@ -724,21 +954,23 @@ pub struct Batch<K, V, SerDe> {
 }
 
 impl<K, V, SerDe> Batch<K, V, SerDe> {
-    pub fn insert(&mut self, key: &K, value: &V)
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<()>
     where
         SerDe: serialize::SerDe<K, V>,
     {
         self.inner.insert(
-            SerDe::SK::serialize(key).as_ref(),
-            SerDe::SV::serialize(value).as_ref(),
+            encode_key::<K, V, SerDe>(key)?.as_ref(),
+            encode_value::<K, V, SerDe>(value)?.as_ref(),
         );
+        Ok(())
     }
 
-    pub fn remove(&mut self, key: &K)
+    pub fn remove(&mut self, key: &K) -> Result<()>
     where
         SerDe: serialize::SerDe<K, V>,
     {
-        self.inner.remove(SerDe::SK::serialize(key).as_ref())
+        self.inner.remove(encode_key::<K, V, SerDe>(key)?.as_ref());
+        Ok(())
     }
 }
 
@@ -759,6 +991,7 @@ use pin_project::pin_project;
 pub struct Subscriber<K, V, SerDe> {
     #[pin]
     inner: sled::Subscriber,
+    tree: IVec,
     _key: PhantomData<fn() -> K>,
     _value: PhantomData<fn() -> V>,
     _serde: PhantomData<fn(SerDe)>,
@@ -768,18 +1001,21 @@ impl<K, V, SerDe> Subscriber<K, V, SerDe> {
     pub fn next_timeout(
         &mut self,
         timeout: core::time::Duration,
-    ) -> core::result::Result<Event<K, V, SerDe>, std::sync::mpsc::RecvTimeoutError>
+    ) -> core::result::Result<Result<Event<K, V, SerDe>>, std::sync::mpsc::RecvTimeoutError>
     where
         SerDe: serialize::SerDe<K, V>,
     {
         self.inner
             .next_timeout(timeout)
-            .map(|e| Event::from_sled(e))
+            .map(|e| Event::from_sled(e, &self.tree))
     }
 
-    pub fn from_sled(subscriber: sled::Subscriber) -> Self {
+    /// Wrap a `sled::Subscriber` of the tree with the given name.
+    /// The name is only used for error reporting.
+    pub fn from_sled(subscriber: sled::Subscriber, tree: IVec) -> Self {
         Self {
             inner: subscriber,
+            tree,
             _key: PhantomData,
             _value: PhantomData,
             _serde: PhantomData,
@@ -791,21 +1027,22 @@ use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll};
 impl<K: Unpin, V: Unpin, SerDe: serialize::SerDe<K, V>> Future for Subscriber<K, V, SerDe> {
-    type Output = Option<Event<K, V, SerDe>>;
+    type Output = Option<Result<Event<K, V, SerDe>>>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.project()
-            .inner
+        let this = self.project();
+        let tree = this.tree;
+        this.inner
             .poll(cx)
-            .map(|opt| opt.map(|e| Event::from_sled(e)))
+            .map(|opt| opt.map(|e| Event::from_sled(e, tree)))
     }
 }
 
 impl<K, V, SerDe: serialize::SerDe<K, V>> Iterator for Subscriber<K, V, SerDe> {
-    type Item = Event<K, V, SerDe>;
+    type Item = Result<Event<K, V, SerDe>>;
 
-    fn next(&mut self) -> Option<Event<K, V, SerDe>> {
-        self.inner.next().map(|e| Event::from_sled(e))
+    fn next(&mut self) -> Option<Result<Event<K, V, SerDe>>> {
+        self.inner.next().map(|e| Event::from_sled(e, &self.tree))
     }
 }
 
@@ -826,15 +1063,18 @@ impl<K, V, SerDe: serialize::SerDe<K, V>> Event<K, V, SerDe> {
         }
     }
 
-    pub fn from_sled(event: sled::Event) -> Self {
+    /// Deserialize a `sled::Event` of the tree with the given name.
+    /// The name is only used for error reporting.
+    pub fn from_sled(event: sled::Event, tree: &[u8]) -> Result<Self> {
         match event {
-            sled::Event::Insert { key, value } => Self::Insert {
-                key: SerDe::DK::deserialize(key),
-                value: SerDe::DV::deserialize(value),
-            },
-            sled::Event::Remove { key } => Self::Remove {
-                key: SerDe::DK::deserialize(key),
-            },
+            sled::Event::Insert { key, value } => {
+                let (key, value) = decode_kv::<K, V, SerDe>(tree, key, value)?;
+                Ok(Self::Insert { key, value })
+            }
+            sled::Event::Remove { key } => Ok(Self::Remove {
+                key: SerDe::DK::deserialize(key.clone())
+                    .map_err(|e| Error::deserialize(tree, &key, e))?,
+            }),
         }
     }
 }
@@ -857,7 +1097,7 @@ impl<V> fmt::Display for CompareAndSwapError<V> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::custom_serde::serialize::BincodeSerDe;
+    use crate::custom_serde::serialize::{BincodeSerDe, OrderedSerDe};
 
     #[test]
     fn test_range() {
@@ -875,11 +1115,20 @@ mod tests {
 
         let expect_results = [(6, 2), (10, 2)];
 
-        for (i, result) in tree.range(6..11).enumerate() {
+        for (i, result) in tree.range(6..11).unwrap().enumerate() {
             assert_eq!(result.unwrap(), expect_results[i]);
         }
     }
 
+    #[test]
+    fn test_range_bounds() {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().unwrap();
+
+        let tree: Tree<i32, u32, OrderedSerDe> = Tree::open(&db, "test_tree");
+        check_range_bounds!(tree);
+    }
+
     #[test]
     fn test_cas() {
         let config = sled::Config::new().temporary(true);
@@ -903,4 +1152,58 @@ mod tests {
             }),
         );
     }
+
+    #[test]
+    fn test_merge_operator() {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().unwrap();
+
+        let tree: Tree<u32, Vec<u32>, BincodeSerDe> = Tree::open(&db, "test_tree");
+        tree.set_typed_merge_operator(|_key, old, new| {
+            let mut merged = old.unwrap_or_default();
+            merged.extend(new);
+            Some(merged)
+        });
+
+        assert_eq!(tree.merge(&1, &vec![1]).unwrap(), Some(vec![1]));
+        assert_eq!(tree.merge(&1, &vec![2, 3]).unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(tree.get(&1).unwrap(), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_merge_operator_with_operand() {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().unwrap();
+
+        let tree: Tree<u32, u64, OrderedSerDe> = Tree::open(&db, "test_tree");
+        tree.set_typed_merge_operator_with_operand(|_key, old: Option<u64>, delta: i64| {
+            let value = old.unwrap_or(0) as i64 + delta;
+            if value > 0 {
+                Some(value as u64)
+            } else {
+                None
+            }
+        });
+
+        tree.merge_operand(&1, &5i64).unwrap();
+        tree.merge_operand(&1, &-2i64).unwrap();
+        assert_eq!(tree.get(&1).unwrap(), Some(3));
+        tree.merge_operand(&1, &-3i64).unwrap();
+        assert_eq!(tree.get(&1).unwrap(), None);
+
+        // Operands of the wrong type leave the stored value untouched.
+        tree.insert(&2, &7).unwrap();
+        tree.merge_operand(&2, &()).unwrap();
+        assert_eq!(tree.get(&2).unwrap(), Some(7));
+
+        // Stored values that can't be deserialized are reported.
+        let raw = db.open_tree("test_tree").unwrap();
+        let key_bytes = crate::serialize_key(&3u32).unwrap();
+        raw.insert(&key_bytes, &[1, 2, 3]).unwrap();
+        assert!(matches!(
+            tree.merge_operand(&3, &1i64),
+            Err(Error::Deserialize { .. })
+        ));
+        assert_eq!(raw.get(&key_bytes).unwrap().unwrap(), [1, 2, 3]);
+    }
 }
@@ -1,407 +1,1794 @@
-// //! A search engine for a single [Tree][crate::Tree], using [tantivy] under the hood.
-// //! # Example
-// //! ```
-// //! use typed_sled::search::SearchEngine;
-// //! use tantivy::{
-// //!     doc,
-// //!     schema::{Schema, TEXT},
-// //! };
-// //!
-// //! #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-// //! struct BlogPost {
-// //!     author: String,
-// //!     title: String,
-// //!     body: String,
-// //! }
-// //!
-// //! # pub fn main() -> Result<(), Box<dyn std::error::Error>> {
-// //! let config = sled::Config::new().temporary(true);
-// //! let db = config.open().unwrap();
-// //!
-// //! let tree = typed_sled::Tree::<u64, BlogPost>::open(&db, "unique_id");
-// //!
-// //! let post = BlogPost {
-// //!     author: "Mike".to_string(),
-// //!     title: "The life of the disillusioned".to_string(),
-// //!     body: "Long story short, he didn't have fun.".to_string(),
-// //! };
-// //! tree.insert(&0, &post)?;
-// //!
-// //! let mut schema_builder = Schema::builder();
-// //! let author = schema_builder.add_text_field("author", TEXT);
-// //! let title = schema_builder.add_text_field("title", TEXT);
-// //! let body = schema_builder.add_text_field("body", TEXT);
-// //!
-// //! let post_to_document = move |_k, v| {
-// //!     doc!(
-// //!         author => v.author.to_owned(),
-// //!         title => v.title.to_owned(),
-// //!         body => v.body.to_owned()
-// //!       )
-// //!  };
-// //! let search_engine = SearchEngine::open_temp(&tree, schema_builder, post_to_document);
-// //! let search_result = search_engine.search("life", 10)?;
-// //!
-// //! for result in search_results.iter() {
-// //!     println!("Found Blog Post with score {}:\n{:#?}", result.0, result.1);
-// //! }
-// //! # Ok(()) }
-// //! ```
-// //!
-// //! [tantivy]: https://docs.rs/tantivy/latest/tantivy/
-// use crate::custom_serde::{
-//     serialize::{self, BincodeSerDe, BincodeSerDeBounds, BincodeSerializer, Serializer},
-//     Event, Tree,
-// };
-
-// use std::fs::create_dir_all;
-// use std::iter::Iterator;
-// use std::thread;
-// use std::{marker::PhantomData, path::Path};
-// use tantivy::collector::TopDocs;
-// use tantivy::DocAddress;
-// use tantivy::{
-//     collector::Collector,
-//     directory::MmapDirectory,
-//     query::{Query, QueryParser},
-//     schema::{BytesOptions, Field, SchemaBuilder, Value},
-//     Document, Index, IndexReader, Score, Term,
-// };
-
-// /// A search engine for a single tree, using `tantivy` under the hood.
-// /// # Example
-// /// ```
-// /// use typed_sled::search::SearchEngine;
-// /// use tantivy::{
-// ///     doc,
-// ///     schema::{Schema, TEXT},
-// /// };
-// /// use serde::{Deserialize, Serialize};
-// ///
-// /// #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-// /// struct BlogPost {
-// ///     author: String,
-// ///     title: String,
-// ///     body: String,
-// /// }
-// ///
-// /// # pub fn main() -> Result<(), Box<dyn std::error::Error>> {
-// /// let config = sled::Config::new().temporary(true);
-// /// let db = config.open().unwrap();
-// ///
-// /// let tree = typed_sled::Tree::<u64, BlogPost>::open(&db, "unique_id");
-// ///
-// /// let post = BlogPost {
-// ///     author: "Mike".to_string(),
-// ///     title: "The life of the disillusioned".to_string(),
-// ///     body: "Long story short, he didn't have fun.".to_string(),
-// /// };
-// /// tree.insert(&0, &post)?;
-// ///
-// /// let mut schema_builder = Schema::builder();
-// /// let author = schema_builder.add_text_field("author", TEXT);
-// /// let title = schema_builder.add_text_field("title", TEXT);
-// /// let body = schema_builder.add_text_field("body", TEXT);
-// ///
-// /// let search_engine = SearchEngine::new_temp(&tree, schema_builder, move |_k, v| {
-// ///     doc!(
-// ///         author => v.author.to_owned(),
-// ///         title => v.title.to_owned(),
-// ///         body => v.body.to_owned()
-// ///       )
-// ///  })?;
-// /// let search_results = search_engine.search("life", 10)?;
-// ///
-// /// for result in search_results.iter() {
-// ///     println!("Found Blog Post with score {}:\n{:#?}", result.0, result.1);
-// /// }
-// /// # Ok(()) }
-// /// ```
-// pub struct SearchEngine<K, V> {
-//     tree: Tree<K, V>,
-//     pub index: Index,
-//     index_reader: IndexReader,
-//     key_field: Field,
-//     phantom_key: PhantomData<fn() -> K>,
-//     phantom_value: PhantomData<fn() -> V>,
-// }
-
-// impl<K, V> SearchEngine<K, V> {
-//     /// Create a new search engine or if the path already exists
-//     /// open an existing search engine.
-//     pub fn new<P: AsRef<Path> + Clone, F>(
-//         path: P,
-//         tree: &Tree<K, V>,
-//         schema_builder: SchemaBuilder,
-//         f: F,
-//     ) -> Result<Self, SearchError>
-//     where
-//         F: Fn(&K, &V) -> Document + Send + Sync + 'static,
-//         K: BincodeSerDeBounds + 'static,
-//         V: BincodeSerDeBounds + 'static,
-//     {
-//         Self::new_with_options(Some(path), tree, schema_builder, f)
-//     }
-
-//     /// Create a new temporary search engine.
-//     pub fn new_temp<F>(
-//         tree: &Tree<K, V>,
-//         schema_builder: SchemaBuilder,
-//         f: F,
-//     ) -> Result<Self, SearchError>
-//     where
-//         F: Fn(&K, &V) -> Document + Send + Sync + 'static,
-//         K: BincodeSerDeBounds + 'static,
-//         V: BincodeSerDeBounds + 'static,
-//     {
-//         Self::new_with_options::<&str, _>(None, tree, schema_builder, f)
-//     }
-
-//     // Change unwraps
-//     /// Create a new search engine with more options.
-//     fn new_with_options<P: AsRef<Path> + Clone, F>(
-//         path: Option<P>,
-//         tree: &Tree<K, V>,
-//         mut schema_builder: SchemaBuilder,
-//         f: F,
-//     ) -> Result<Self, SearchError>
-//     where
-//         F: Fn(&K, &V) -> Document + Send + Sync + 'static,
-//         K: BincodeSerDeBounds + 'static,
-//         V: BincodeSerDeBounds + 'static,
-//     {
-//         let key_field = schema_builder.add_bytes_field(
-//             "_typed_sled_key",
-//             BytesOptions::default()
-//                 .set_indexed()
-//                 .set_stored()
-//                 .set_fast(),
-//         );
-//         let schema = schema_builder.build();
-
-//         let f = move |k: &K, v: &V| {
-//             let mut document = f(k, v);
-//             document.add_bytes(key_field, BincodeSerializer::serialize(k));
-//             document
-//         };
-
-//         let mut from_new = false;
-//         let index = if let Some(path) = path {
-//             from_new = !create_dir_all(path.clone()).is_err();
-//             Index::open_or_create(
-//                 MmapDirectory::open(path).expect("SearchEngine: failed to open directory"),
-//                 schema,
-//             )?
-//         } else {
-//             from_new = true;
-//             Index::create_from_tempdir(schema)?
-//         };
-
-//         if from_new {
-//             let mut index_writer = index.writer(100_000_000)?;
-//             for r in tree.iter() {
-//                 let (k, v) = r?;
-//                 index_writer.add_document(f(&k, &v));
-//             }
-//             index_writer.commit()?;
-//         }
-
-//         let mut subscriber = tree.watch_all();
-//         let mut index_writer = index.writer(5_000_000)?;
-//         thread::spawn(move || {
-//             while let Some(e) = subscriber.next() {
-//                 match e {
-//                     Event::Insert { key, value } => {
-//                         index_writer.add_document(f(&key, &value));
-//                         // How should this error be handled?
-//                         index_writer.commit();
-//                     }
-//                     Event::Remove { key } => {
-//                         index_writer.delete_term(Term::from_field_bytes(
-//                             key_field,
-//                             &BincodeSerializer::serialize(&key),
-//                         ));
-//                         // How should this error be handled?
-//                         index_writer.commit();
-//                     }
-//                 }
-//             }
-//         });
-
-//         let index_reader = index.reader()?;
-//         Ok(SearchEngine {
-//             tree: tree.to_owned(),
-//             index,
-//             index_reader,
-//             key_field,
-//             phantom_key: PhantomData,
-//             phantom_value: PhantomData,
-//         })
-//     }
-
-//     /// Search for all key value pairs matching a query. The query will be
-//     /// parsed by the `QueryParser` from `tantivy`. All fields of the schema
-//     /// will be queried.
-//     /// For more info on what queries can be used:
-//     /// https://docs.rs/tantivy/latest/tantivy/query/struct.QueryParser.html
-//     pub fn search(
-//         &self,
-//         query: &str,
-//         limit: usize,
-//     ) -> Result<Vec<(Score, Option<(K, V)>)>, SearchError>
-//     where
-//         K: BincodeSerDeBounds,
-//         V: BincodeSerDeBounds,
-//     {
-//         let query_parser = QueryParser::for_index(
-//             &self.index,
-//             self.index
-//                 .schema()
-//                 .fields()
-//                 .map(|(field, _)| field)
-//                 .collect(),
-//         );
-//         let query = query_parser.parse_query(query)?;
-
-//         self.search_with_query(&query, limit)
-//     }
-
-//     /// Search for all key value pairs matching a custom query.
-//     /// See `tantivy` queries for what type of queries can be used.
-//     pub fn search_with_query(
-//         &self,
-//         query: &dyn Query,
-//         limit: usize,
-//     ) -> Result<Vec<(Score, Option<(K, V)>)>, SearchError>
-//     where
-//         K: BincodeSerDeBounds,
-//         V: BincodeSerDeBounds,
-//     {
-//         let searcher = self.index_reader.searcher();
-//         println!(
-//             "{}",
-//             searcher.search(query, &TopDocs::with_limit(limit))?.len()
-//         );
-
-//         let mut v = Vec::new();
-//         for (score, doc_addr) in self
-//             .search_with_query_and_collector(query, &TopDocs::with_limit(limit))?
-//             .iter()
-//         {
-//             let doc = searcher.doc(*doc_addr)?;
-
-//             let key_bytes = if let Value::Bytes(bytes) = doc
-//                 .get_first(self.key_field)
-//                 .ok_or(SearchError::DocDoesNotExist)?
-//             {
-//                 bytes
-//             } else {
-//                 panic!();
-//             };
-//             let kv = self.tree.get_kv_from_raw(key_bytes)?;
-//             v.push((*score, kv))
-//         }
-//         Ok(v)
-//     }
-
-//     /// Search for all key value pairs matching a query and collect them with a custom collector.
-//     /// The query will be parsed by the `QueryParser` from `tantivy`. All fields of the schema
-//     /// will be queried.
-//     /// See `tantivy` for what type of collectors can be used.
-//     pub fn search_with_collector<C: Collector>(
-//         &self,
-//         query: &str,
-//         collector: &C,
-//     ) -> Result<C::Fruit, SearchError> {
-//         let query_parser = QueryParser::for_index(
-//             &self.index,
-//             self.index
-//                 .schema()
-//                 .fields()
-//                 .map(|(field, _)| field)
-//                 .collect(),
-//         );
-//         let query = query_parser.parse_query(query)?;
-
-//         self.search_with_query_and_collector(&query, collector)
-//     }
-
-//     /// Search for all key value pairs matching a custom query and collect them with a custom collector.
-//     /// See `tantivy` for what type of queries and collectors can be used.
-//     pub fn search_with_query_and_collector<C: Collector>(
-//         &self,
-//         query: &dyn Query,
-//         collector: &C,
-//     ) -> Result<C::Fruit, SearchError> {
-//         let searcher = self.index_reader.searcher();
-//         Ok(searcher.search(query, collector)?)
-//     }
-
-//     /// Retrieve the key value pair corresponding to a `DocAdress`.
-//     /// This can for example be used after using `search_with_collector` with
-//     /// a custom collector which yields `DocAdress`es.
-//     pub fn doc_address_to_kv(&self, doc_addr: DocAddress) -> Result<Option<(K, V)>, SearchError>
-//     where
-//         K: BincodeSerDeBounds,
-//         V: BincodeSerDeBounds,
-//     {
-//         let doc = self.index_reader.searcher().doc(doc_addr)?;
-
-//         let key_bytes = if let Value::Bytes(bytes) = doc
-//             .get_first(self.key_field)
-//             .ok_or(SearchError::DocDoesNotExist)?
-//         {
-//             bytes
-//         } else {
-//             panic!();
-//         };
-//         let kv = self.tree.get_kv_from_raw(key_bytes)?;
-//         Ok(kv)
-//     }
-
-//     /// Retrieve the key value pair corresponding to an Iterator over `DocAdress`.
-//     /// This can for example be used after using `search_with_collector` with
-//     /// a custom collector which yields `DocAdress`es.
-//     pub fn doc_adresses_to_kvs<I: Iterator<Item = DocAddress>>(
-//         &self,
-//         doc_addrs: I,
-//     ) -> Result<Vec<Option<(K, V)>>, SearchError>
-//     where
-//         K: BincodeSerDeBounds,
-//         V: BincodeSerDeBounds,
-//     {
-//         let searcher = self.index_reader.searcher();
-//         let mut v = Vec::new();
-//         for doc_addr in doc_addrs {
-//             let doc = searcher.doc(doc_addr)?;
-//             let key_bytes = if let Value::Bytes(bytes) = doc
-//                 .get_first(self.key_field)
-//                 .ok_or(SearchError::DocDoesNotExist)?
-//             {
-//                 bytes
-//             } else {
-//                 panic!();
-//             };
-//             let kv = self.tree.get_kv_from_raw(key_bytes)?;
-//             v.push(kv);
-//         }
-//         Ok(v)
-//     }
-
-//     /// Get the index of the SearchEngine.
-//     pub fn index(&self) -> &Index {
-//         &self.index
-//     }
-// }
-
-// #[derive(Debug, thiserror::Error)]
-// pub enum SearchError {
-//     #[error("Tree error: {0}")]
-//     Tree(#[from] sled::Error),
-//     #[error("Search error: {0}")]
-//     Search(#[from] tantivy::TantivyError),
-//     #[error("Error while parsing query: {0}")]
-//     QueryParserError(#[from] tantivy::query::QueryParserError),
-//     #[error("Document Address doesn't exist")]
-//     DocDoesNotExist,
-// }
+//! A search engine for a single [Tree][crate::custom_serde::Tree], using [tantivy] under the hood.
+//!
+//! The document builder receives the deserialized keys and values of the
+//! tree's `SerDe`, e.g. [Lazy][crate::custom_serde::serialize::Lazy] values
+//! when using [BincodeSerDeLazy][crate::custom_serde::serialize::BincodeSerDeLazy].
+//! # Example
+//! ```
+//! use typed_sled::custom_serde::{search::SearchEngine, serialize::BincodeSerDeLazy, Tree};
+//! use tantivy::{
+//!     doc,
+//!     schema::{Schema, TEXT},
+//! };
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+//! struct BlogPost {
+//!     author: String,
+//!     title: String,
+//!     body: String,
+//! }
+//!
+//! pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let config = sled::Config::new().temporary(true);
+//!     let db = config.open().unwrap();
+//!
+//!     let tree = Tree::<u64, BlogPost, BincodeSerDeLazy>::open(&db, "unique_id");
+//!
+//!     let post = BlogPost {
+//!         author: "Mike".to_string(),
+//!         title: "The life of the disillusioned".to_string(),
+//!         body: "Long story short, he didn't have fun.".to_string(),
+//!     };
+//!     tree.insert(&0, &post)?;
+//!
+//!     let mut schema_builder = Schema::builder();
+//!     let author = schema_builder.add_text_field("author", TEXT);
+//!     let title = schema_builder.add_text_field("title", TEXT);
+//!     let body = schema_builder.add_text_field("body", TEXT);
+//!
+//!     let search_engine = SearchEngine::new_temp(&tree, schema_builder, move |_k, v| {
+//!         let v = v.deserialize();
+//!         doc!(
+//!             author => v.author.to_owned(),
+//!             title => v.title.to_owned(),
+//!             body => v.body.to_owned()
+//!         )
+//!     })?;
+//!     let search_results = search_engine.search("life", 10)?;
+//!
+//!     for hit in search_results.iter() {
+//!         println!("Found Blog Post with score {}:\n{:#?}", hit.score, hit.value.deserialize());
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! [tantivy]: https://docs.rs/tantivy/latest/tantivy/
+use crate::custom_serde::{
+    decode_kv,
+    serialize::{self, Deserializer as _, Key, Value as TreeValue},
+    Tree,
+};
+use crate::searchable::{SearchFields, Searchable};
+
+use serde::{Deserialize, Serialize};
+use sled::{Event, IVec};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::create_dir_all;
+use std::iter::Iterator;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tantivy::collector::{
+    Count, FacetCollector, FacetCounts as TantivyFacetCounts, FruitHandle, MultiCollector, TopDocs,
+};
+use tantivy::fastfield::{BytesFastFieldReader, Column, MultiValuedFastFieldReader};
+use tantivy::{
+    collector::{Collector, SegmentCollector},
+    directory::MmapDirectory,
+    query::{Query, QueryParser},
+    schema::{BytesOptions, Cardinality, Facet, Field, FieldType, Schema, SchemaBuilder, Value},
+    u64_to_f64, u64_to_i64, DocAddress, DocId, Document, Index, IndexReader, IndexWriter, Score,
+    Searcher, SegmentOrdinal, SegmentReader, Snippet, SnippetGenerator, Term,
+};
+
+/// A search engine for a single tree, using `tantivy` under the hood.
+/// See the [module documentation][self] for an example.
+pub struct SearchEngine<K, V, SerDe> {
+    tree: Tree<K, V, SerDe>,
+    pub index: Index,
+    index_reader: IndexReader,
+    key_field: Field,
+    // Builds the document of an entry, also used to create snippets.
+    document: Arc<DocumentFn>,
+    indexer: Arc<IndexerHandle>,
+}
+
+type DocumentFn = dyn Fn(IVec, IVec) -> crate::Result<Document> + Send + Sync;
+
+// Manual implementation, so K, V and SerDe don't need to implement Clone.
+impl<K, V, SerDe> Clone for SearchEngine<K, V, SerDe> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            index: self.index.clone(),
+            index_reader: self.index_reader.clone(),
+            key_field: self.key_field,
+            document: self.document.clone(),
+            indexer: self.indexer.clone(),
+        }
+    }
+}
+
+impl<K, V, SerDe> SearchEngine<K, V, SerDe> {
+    /// Create a new search engine or if the path already exists
+    /// open an existing search engine.
+    ///
+    /// An existing index is rebuilt if it might not match the tree anymore,
+    /// e.g. because the tree was modified while the search engine wasn't
+    /// running or the previous search engine wasn't shut down cleanly.
+    pub fn new<P: AsRef<Path> + Clone, F>(
+        path: P,
+        tree: &Tree<K, V, SerDe>,
+        schema_builder: SchemaBuilder,
+        f: F,
+    ) -> Result<Self, SearchError>
+    where
+        F: Fn(&Key<K, V, SerDe>, &TreeValue<K, V, SerDe>) -> Document + Send + Sync + 'static,
+        K: 'static,
+        V: 'static,
+        SerDe: serialize::SerDe<K, V> + 'static,
+    {
+        Self::new_with_options(Some(path), tree, schema_builder, CommitPolicy::default(), f)
+    }
+
+    /// Create a new temporary search engine.
+    pub fn new_temp<F>(
+        tree: &Tree<K, V, SerDe>,
+        schema_builder: SchemaBuilder,
+        f: F,
+    ) -> Result<Self, SearchError>
+    where
+        F: Fn(&Key<K, V, SerDe>, &TreeValue<K, V, SerDe>) -> Document + Send + Sync + 'static,
+        K: 'static,
+        V: 'static,
+        SerDe: serialize::SerDe<K, V> + 'static,
+    {
+        Self::new_with_options::<&str, _>(None, tree, schema_builder, CommitPolicy::default(), f)
+    }
+
+    /// Create a new search engine with more options.
+    /// If `path` is `None` a temporary search engine is created.
+    pub fn new_with_options<P: AsRef<Path> + Clone, F>(
+        path: Option<P>,
+        tree: &Tree<K, V, SerDe>,
+        mut schema_builder: SchemaBuilder,
+        commit_policy: CommitPolicy,
+        f: F,
+    ) -> Result<Self, SearchError>
+    where
+        F: Fn(&Key<K, V, SerDe>, &TreeValue<K, V, SerDe>) -> Document + Send + Sync + 'static,
+        K: 'static,
+        V: 'static,
+        SerDe: serialize::SerDe<K, V> + 'static,
+    {
+        let key_field = schema_builder.add_bytes_field(
+            "_typed_sled_key",
+            BytesOptions::default()
+                .set_indexed()
+                .set_stored()
+                .set_fast(),
+        );
+        let schema = schema_builder.build();
+
+        // The stored keys are already serialized by `SerDe::SK`, so they are
+        // used as is to identify the documents.
+        let tree_name = tree.inner.name();
+        let document: Arc<DocumentFn> = Arc::new(move |key: IVec, value: IVec| {
+            let (k, v) = decode_kv::<K, V, SerDe>(&tree_name, key.clone(), value)?;
+            let mut document = f(&k, &v);
+            document.add_bytes(key_field, key.to_vec());
+            Ok(document)
+        });
+
+        let index = if let Some(path) = path {
+            create_dir_all(path.clone()).map_err(tantivy::TantivyError::from)?;
+            Index::open_or_create(
+                MmapDirectory::open(path).map_err(tantivy::TantivyError::from)?,
+                schema,
+            )?
+        } else {
+            Index::create_from_tempdir(schema)?
+        };
+        let up_to_date = index.load_metas()?.payload == Some(clean_watermark(tree.checksum()?));
+
+        // Subscribing before the initial indexing so no write is missed.
+        // Entries written in between are indexed twice, which is fine
+        // because the indexer replaces the documents of existing keys.
+        let subscriber = tree.inner.watch_prefix(vec![]);
+        let index_reader = index.reader()?;
+        let (control_sender, control_receiver) = mpsc::channel();
+        let (error_sender, error_receiver) = mpsc::channel();
+        let mut indexer = Indexer {
+            tree: tree.to_owned(),
+            subscriber,
+            writer: index.writer(5_000_000)?,
+            reader: index_reader.clone(),
+            key_field,
+            document: document.clone(),
+            commit_policy,
+            control: control_receiver,
+            errors: error_sender,
+            pending: 0,
+            first_pending: None,
+        };
+        if !up_to_date {
+            indexer.rebuild()?;
+        }
+        let handle = thread::spawn(move || indexer.run());
+
+        Ok(SearchEngine {
+            tree: tree.to_owned(),
+            index,
+            index_reader,
+            key_field,
+            document,
+            indexer: Arc::new(IndexerHandle {
+                control: control_sender,
+                errors: Mutex::new(error_receiver),
+                thread: Mutex::new(Some(handle)),
+            }),
+        })
+    }
+
+    /// Create a new temporary search engine for a tree with [Searchable] values.
+    /// The schema and the documents are derived from the value type.
+    pub fn for_searchable(tree: &Tree<K, V, SerDe>) -> Result<Self, SearchError>
+    where
+        K: 'static,
+        V: 'static,
+        SerDe: serialize::SerDe<K, V> + 'static,
+        TreeValue<K, V, SerDe>: Searchable,
+    {
+        Self::for_searchable_with_options::<&str>(None, tree, CommitPolicy::default())
+    }
+
+    /// Create a new search engine for a tree with [Searchable] values with more options.
+    /// If `path` is `None` a temporary search engine is created.
+    pub fn for_searchable_with_options<P: AsRef<Path> + Clone>(
+        path: Option<P>,
+        tree: &Tree<K, V, SerDe>,
+        commit_policy: CommitPolicy,
+    ) -> Result<Self, SearchError>
+    where
+        K: 'static,
+        V: 'static,
+        SerDe: serialize::SerDe<K, V> + 'static,
+        TreeValue<K, V, SerDe>: Searchable,
+    {
+        let mut schema_builder = Schema::builder();
+        let fields = SearchFields::new::<TreeValue<K, V, SerDe>>(&mut schema_builder);
+        Self::new_with_options(path, tree, schema_builder, commit_policy, move |_k, v| {
+            fields.document(v)
+        })
+    }
+
+    /// Block until everything written to the tree before this call is searchable.
+    ///
+    /// Returns [SearchError::IndexerStopped] if the indexer isn't running anymore.
+    pub fn wait_until_indexed(&self) -> Result<(), SearchError> {
+        let (ack_sender, ack_receiver) = mpsc::channel();
+        self.indexer
+            .control
+            .send(Control::Sync(ack_sender))
+            .map_err(|_| SearchError::IndexerStopped)?;
+        ack_receiver
+            .recv()
+            .map_err(|_| SearchError::IndexerStopped)?
+    }
+
+    /// Rebuild the index from the entries of the tree, blocking until it's done.
+    ///
+    /// This is done automatically when opening a search engine whose index
+    /// might not match the tree, for example because the process crashed
+    /// before the indexer committed all changes. Calling it manually is only
+    /// needed to repair an index which was modified by other means.
+    pub fn reindex(&self) -> Result<(), SearchError> {
+        let (ack_sender, ack_receiver) = mpsc::channel();
+        self.indexer
+            .control
+            .send(Control::Reindex(ack_sender))
+            .map_err(|_| SearchError::IndexerStopped)?;
+        ack_receiver
+            .recv()
+            .map_err(|_| SearchError::IndexerStopped)?
+    }
+
+    /// Index the pending changes and stop the indexer, waiting for it to finish.
+    ///
+    /// This stops the indexer of all clones of this `SearchEngine`, searching
+    /// is still possible afterwards. The indexer is also stopped when the last
+    /// clone is dropped.
+    pub fn shutdown(&self) -> Result<(), SearchError> {
+        self.indexer.shutdown()
+    }
+
+    /// Returns `true` as long as the indexer is running.
+    pub fn is_indexer_running(&self) -> bool {
+        self.indexer.is_running()
+    }
+
+    /// Returns the errors the indexer ran into since the last call.
+    ///
+    /// The indexer keeps running after an error, but the change it failed
+    /// to index won't be searchable.
+    pub fn indexer_errors(&self) -> Vec<SearchError> {
+        self.indexer
+            .errors
+            .lock()
+            .expect("SearchEngine: poisoned lock")
+            .try_iter()
+            .collect()
+    }
+
+    /// Returns the field of the schema with the given name.
+    ///
+    /// Returns [SearchError::UnknownField] if there is no such field.
+    pub fn field(&self, name: &str) -> Result<Field, SearchError> {
+        self.index
+            .schema()
+            .get_field(name)
+            .ok_or_else(|| SearchError::UnknownField(name.to_owned()))
+    }
+
+    /// Search for all key value pairs matching a query in the given fields.
+    /// The query will be parsed by the `QueryParser` from `tantivy`, see [search][Self::search].
+    pub fn search_in(
+        &self,
+        fields: &[&str],
+        query: impl AsRef<str>,
+        limit: usize,
+    ) -> Result<SearchResults<Key<K, V, SerDe>, TreeValue<K, V, SerDe>>, SearchError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let fields = fields
+            .iter()
+            .map(|name| self.field(name))
+            .collect::<Result<_, _>>()?;
+        let query = QueryParser::for_index(&self.index, fields).parse_query(query.as_ref())?;
+        self.search_with_query(&query, limit)
+    }
+
+    /// Search for all key value pairs matching a query. The query will be
+    /// parsed by the `QueryParser` from `tantivy`. All fields of `tanvity::schema::Type::Str`
+    /// will be queried. This includes all fields created with `TEXT` or `STRING`.
+    /// For more info on what queries can be used:
+    /// https://docs.rs/tantivy/latest/tantivy/query/struct.QueryParser.html
+    pub fn search(
+        &self,
+        query: impl AsRef<str>,
+        limit: usize,
+    ) -> Result<SearchResults<Key<K, V, SerDe>, TreeValue<K, V, SerDe>>, SearchError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let query = self.parse_query(query)?;
+        self.search_with_query(&*query, limit)
+    }
+
+    /// Parse a query like [search][Self::search] does.
+    pub fn parse_query(&self, query: impl AsRef<str>) -> Result<Box<dyn Query>, SearchError> {
+        use tantivy::schema::Type;
+        // Default types are only String like types
+        let query_parser = QueryParser::for_index(
+            &self.index,
+            self.index
+                .schema()
+                .fields()
+                .filter_map(|(field, ty)| {
+                    if matches!(ty.field_type().value_type(), Type::Str) {
+                        Some(field)
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+        );
+        Ok(query_parser.parse_query(query.as_ref())?)
+    }
+
+    /// Search for all key value pairs matching a custom query.
+    /// See `tantivy` queries for what type of queries can be used.
+    pub fn search_with_query(
+        &self,
+        query: &dyn Query,
+        limit: usize,
+    ) -> Result<SearchResults<Key<K, V, SerDe>, TreeValue<K, V, SerDe>>, SearchError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let searcher = self.index_reader.searcher();
+        let top_docs = searcher.search(query, &TopDocs::with_limit(limit))?;
+        self.load_top_docs(&searcher, top_docs)
+    }
+
+    /// Search for all key value pairs matching a query, parsed like [search][Self::search] does,
+    /// and aggregate all hits, not only the returned ones, as described by `facets`.
+    /// Use a `limit` of 0 to only aggregate.
+    ///
+    /// The facet, numeric and date fields have to be added to the documents
+    /// by the document builder, numeric and date fields as fast fields.
+    pub fn search_with_facets(
+        &self,
+        query: impl AsRef<str>,
+        limit: usize,
+        facets: &[FacetRequest],
+    ) -> Result<FacetedResults<Key<K, V, SerDe>, TreeValue<K, V, SerDe>>, SearchError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let query = self.parse_query(query)?;
+        self.search_with_query_and_facets(&*query, limit, facets)
+    }
+
+    /// Search for all key value pairs matching a custom query and aggregate
+    /// the hits, see [search_with_facets][Self::search_with_facets].
+    pub fn search_with_query_and_facets(
+        &self,
+        query: &dyn Query,
+        limit: usize,
+        facets: &[FacetRequest],
+    ) -> Result<FacetedResults<Key<K, V, SerDe>, TreeValue<K, V, SerDe>>, SearchError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        enum Handle {
+            Facet(FruitHandle<TantivyFacetCounts>, Facet),
+            Ranges(FruitHandle<Vec<u64>>, Vec<Range<f64>>),
+        }
+
+        let schema = self.index.schema();
+        let mut collector = MultiCollector::new();
+        // `TopDocs` doesn't support a limit of 0, which is used to only aggregate.
+        let top_docs = (limit > 0).then(|| collector.add_collector(TopDocs::with_limit(limit)));
+        let count = collector.add_collector(Count);
+        let mut handles = Vec::with_capacity(facets.len());
+        for request in facets {
+            match request {
+                FacetRequest::Facet { field, parent } => {
+                    let field_id = self.field(field)?;
+                    if !matches!(
+                        schema.get_field_entry(field_id).field_type(),
+                        FieldType::Facet(_)
+                    ) {
+                        return Err(SearchError::UnsupportedField(field.clone()));
+                    }
+                    let parent = Facet::from_text(parent)?;
+                    let mut facet_collector = FacetCollector::for_field(field_id);
+                    facet_collector.add_facet(parent.clone());
+                    handles.push(Handle::Facet(
+                        collector.add_collector(facet_collector),
+                        parent,
+                    ));
+                }
+                FacetRequest::Ranges { field, ranges } => {
+                    let field_id = self.field(field)?;
+                    let (to_f64, cardinality) =
+                        numeric_fast_field(schema.get_field_entry(field_id).field_type())
+                            .ok_or_else(|| SearchError::UnsupportedField(field.clone()))?;
+                    let range_collector = RangeCollector {
+                        field: field_id,
+                        cardinality,
+                        to_f64,
+                        ranges: ranges.clone(),
+                    };
+                    handles.push(Handle::Ranges(
+                        collector.add_collector(range_collector),
+                        ranges.clone(),
+                    ));
+                }
+            }
+        }
+
+        let searcher = self.index_reader.searcher();
+        let mut fruits = searcher.search(query, &collector)?;
+        let top_docs = top_docs.map_or_else(Vec::new, |top_docs| top_docs.extract(&mut fruits));
+        let results = self.load_top_docs(&searcher, top_docs)?;
+        let total_hits = count.extract(&mut fruits);
+        let facets = handles
+            .into_iter()
+            .map(|handle| match handle {
+                Handle::Facet(handle, parent) => FacetResult::Facet(
+                    handle
+                        .extract(&mut fruits)
+                        .get(parent)
+                        .map(|(facet, count)| (facet.to_string(), count))
+                        .collect(),
+                ),
+                Handle::Ranges(handle, ranges) => FacetResult::Ranges(
+                    ranges
+                        .into_iter()
+                        .zip(handle.extract(&mut fruits))
+                        .collect(),
+                ),
+            })
+            .collect();
+        Ok(FacetedResults {
+            results,
+            total_hits,
+            facets,
+        })
+    }
+
+    fn load_top_docs(
+        &self,
+        searcher: &Searcher,
+        top_docs: Vec<(Score, DocAddress)>,
+    ) -> Result<SearchResults<Key<K, V, SerDe>, TreeValue<K, V, SerDe>>, SearchError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let mut scored_keys = Vec::with_capacity(top_docs.len());
+        for (score, doc_addr) in top_docs {
+            let doc = searcher.doc(doc_addr)?;
+            scored_keys.push((score, self.key_bytes(&doc)?.to_vec()));
+        }
+        let (hits, missing) = self.load_hits(scored_keys, &[])?;
+        Ok(SearchResults { hits, missing })
+    }
+
+    /// Search for a page of key value pairs matching a query, parsed like [search][Self::search] does.
+    ///
+    /// Pass the [next_cursor][SearchPage::next_cursor] of a page to get the following page.
+    /// Hits are ordered by descending score and then by the serialized bytes of their
+    /// keys, so the pages stay consistent while the tree is modified, as long as the
+    /// scores don't change. The bytes are only ordered like the keys themselves if
+    /// `SerDe` preserves the order, like [OrderedSerDe][crate::custom_serde::serialize::OrderedSerDe].
+    pub fn search_page(
+        &self,
+        query: impl AsRef<str>,
+        page_size: usize,
+        cursor: Option<&SearchCursor>,
+    ) -> Result<SearchPage<Key<K, V, SerDe>, TreeValue<K, V, SerDe>>, SearchError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let query = self.parse_query(query)?;
+        self.search_page_with_query(&*query, page_size, cursor, &[])
+    }
+
+    /// Search for a page of key value pairs matching a custom query, see [search_page][Self::search_page].
+    ///
+    /// For every field in `snippet_fields` a [Snippet] highlighting the matched
+    /// terms is created for every hit. The snippets are generated from the
+    /// documents of the current values, so the fields don't need to be stored.
+    pub fn search_page_with_query(
+        &self,
+        query: &dyn Query,
+        page_size: usize,
+        cursor: Option<&SearchCursor>,
+        snippet_fields: &[&str],
+    ) -> Result<SearchPage<Key<K, V, SerDe>, TreeValue<K, V, SerDe>>, SearchError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let searcher = self.index_reader.searcher();
+        let snippet_generators = snippet_fields
+            .iter()
+            .map(|name| {
+                let generator = SnippetGenerator::create(&searcher, query, self.field(name)?)?;
+                Ok((name.to_string(), generator))
+            })
+            .collect::<Result<Vec<_>, SearchError>>()?;
+
+        // Collecting one more hit than needed tells whether there is a next page.
+        let collector = PageCollector {
+            key_field: self.key_field,
+            after: cursor.cloned(),
+            limit: page_size + 1,
+        };
+        let (total_hits, mut scored_keys) = searcher.search(query, &(Count, collector))?;
+        let next_cursor = if scored_keys.len() > page_size {
+            scored_keys.truncate(page_size);
+            scored_keys.last().map(|(score, key)| SearchCursor {
+                score: *score,
+                key: key.clone(),
+            })
+        } else {
+            None
+        };
+        let (hits, missing) = self.load_hits(scored_keys, &snippet_generators)?;
+        Ok(SearchPage {
+            hits,
+            total_hits,
+            next_cursor,
+            missing,
+        })
+    }
+
+    // Look up the entries of the hits, separating the keys which aren't in the tree anymore.
+    #[allow(clippy::type_complexity)]
+    fn load_hits(
+        &self,
+        scored_keys: Vec<(Score, Vec<u8>)>,
+        snippet_generators: &[(String, SnippetGenerator)],
+    ) -> Result<
+        (
+            Vec<SearchHit<Key<K, V, SerDe>, TreeValue<K, V, SerDe>>>,
+            Vec<Key<K, V, SerDe>>,
+        ),
+        SearchError,
+    >
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let tree_name = self.tree.inner.name();
+        let mut hits = Vec::new();
+        let mut missing = Vec::new();
+        for (score, key) in scored_keys {
+            let key = IVec::from(key);
+            let value = match self.tree.inner.get(&key).map_err(crate::Error::from)? {
+                Some(value) => value,
+                None => {
+                    // Removed from the tree, but the removal isn't indexed yet.
+                    let key = SerDe::DK::deserialize(key.clone())
+                        .map_err(|e| crate::Error::deserialize(&tree_name, &key, e))?;
+                    missing.push(key);
+                    continue;
+                }
+            };
+            let snippets = if snippet_generators.is_empty() {
+                Vec::new()
+            } else {
+                let document = (self.document)(key.clone(), value.clone())?;
+                snippet_generators
+                    .iter()
+                    .map(|(name, generator)| (name.clone(), generator.snippet_from_doc(&document)))
+                    .collect()
+            };
+            let (key, value) = decode_kv::<K, V, SerDe>(&tree_name, key, value)?;
+            hits.push(SearchHit {
+                score,
+                key,
+                value,
+                snippets,
+            });
+        }
+        Ok((hits, missing))
+    }
+
+    fn key_bytes<'d>(&self, doc: &'d Document) -> Result<&'d [u8], SearchError> {
+        match doc.get_first(self.key_field) {
+            Some(Value::Bytes(bytes)) => Ok(bytes),
+            _ => Err(SearchError::DocDoesNotExist),
+        }
+    }
+
+    /// Search for all key value pairs matching a query and collect them with a custom collector.
+    /// The query will be parsed by the `QueryParser` from `tantivy`. All fields of the schema
+    /// will be queried.
+    /// See `tantivy` for what type of collectors can be used.
+    pub fn search_with_collector<C: Collector>(
+        &self,
+        query: &str,
+        collector: &C,
+    ) -> Result<C::Fruit, SearchError> {
+        let query_parser = QueryParser::for_index(
+            &self.index,
+            self.index
+                .schema()
+                .fields()
+                .map(|(field, _)| field)
+                .collect(),
+        );
+        let query = query_parser.parse_query(query)?;
+
+        self.search_with_query_and_collector(&query, collector)
+    }
+
+    /// Search for all key value pairs matching a custom query and collect them with a custom collector.
+    /// See `tantivy` for what type of queries and collectors can be used.
+    pub fn search_with_query_and_collector<C: Collector>(
+        &self,
+        query: &dyn Query,
+        collector: &C,
+    ) -> Result<C::Fruit, SearchError> {
+        let searcher = self.index_reader.searcher();
+        Ok(searcher.search(query, collector)?)
+    }
+
+    /// Retrieve the key value pair corresponding to a `DocAdress`.
+    /// This can for example be used after using `search_with_collector` with
+    /// a custom collector which yields `DocAdress`es.
+    pub fn doc_address_to_kv(
+        &self,
+        doc_addr: DocAddress,
+    ) -> Result<Option<(Key<K, V, SerDe>, TreeValue<K, V, SerDe>)>, SearchError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let doc = self.index_reader.searcher().doc(doc_addr)?;
+        let key_bytes = self.key_bytes(&doc)?;
+        let kv = self.tree.get_kv_from_raw(key_bytes)?;
+        Ok(kv)
+    }
+
+    /// Retrieve the key value pair corresponding to an Iterator over `DocAdress`.
+    /// This can for example be used after using `search_with_collector` with
+    /// a custom collector which yields `DocAdress`es.
+    pub fn doc_adresses_to_kvs<I: Iterator<Item = DocAddress>>(
+        &self,
+        doc_addrs: I,
+    ) -> Result<Vec<Option<(Key<K, V, SerDe>, TreeValue<K, V, SerDe>)>>, SearchError>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let searcher = self.index_reader.searcher();
+        let mut v = Vec::new();
+        for doc_addr in doc_addrs {
+            let doc = searcher.doc(doc_addr)?;
+            let key_bytes = self.key_bytes(&doc)?;
+            let kv = self.tree.get_kv_from_raw(key_bytes)?;
+            v.push(kv);
+        }
+        Ok(v)
+    }
+
+    /// Get the index of the SearchEngine.
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+}
+
+/// When the indexer commits the changes it has indexed, making them searchable.
+///
+/// Changes are committed as soon as either `max_events` changes are pending or
+/// the oldest pending change is older than `max_delay`. Committing is relatively
+/// expensive, so batching many changes improves the indexing throughput.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitPolicy {
+    /// The maximum number of changes to index before committing.
+    pub max_events: usize,
+    /// The maximum time a change waits before it is committed.
+    pub max_delay: Duration,
+}
+
+impl CommitPolicy {
+    /// Commit after every single change.
+    pub fn every_event() -> Self {
+        Self {
+            max_events: 1,
+            max_delay: Duration::from_millis(0),
+        }
+    }
+}
+
+impl Default for CommitPolicy {
+    fn default() -> Self {
+        Self {
+            max_events: 1000,
+            max_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// How long the indexer waits for new events before checking for control messages.
+const INDEXER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long the indexer waits for the events of writes which are still in progress
+/// when shutting down.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_millis(10);
+
+// The watermark stored in the payload of every commit. An index is only
+// known to match the tree if it was committed on shutdown, when nothing was
+// pending anymore, and the tree checksum didn't change since. Commits made
+// while running are marked dirty, as a crash may happen before the events
+// which were still pending get committed.
+const DIRTY_WATERMARK: &str = "typed_sled:dirty";
+
+fn clean_watermark(checksum: u32) -> String {
+    format!("typed_sled:clean:{}", checksum)
+}
+
+enum Control {
+    Sync(mpsc::Sender<Result<(), SearchError>>),
+    Reindex(mpsc::Sender<Result<(), SearchError>>),
+    Shutdown,
+}
+
+// Shared by all clones of a SearchEngine, stops the indexer when the last one is dropped.
+struct IndexerHandle {
+    control: mpsc::Sender<Control>,
+    errors: Mutex<mpsc::Receiver<SearchError>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl IndexerHandle {
+    fn shutdown(&self) -> Result<(), SearchError> {
+        let thread = self
+            .thread
+            .lock()
+            .expect("SearchEngine: poisoned lock")
+            .take();
+        if let Some(thread) = thread {
+            // Fails if the indexer already stopped, joining reports why.
+            let _ = self.control.send(Control::Shutdown);
+            thread.join().map_err(|_| SearchError::IndexerStopped)?;
+        }
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        self.thread
+            .lock()
+            .expect("SearchEngine: poisoned lock")
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+}
+
+impl Drop for IndexerHandle {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+struct Indexer<K, V, SerDe> {
+    tree: Tree<K, V, SerDe>,
+    subscriber: sled::Subscriber,
+    writer: IndexWriter,
+    reader: IndexReader,
+    key_field: Field,
+    document: Arc<DocumentFn>,
+    commit_policy: CommitPolicy,
+    control: mpsc::Receiver<Control>,
+    errors: mpsc::Sender<SearchError>,
+    pending: usize,
+    first_pending: Option<Instant>,
+}
+
+impl<K, V, SerDe> Indexer<K, V, SerDe> {
+    fn run(mut self) {
+        loop {
+            let timeout = match self.first_pending {
+                Some(first_pending) => self
+                    .commit_policy
+                    .max_delay
+                    .saturating_sub(first_pending.elapsed())
+                    .min(INDEXER_POLL_INTERVAL),
+                None => INDEXER_POLL_INTERVAL,
+            };
+            match self.subscriber.next_timeout(timeout) {
+                Ok(event) => self.index(event),
+                // sled also reports a timeout as `Disconnected` while it waits for a
+                // write in progress. Shutting down is signaled through `control`.
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => {}
+            }
+
+            if self.commit_due() {
+                self.commit_and_report();
+            }
+
+            match self.control.try_recv() {
+                Ok(Control::Sync(ack)) => {
+                    self.drain(Duration::from_millis(0));
+                    let res = self.commit().and_then(|()| Ok(self.reader.reload()?));
+                    let _ = ack.send(res);
+                }
+                Ok(Control::Reindex(ack)) => {
+                    // The pending events are covered by the rebuild.
+                    self.drain(Duration::from_millis(0));
+                    let res = self.rebuild();
+                    let _ = ack.send(res);
+                }
+                Ok(Control::Shutdown) | Err(TryRecvError::Disconnected) => {
+                    if let Err(e) = self.shutdown() {
+                        let _ = self.errors.send(e);
+                    }
+                    return;
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+    }
+
+    // Index all events that are available within `timeout`.
+    fn drain(&mut self, timeout: Duration) {
+        while let Ok(event) = self.subscriber.next_timeout(timeout) {
+            self.index(event);
+        }
+    }
+
+    // Replace all documents with the entries of the tree.
+    fn rebuild(&mut self) -> Result<(), SearchError> {
+        self.writer.delete_all_documents()?;
+        for r in self.tree.inner.iter() {
+            let (key, value) = r.map_err(crate::Error::from)?;
+            self.writer.add_document((self.document)(key, value)?)?;
+        }
+        self.pending = 0;
+        self.first_pending = None;
+        self.commit_with_payload(DIRTY_WATERMARK)?;
+        self.reader.reload()?;
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), SearchError> {
+        // The checksum is taken before draining, so every change it covers
+        // gets indexed. Changes made afterwards make it mismatch on the next open.
+        let checksum = self.tree.checksum()?;
+        self.drain(SHUTDOWN_GRACE_PERIOD);
+        self.pending = 0;
+        self.first_pending = None;
+        self.commit_with_payload(&clean_watermark(checksum))
+    }
+
+    fn index(&mut self, event: Event) {
+        if let Err(e) = self.try_index(event) {
+            let _ = self.errors.send(e);
+        }
+        self.pending += 1;
+        self.first_pending.get_or_insert_with(Instant::now);
+    }
+
+    fn try_index(&mut self, event: Event) -> Result<(), SearchError> {
+        match event {
+            Event::Insert { key, value } => {
+                // Replace the document of a previous value of the key.
+                self.writer
+                    .delete_term(Term::from_field_bytes(self.key_field, &key));
+                self.writer.add_document((self.document)(key, value)?)?;
+            }
+            Event::Remove { key } => {
+                self.writer
+                    .delete_term(Term::from_field_bytes(self.key_field, &key));
+            }
+        }
+        Ok(())
+    }
+
+    fn commit_due(&self) -> bool {
+        match self.first_pending {
+            Some(first_pending) => {
+                self.pending >= self.commit_policy.max_events
+                    || first_pending.elapsed() >= self.commit_policy.max_delay
+            }
+            None => false,
+        }
+    }
+
+    fn commit(&mut self) -> Result<(), SearchError> {
+        if self.pending > 0 {
+            self.pending = 0;
+            self.first_pending = None;
+            self.commit_with_payload(DIRTY_WATERMARK)?;
+        }
+        Ok(())
+    }
+
+    fn commit_with_payload(&mut self, payload: &str) -> Result<(), SearchError> {
+        let mut prepared_commit = self.writer.prepare_commit()?;
+        prepared_commit.set_payload(payload);
+        prepared_commit.commit()?;
+        Ok(())
+    }
+
+    fn commit_and_report(&mut self) {
+        if let Err(e) = self.commit() {
+            let _ = self.errors.send(e);
+        }
+    }
+}
+
+/// A key value pair matching a search.
+#[derive(Debug)]
+pub struct SearchHit<K, V> {
+    pub score: Score,
+    pub key: K,
+    pub value: V,
+    /// The snippets of the requested fields, see [SearchEngine::search_page_with_query].
+    pub snippets: Vec<(String, Snippet)>,
+}
+
+impl<K, V> SearchHit<K, V> {
+    /// Returns the snippet of a field if it was requested.
+    pub fn snippet(&self, field: &str) -> Option<&Snippet> {
+        self.snippets
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, snippet)| snippet)
+    }
+}
+
+/// The hits of a search, ordered by descending score.
+///
+/// Hits whose key was removed from the tree, but whose removal isn't
+/// indexed yet, are left out and reported by [missing][Self::missing].
+#[derive(Debug)]
+pub struct SearchResults<K, V> {
+    hits: Vec<SearchHit<K, V>>,
+    missing: Vec<K>,
+}
+
+impl<K, V> SearchResults<K, V> {
+    /// Returns the keys of the hits which aren't in the tree anymore.
+    pub fn missing(&self) -> &[K] {
+        &self.missing
+    }
+
+    /// Returns the hits.
+    pub fn into_hits(self) -> Vec<SearchHit<K, V>> {
+        self.hits
+    }
+}
+
+impl<K, V> std::ops::Deref for SearchResults<K, V> {
+    type Target = Vec<SearchHit<K, V>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.hits
+    }
+}
+
+impl<K, V> std::ops::DerefMut for SearchResults<K, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.hits
+    }
+}
+
+/// An aggregation over all hits of a search, see [SearchEngine::search_with_facets].
+#[derive(Debug, Clone, PartialEq)]
+pub enum FacetRequest {
+    /// Count the hits per direct child of the facet `parent`, e.g. `"/fruits"`, in a facet field.
+    Facet { field: String, parent: String },
+    /// Count the hits per range of a numeric or date fast field.
+    ///
+    /// The ranges include their start and exclude their end and may overlap.
+    /// Dates are given as seconds since the unix epoch. A hit with several
+    /// values in the same range is only counted once.
+    Ranges {
+        field: String,
+        ranges: Vec<Range<f64>>,
+    },
+}
+
+impl FacetRequest {
+    /// Count the hits per direct child of the facet `parent` in a facet field.
+    pub fn facet(field: impl Into<String>, parent: impl Into<String>) -> Self {
+        FacetRequest::Facet {
+            field: field.into(),
+            parent: parent.into(),
+        }
+    }
+
+    /// Count the hits per range of a numeric or date fast field.
+    pub fn ranges(field: impl Into<String>, ranges: impl IntoIterator<Item = Range<f64>>) -> Self {
+        FacetRequest::Ranges {
+            field: field.into(),
+            ranges: ranges.into_iter().collect(),
+        }
+    }
+
+    /// Count the hits per range of `buckets` consecutive ranges of width `interval`, starting at `start`.
+    pub fn histogram(field: impl Into<String>, start: f64, interval: f64, buckets: usize) -> Self {
+        Self::ranges(
+            field,
+            (0..buckets).map(|i| {
+                let from = start + interval * i as f64;
+                from..from + interval
+            }),
+        )
+    }
+}
+
+/// The result of a [FacetRequest].
+#[derive(Debug, Clone, PartialEq)]
+pub enum FacetResult {
+    /// The counts of the child facets with at least one hit, ordered by facet.
+    Facet(Vec<(String, u64)>),
+    /// The counts of all requested ranges, in the requested order.
+    Ranges(Vec<(Range<f64>, u64)>),
+}
+
+/// The hits of a search together with the aggregations, see [SearchEngine::search_with_facets].
+#[derive(Debug)]
+pub struct FacetedResults<K, V> {
+    /// The best hits of the search.
+    pub results: SearchResults<K, V>,
+    /// The number of documents matching the query.
+    pub total_hits: usize,
+    /// The results of the requested aggregations, in the requested order.
+    pub facets: Vec<FacetResult>,
+}
+
+// Returns how to read a numeric or date fast field as f64.
+fn numeric_fast_field(field_type: &FieldType) -> Option<(fn(u64) -> f64, Cardinality)> {
+    let (to_f64, cardinality): (fn(u64) -> f64, _) = match field_type {
+        FieldType::U64(options) => (|v| v as f64, options.get_fastfield_cardinality()),
+        FieldType::I64(options) => (
+            |v| u64_to_i64(v) as f64,
+            options.get_fastfield_cardinality(),
+        ),
+        FieldType::F64(options) => (u64_to_f64, options.get_fastfield_cardinality()),
+        FieldType::Date(options) => (
+            |v| u64_to_i64(v) as f64 / 1_000_000.0,
+            options.get_fastfield_cardinality(),
+        ),
+        _ => return None,
+    };
+    Some((to_f64, cardinality?))
+}
+
+// Counts the hits per range of a numeric fast field.
+struct RangeCollector {
+    field: Field,
+    cardinality: Cardinality,
+    to_f64: fn(u64) -> f64,
+    ranges: Vec<Range<f64>>,
+}
+
+enum NumericColumn {
+    Single(Arc<dyn Column<u64>>),
+    Multi(MultiValuedFastFieldReader<u64>),
+}
+
+struct RangeSegmentCollector {
+    column: NumericColumn,
+    to_f64: fn(u64) -> f64,
+    ranges: Vec<Range<f64>>,
+    counts: Vec<u64>,
+    values: Vec<u64>,
+    matched: Vec<bool>,
+}
+
+impl Collector for RangeCollector {
+    type Fruit = Vec<u64>;
+    type Child = RangeSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_local_id: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        // The raw values are mapped back depending on the field type.
+        let column = match self.cardinality {
+            Cardinality::SingleValue => {
+                NumericColumn::Single(segment.fast_fields().u64_lenient(self.field)?)
+            }
+            Cardinality::MultiValues => {
+                NumericColumn::Multi(segment.fast_fields().u64s_lenient(self.field)?)
+            }
+        };
+        Ok(RangeSegmentCollector {
+            column,
+            to_f64: self.to_f64,
+            ranges: self.ranges.clone(),
+            counts: vec![0; self.ranges.len()],
+            values: Vec::new(),
+            matched: vec![false; self.ranges.len()],
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(&self, segment_fruits: Vec<Vec<u64>>) -> tantivy::Result<Self::Fruit> {
+        let mut counts = vec![0; self.ranges.len()];
+        for fruit in segment_fruits {
+            for (count, segment_count) in counts.iter_mut().zip(fruit) {
+                *count += segment_count;
+            }
+        }
+        Ok(counts)
+    }
+}
+
+impl SegmentCollector for RangeSegmentCollector {
+    type Fruit = Vec<u64>;
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        self.values.clear();
+        match &self.column {
+            NumericColumn::Single(column) => self.values.push(column.get_val(doc)),
+            NumericColumn::Multi(reader) => reader.get_vals(doc, &mut self.values),
+        }
+        self.matched.iter_mut().for_each(|matched| *matched = false);
+        for value in &self.values {
+            let value = (self.to_f64)(*value);
+            for (range, matched) in self.ranges.iter().zip(&mut self.matched) {
+                *matched |= range.contains(&value);
+            }
+        }
+        for (count, matched) in self.counts.iter_mut().zip(&self.matched) {
+            *count += *matched as u64;
+        }
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        self.counts
+    }
+}
+
+/// A page of hits, see [SearchEngine::search_page].
+#[derive(Debug)]
+pub struct SearchPage<K, V> {
+    /// The hits of this page, ordered by descending score and then by the
+    /// serialized bytes of their keys.
+    pub hits: Vec<SearchHit<K, V>>,
+    /// The number of documents matching the query across all pages.
+    pub total_hits: usize,
+    /// The cursor of the next page, `None` if this is the last page.
+    pub next_cursor: Option<SearchCursor>,
+    /// The keys of the hits on this page which aren't in the tree anymore.
+    /// They are left out of `hits`, so a page can have less hits than requested.
+    pub missing: Vec<K>,
+}
+
+/// The position after the last hit of a [SearchPage].
+///
+/// A cursor can be serialized to be handed out to clients.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchCursor {
+    score: Score,
+    key: Vec<u8>,
+}
+
+// Collects the best `limit` hits after a cursor, identified by their key bytes.
+struct PageCollector {
+    key_field: Field,
+    after: Option<SearchCursor>,
+    limit: usize,
+}
+
+struct PageSegmentCollector {
+    keys: BytesFastFieldReader,
+    after: Option<SearchCursor>,
+    limit: usize,
+    // A max heap with the worst hit on top.
+    best: BinaryHeap<RankedHit>,
+}
+
+// Ordered from the best to the worst hit.
+struct RankedHit {
+    score: Score,
+    key: Vec<u8>,
+}
+
+impl Ord for RankedHit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .score
+            .total_cmp(&self.score)
+            .then_with(|| self.key.cmp(&other.key))
+    }
+}
+
+impl PartialOrd for RankedHit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankedHit {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedHit {}
+
+impl Collector for PageCollector {
+    type Fruit = Vec<(Score, Vec<u8>)>;
+    type Child = PageSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_local_id: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        Ok(PageSegmentCollector {
+            keys: segment.fast_fields().bytes(self.key_field)?,
+            after: self.after.clone(),
+            limit: self.limit,
+            best: BinaryHeap::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<Vec<(Score, Vec<u8>)>>,
+    ) -> tantivy::Result<Self::Fruit> {
+        let mut hits: Vec<RankedHit> = segment_fruits
+            .into_iter()
+            .flatten()
+            .map(|(score, key)| RankedHit { score, key })
+            .collect();
+        hits.sort();
+        hits.truncate(self.limit);
+        Ok(hits.into_iter().map(|hit| (hit.score, hit.key)).collect())
+    }
+}
+
+impl SegmentCollector for PageSegmentCollector {
+    type Fruit = Vec<(Score, Vec<u8>)>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let key = self.keys.get_bytes(doc);
+        if let Some(after) = &self.after {
+            let is_after = score < after.score || (score == after.score && key > &after.key[..]);
+            if !is_after {
+                return;
+            }
+        }
+        if self.best.len() == self.limit {
+            match self.best.peek() {
+                Some(worst)
+                    if worst.score < score || (worst.score == score && key < &worst.key[..]) =>
+                {
+                    self.best.pop();
+                }
+                _ => return,
+            }
+        }
+        self.best.push(RankedHit {
+            score,
+            key: key.to_vec(),
+        });
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        self.best
+            .into_sorted_vec()
+            .into_iter()
+            .map(|hit| (hit.score, hit.key))
+            .collect()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SearchError {
+    #[error("Tree error: {0}")]
+    Tree(#[from] crate::Error),
+    #[error("Search error: {0}")]
+    Search(#[from] tantivy::TantivyError),
+    #[error("Error while parsing query: {0}")]
+    QueryParserError(#[from] tantivy::query::QueryParserError),
+    #[error("Document Address doesn't exist")]
+    DocDoesNotExist,
+    #[error("The indexer has been stopped")]
+    IndexerStopped,
+    #[error("The schema has no field named {0:?}")]
+    UnknownField(String),
+    #[error("The field {0:?} can't be used for this aggregation")]
+    UnsupportedField(String),
+    #[error("Invalid facet: {0}")]
+    InvalidFacet(#[from] tantivy::schema::FacetParseError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_serde::serialize::{BincodeSerDe, BincodeSerDeLazy};
+    use tantivy::{
+        doc,
+        schema::{FacetOptions, Schema, FAST, INDEXED, TEXT},
+    };
+
+    #[test]
+    fn test_lazy_values() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, String, BincodeSerDeLazy>::open(&db, "test_tree");
+        tree.insert(&0, &"apple".to_owned()).unwrap();
+
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let search_engine = SearchEngine::new_temp(
+            &tree,
+            schema_builder,
+            move |_k, v: &serialize::Lazy<String>| doc!(text => v.deserialize()),
+        )
+        .unwrap();
+        tree.insert(&1, &"apple banana".to_owned()).unwrap();
+        tree.insert(&0, &"cherry".to_owned()).unwrap();
+        search_engine.wait_until_indexed().unwrap();
+
+        let results = search_engine.search("apple", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        let (key, value) = (&results[0].key, &results[0].value);
+        assert_eq!(key.deserialize(), 1);
+        assert_eq!(value.deserialize(), "apple banana");
+        assert_eq!(search_engine.search("cherry", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_commit_policy() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, String, BincodeSerDe>::open(&db, "test_tree");
+
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let search_engine = SearchEngine::new_with_options::<&str, _>(
+            None,
+            &tree,
+            schema_builder,
+            CommitPolicy {
+                max_events: 3,
+                max_delay: Duration::from_secs(3600),
+            },
+            move |_k, v| doc!(text => v.to_owned()),
+        )
+        .unwrap();
+
+        tree.insert(&0, &"apple".to_owned()).unwrap();
+        tree.insert(&1, &"apple".to_owned()).unwrap();
+        // Nothing is committed before `max_events` changes are pending.
+        thread::sleep(Duration::from_millis(200));
+        search_engine.index_reader.reload().unwrap();
+        assert!(search_engine.search("apple", 10).unwrap().is_empty());
+
+        tree.insert(&2, &"apple".to_owned()).unwrap();
+        let start = Instant::now();
+        loop {
+            search_engine.index_reader.reload().unwrap();
+            if search_engine.search("apple", 10).unwrap().len() == 3 {
+                break;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10), "not committed");
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(search_engine.indexer_errors().is_empty());
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, String, BincodeSerDe>::open(&db, "test_tree");
+
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let search_engine = SearchEngine::new_temp(
+            &tree,
+            schema_builder,
+            move |_k, v| doc!(text => v.to_owned()),
+        )
+        .unwrap();
+        let clone = search_engine.clone();
+        assert!(clone.is_indexer_running());
+
+        tree.insert(&0, &"apple".to_owned()).unwrap();
+        // Pending changes are committed on shutdown.
+        search_engine.shutdown().unwrap();
+        assert!(!clone.is_indexer_running());
+        search_engine.index_reader.reload().unwrap();
+        assert_eq!(clone.search("apple", 10).unwrap().len(), 1);
+
+        tree.insert(&1, &"apple".to_owned()).unwrap();
+        assert!(matches!(
+            clone.wait_until_indexed(),
+            Err(SearchError::IndexerStopped)
+        ));
+        // Shutting down twice is fine.
+        clone.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_detect_stale_index() {
+        let path = std::env::temp_dir().join(format!(
+            "typed_sled_test_detect_stale_index_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, String, BincodeSerDe>::open(&db, "test_tree");
+        let open = || {
+            let mut schema_builder = Schema::builder();
+            let text = schema_builder.add_text_field("text", TEXT);
+            SearchEngine::new(
+                &path,
+                &tree,
+                schema_builder,
+                move |_k, v| doc!(text => v.to_owned()),
+            )
+            .unwrap()
+        };
+
+        tree.insert(&0, &"apple".to_owned()).unwrap();
+        let search_engine = open();
+        tree.insert(&1, &"apple".to_owned()).unwrap();
+        drop(search_engine);
+
+        // Reopening an index that matches the tree doesn't duplicate documents.
+        let search_engine = open();
+        assert_eq!(search_engine.search("apple", 10).unwrap().len(), 2);
+        search_engine.reindex().unwrap();
+        assert_eq!(search_engine.search("apple", 10).unwrap().len(), 2);
+        drop(search_engine);
+
+        // Changes made while the search engine wasn't running are picked up.
+        tree.insert(&2, &"apple".to_owned()).unwrap();
+        tree.remove(&0).unwrap();
+        let search_engine = open();
+        let results = search_engine.search("apple", 10).unwrap();
+        let mut keys: Vec<u64> = results.iter().map(|r| r.key).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![1, 2]);
+        drop(search_engine);
+
+        // An index which wasn't shut down cleanly is rebuilt.
+        let index = Index::open_in_dir(&path).unwrap();
+        let mut writer: IndexWriter = index.writer(5_000_000).unwrap();
+        writer.delete_all_documents().unwrap();
+        let mut prepared_commit = writer.prepare_commit().unwrap();
+        prepared_commit.set_payload(DIRTY_WATERMARK);
+        prepared_commit.commit().unwrap();
+        drop(writer);
+        let search_engine = open();
+        assert_eq!(search_engine.search("apple", 10).unwrap().len(), 2);
+        drop(search_engine);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_search_page() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, String, BincodeSerDe>::open(&db, "test_tree");
+
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let search_engine = SearchEngine::new_temp(
+            &tree,
+            schema_builder,
+            move |_k, v| doc!(text => v.to_owned()),
+        )
+        .unwrap();
+
+        for key in 0..5 {
+            tree.insert(&key, &"apple".to_owned()).unwrap();
+        }
+        tree.insert(&5, &"banana".to_owned()).unwrap();
+        search_engine.wait_until_indexed().unwrap();
+
+        // Hits with the same score are ordered by their key bytes.
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = search_engine
+                .search_page("apple", 2, cursor.as_ref())
+                .unwrap();
+            assert_eq!(page.total_hits, 5);
+            assert!(page.missing.is_empty());
+            keys.push(page.hits.iter().map(|hit| hit.key).collect::<Vec<_>>());
+            match page.next_cursor {
+                // Cursors can be handed out to clients.
+                Some(next_cursor) => {
+                    let bytes = bincode::serialize(&next_cursor).unwrap();
+                    cursor = Some(bincode::deserialize(&bytes).unwrap());
+                }
+                None => break,
+            }
+        }
+        assert_eq!(keys, vec![vec![0, 1], vec![2, 3], vec![4]]);
+
+        let page = search_engine.search_page("apple", 5, None).unwrap();
+        assert_eq!(page.hits.len(), 5);
+        assert!(page.next_cursor.is_none());
+        let page = search_engine.search_page("cherry", 5, None).unwrap();
+        assert_eq!(page.total_hits, 0);
+        assert!(page.hits.is_empty());
+    }
+
+    #[test]
+    fn test_search_page_orders_by_key_bytes() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, String, BincodeSerDe>::open(&db, "test_tree");
+
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let search_engine = SearchEngine::new_temp(
+            &tree,
+            schema_builder,
+            move |_k, v| doc!(text => v.to_owned()),
+        )
+        .unwrap();
+        for key in [1, 2, 256, 257] {
+            tree.insert(&key, &"apple".to_owned()).unwrap();
+        }
+        search_engine.wait_until_indexed().unwrap();
+
+        // Bincode encodes integers in little endian, so 256 comes before 1.
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = search_engine
+                .search_page("apple", 1, cursor.as_ref())
+                .unwrap();
+            keys.extend(page.hits.iter().map(|hit| hit.key));
+            match page.next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => break,
+            }
+        }
+        assert_eq!(keys, vec![256, 1, 257, 2]);
+    }
+
+    #[test]
+    fn test_search_page_with_different_scores() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, String, BincodeSerDe>::open(&db, "test_tree");
+
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let search_engine = SearchEngine::new_temp(
+            &tree,
+            schema_builder,
+            move |_k, v| doc!(text => v.to_owned()),
+        )
+        .unwrap();
+
+        // Documents with more occurrences of "apple" score higher.
+        for key in 0..10 {
+            tree.insert(&key, &"apple ".repeat(key as usize % 4 + 1))
+                .unwrap();
+        }
+        search_engine.wait_until_indexed().unwrap();
+
+        let expected = vec![3, 7, 2, 6, 1, 5, 9, 0, 4, 8];
+        let results = search_engine.search("apple", 10).unwrap();
+        let mut scores: Vec<_> = results.iter().map(|hit| (hit.score, hit.key)).collect();
+        scores.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+        assert_eq!(
+            scores.iter().map(|(_, key)| *key).collect::<Vec<_>>(),
+            expected
+        );
+
+        // Pages match the full search across page boundaries and cursors.
+        for page_size in 1..=4 {
+            let mut keys = Vec::new();
+            let mut cursor = None;
+            loop {
+                let page = search_engine
+                    .search_page("apple", page_size, cursor.as_ref())
+                    .unwrap();
+                assert!(page.hits.len() <= page_size);
+                keys.extend(page.hits.iter().map(|hit| hit.key));
+                match page.next_cursor {
+                    Some(next_cursor) => cursor = Some(next_cursor),
+                    None => break,
+                }
+            }
+            assert_eq!(keys, expected, "page size {}", page_size);
+        }
+    }
+
+    #[test]
+    fn test_snippets() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, String, BincodeSerDe>::open(&db, "test_tree");
+
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let search_engine = SearchEngine::new_temp(
+            &tree,
+            schema_builder,
+            move |_k, v| doc!(text => v.to_owned()),
+        )
+        .unwrap();
+
+        tree.insert(&0, &"the quick brown fox".to_owned()).unwrap();
+        search_engine.wait_until_indexed().unwrap();
+
+        let query = search_engine.parse_query("fox").unwrap();
+        let page = search_engine
+            .search_page_with_query(&*query, 10, None, &["text"])
+            .unwrap();
+        let snippet = page.hits[0].snippet("text").unwrap();
+        assert_eq!(snippet.to_html(), "the quick brown <b>fox</b>");
+        assert!(page.hits[0].snippet("title").is_none());
+
+        assert!(matches!(
+            search_engine.search_page_with_query(&*query, 10, None, &["title"]),
+            Err(SearchError::UnknownField(field)) if field == "title"
+        ));
+    }
+
+    #[test]
+    fn test_missing_keys() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, String, BincodeSerDe>::open(&db, "test_tree");
+
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let search_engine = SearchEngine::new_temp(
+            &tree,
+            schema_builder,
+            move |_k, v| doc!(text => v.to_owned()),
+        )
+        .unwrap();
+
+        tree.insert(&0, &"apple".to_owned()).unwrap();
+        tree.insert(&1, &"apple".to_owned()).unwrap();
+        search_engine.shutdown().unwrap();
+        search_engine.index_reader.reload().unwrap();
+        // The removal isn't indexed anymore.
+        tree.remove(&0).unwrap();
+
+        let results = search_engine.search("apple", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].key, 1);
+        assert_eq!(results.missing(), &[0]);
+
+        let page = search_engine.search_page("apple", 10, None).unwrap();
+        assert_eq!(page.total_hits, 2);
+        assert_eq!(page.hits.len(), 1);
+        assert_eq!(page.missing, vec![0]);
+    }
+
+    #[test]
+    fn test_search_with_facets() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, (String, String, u64), BincodeSerDe>::open(&db, "test_tree");
+
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let category = schema_builder.add_facet_field("category", FacetOptions::default());
+        let price = schema_builder.add_u64_field("price", INDEXED | FAST);
+        let search_engine = SearchEngine::new_temp(&tree, schema_builder, move |_k, v| {
+            doc!(
+                text => v.0.to_owned(),
+                category => Facet::from_text(&v.1).unwrap(),
+                price => v.2
+            )
+        })
+        .unwrap();
+
+        let entries = [
+            ("red apple", "/fruits/apples", 3),
+            ("green apple", "/fruits/apples", 12),
+            ("apple pie", "/cakes", 25),
+            ("banana", "/fruits/bananas", 5),
+        ];
+        for (key, &(name, category, price)) in entries.iter().enumerate() {
+            let value = (name.to_owned(), category.to_owned(), price);
+            tree.insert(&(key as u64), &value).unwrap();
+        }
+        search_engine.wait_until_indexed().unwrap();
+
+        let results = search_engine
+            .search_with_facets(
+                "apple",
+                1,
+                &[
+                    FacetRequest::facet("category", "/"),
+                    FacetRequest::facet("category", "/fruits"),
+                    FacetRequest::histogram("price", 0.0, 10.0, 3),
+                ],
+            )
+            .unwrap();
+        // The aggregations include the hits beyond the limit.
+        assert_eq!(results.results.len(), 1);
+        assert_eq!(results.total_hits, 3);
+        assert_eq!(
+            results.facets,
+            vec![
+                FacetResult::Facet(vec![("/cakes".to_owned(), 1), ("/fruits".to_owned(), 2)]),
+                FacetResult::Facet(vec![("/fruits/apples".to_owned(), 2)]),
+                FacetResult::Ranges(vec![(0.0..10.0, 1), (10.0..20.0, 1), (20.0..30.0, 1)]),
+            ]
+        );
+
+        assert!(matches!(
+            search_engine.search_with_facets("apple", 1, &[FacetRequest::facet("text", "/")]),
+            Err(SearchError::UnsupportedField(field)) if field == "text"
+        ));
+        assert!(matches!(
+            search_engine.search_with_facets(
+                "apple",
+                1,
+                &[FacetRequest::facet("category", "fruits")]
+            ),
+            Err(SearchError::InvalidFacet(_))
+        ));
+    }
+}
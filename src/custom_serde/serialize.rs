@@ -9,6 +9,7 @@
 //! to do just that.
 
 // use rkyv::{archived_root, ser::Serializer as _, AlignedVec, Archive, Archived};
+use crate::SerdeError;
 use serde::de::DeserializeOwned;
 use std::convert::AsRef;
 
@@ -28,6 +29,9 @@ pub trait SerDe<K, V> {
     type DK: Deserializer<K>;
     /// Value Deserializer
     type DV: Deserializer<V>;
+    /// Whether the keys serialized by `SK` sort like the keys themselves, so that
+    /// e.g. the largest key can be found without iterating over all keys.
+    const ORDERED_KEYS: bool = false;
 }
 
 pub type Key<K, V, SD> = <<SD as SerDe<K, V>>::DK as Deserializer<K>>::DeserializedValue;
@@ -36,13 +40,13 @@ pub type Value<K, V, SD> = <<SD as SerDe<K, V>>::DV as Deserializer<V>>::Deseria
 pub trait Serializer<T> {
     type Bytes: AsRef<[u8]>;
 
-    fn serialize(value: &T) -> Self::Bytes;
+    fn serialize(value: &T) -> Result<Self::Bytes, SerdeError>;
 }
 
 pub trait Deserializer<T> {
     type DeserializedValue;
 
-    fn deserialize(bytes: sled::IVec) -> Self::DeserializedValue;
+    fn deserialize(bytes: sled::IVec) -> Result<Self::DeserializedValue, SerdeError>;
 }
 
 /// (De)serializer using bincode.
@@ -62,6 +66,17 @@ pub struct BincodeSerializer;
 pub struct BincodeDeserializer;
 pub struct BincodeDeserializerLazy;
 
+/// (De)serializer using the order-preserving [key_codec][crate::key_codec] for keys
+/// and bincode for values. This is the (de)serialization used by [crate::Tree].
+#[derive(Debug)]
+pub struct OrderedSerDe;
+/// Serializer whose output sorts like the `Ord` implementation of the serialized type.
+/// See [key_codec][crate::key_codec].
+#[derive(Debug)]
+pub struct OrderedSerializer;
+#[derive(Debug)]
+pub struct OrderedDeserializer;
+
 impl<K: serde::Serialize + DeserializeOwned, V: serde::Serialize + DeserializeOwned> SerDe<K, V>
     for BincodeSerDe
 {
@@ -96,28 +111,53 @@ impl<K: serde::Serialize + DeserializeOwned, V: serde::Serialize> SerDe<K, V>
     type DV = BincodeDeserializerLazy;
 }
 
+impl<K: serde::Serialize + DeserializeOwned, V: serde::Serialize + DeserializeOwned> SerDe<K, V>
+    for OrderedSerDe
+{
+    type SK = OrderedSerializer;
+    type SV = BincodeSerializer;
+    type DK = OrderedDeserializer;
+    type DV = BincodeDeserializer;
+    const ORDERED_KEYS: bool = true;
+}
+
 impl<T: serde::Serialize> Serializer<T> for BincodeSerializer {
     type Bytes = Vec<u8>;
 
-    fn serialize(value: &T) -> Self::Bytes {
-        bincode::serialize(value).expect("serialization failed, did the type serialized change?")
+    fn serialize(value: &T) -> Result<Self::Bytes, SerdeError> {
+        Ok(bincode::serialize(value)?)
     }
 }
 
 impl<T: serde::de::DeserializeOwned> Deserializer<T> for BincodeDeserializer {
     type DeserializedValue = T;
 
-    fn deserialize(bytes: sled::IVec) -> Self::DeserializedValue {
-        bincode::deserialize(&bytes)
-            .expect("deserialization failed, did the type serialized change?")
+    fn deserialize(bytes: sled::IVec) -> Result<Self::DeserializedValue, SerdeError> {
+        Ok(bincode::deserialize(&bytes)?)
+    }
+}
+
+impl<T: serde::Serialize> Serializer<T> for OrderedSerializer {
+    type Bytes = Vec<u8>;
+
+    fn serialize(value: &T) -> Result<Self::Bytes, SerdeError> {
+        Ok(crate::serialize_key(value)?)
+    }
+}
+
+impl<T: serde::de::DeserializeOwned> Deserializer<T> for OrderedDeserializer {
+    type DeserializedValue = T;
+
+    fn deserialize(bytes: sled::IVec) -> Result<Self::DeserializedValue, SerdeError> {
+        Ok(crate::deserialize_key(&bytes)?)
     }
 }
 
 impl<T> Deserializer<T> for BincodeDeserializerLazy {
     type DeserializedValue = Lazy<T>;
 
-    fn deserialize(bytes: sled::IVec) -> Self::DeserializedValue {
-        Lazy::new(bytes)
+    fn deserialize(bytes: sled::IVec) -> Result<Self::DeserializedValue, SerdeError> {
+        Ok(Lazy::new(bytes))
     }
 }
 
@@ -136,13 +176,27 @@ impl<T> Lazy<T> {
 }
 
 impl<T> Lazy<T> {
+    /// Deserialize the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value can't be deserialized. Use
+    /// [try_deserialize][Lazy::try_deserialize] to handle the error instead.
     pub fn deserialize<'de>(&'de self) -> T
     where
         T: serde::Deserialize<'de>,
     {
-        bincode::deserialize(&self.v)
+        self.try_deserialize()
             .expect("deserialization failed, did the type serialized change?")
     }
+
+    /// Deserialize the value, returning an error if the deserialization failed.
+    pub fn try_deserialize<'de>(&'de self) -> bincode::Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        bincode::deserialize(&self.v)
+    }
 }
 
 #[test]
@@ -0,0 +1,466 @@
+//! A typed wrapper around [sled::Db] that keeps track of the types of its trees.
+//!
+//! Every tree opened through [Db] is recorded in a reserved registry tree
+//! together with the type names of its keys and values and the codec used
+//! to (de)serialize them. Opening an existing tree with different types
+//! fails with [Error::TypeMismatch] instead of silently
+//! misinterpreting the stored bytes. The registry also records the sled tree
+//! holding the entries of every tree, which lets the `convert` modules replace
+//! a tree with its converted version atomically.
+//!
+//! Tree names containing `__typed_sled` are reserved for the internal trees of
+//! this crate, like the registry or the trees storing the expiry times of a `TtlTree`,
+//! and can't be opened, dropped or renamed through a [Db].
+//!
+//! # Schema versions
+//!
+//! The check compares the names returned by [std::any::type_name], so it can't
+//! notice that the definition of a type changed, e.g. that a field was added to
+//! the value type. Open trees with [Db::open_tree_with_schema] and increase the
+//! schema version whenever the definition of a stored type changes.
+//!
+//! The type names aren't guaranteed to be stable across compiler versions. If they
+//! change after a toolchain upgrade, update the registered types with
+//! [Db::reregister_tree].
+//!
+//! # Example
+//! ```
+//! use typed_sled::{Db, Error};
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     // Creating a temporary sled database.
+//!     // If you want to persist the data use sled::open instead.
+//!     let db = Db::new(sled::Config::new().temporary(true).open()?)?;
+//!
+//!     let tree = db.open_tree::<String, u64>("unique_id")?;
+//!     tree.insert(&"some_key".to_owned(), &10)?;
+//!
+//!     // Opening the tree again with the same types works.
+//!     let same_tree = db.open_tree::<String, u64>("unique_id")?;
+//!     assert_eq!(same_tree.get(&"some_key".to_owned())?, Some(10));
+//!
+//!     // Opening it with different types doesn't.
+//!     assert!(matches!(
+//!         db.open_tree::<u64, String>("unique_id"),
+//!         Err(Error::TypeMismatch { .. })
+//!     ));
+//!     Ok(())
+//! }
+//! ```
+use crate::custom_serde::{self, serialize};
+use crate::{Error, Result, Tree, KV};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use std::any::type_name;
+
+/// Name of the tree storing the [TreeMeta] of all typed trees.
+pub const REGISTRY_TREE: &str = "__typed_sled_registry";
+
+/// Tree names containing this are reserved for the internal trees of typed_sled.
+const RESERVED_NAME: &str = "__typed_sled";
+
+/// The number of entries copied per batch by [Db::rename_tree].
+const RENAME_BATCH_SIZE: usize = 1024;
+
+/// The registry entry of a tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Registration {
+    pub(crate) meta: TreeMeta,
+    /// The name of the sled tree holding the entries, which differs from the
+    /// name of the tree after it was converted.
+    pub(crate) physical: String,
+}
+
+/// Returns a new, unique name for the sled tree holding the entries of a typed tree.
+#[cfg(feature = "convert")]
+pub(crate) fn generate_physical_name(db: &sled::Db) -> Result<String> {
+    Ok(format!("{}_tree_{}", RESERVED_NAME, db.generate_id()?))
+}
+
+/// Type information stored for every tree opened through a [Db].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TreeMeta {
+    /// The type name of the keys.
+    pub key_type: String,
+    /// The type name of the values.
+    pub value_type: String,
+    /// The type name of the `SerDe` used to (de)serialize keys and values.
+    pub codec: String,
+    /// The version of the definitions of the key and value types, see the
+    /// [module documentation][self].
+    pub schema_version: u32,
+}
+
+impl TreeMeta {
+    /// Create the metadata for a tree with the given key, value and `SerDe` types
+    /// and schema version 0.
+    pub fn new<K, V, SerDe>() -> Self {
+        Self {
+            key_type: type_name::<K>().to_owned(),
+            value_type: type_name::<V>().to_owned(),
+            codec: type_name::<SerDe>().to_owned(),
+            schema_version: 0,
+        }
+    }
+
+    /// Set the schema version.
+    pub fn with_schema_version(mut self, schema_version: u32) -> Self {
+        self.schema_version = schema_version;
+        self
+    }
+
+    /// Returns `true` if a tree described by `self` can be opened as a tree described by `other`.
+    pub fn is_compatible(&self, other: &TreeMeta) -> bool {
+        self == other
+    }
+}
+
+impl fmt::Display for TreeMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Tree<{}, {}> ({}, schema version {})",
+            self.key_type, self.value_type, self.codec, self.schema_version
+        )
+    }
+}
+
+/// A [sled::Db] which records the key and value types of the trees opened through it.
+#[derive(Clone, Debug)]
+pub struct Db {
+    inner: sled::Db,
+    registry: sled::Tree,
+}
+
+impl Db {
+    /// Wrap a `sled::Db`, opening the registry tree.
+    pub fn new(db: sled::Db) -> Result<Self> {
+        let registry = db.open_tree(REGISTRY_TREE)?;
+        Ok(Self {
+            inner: db,
+            registry,
+        })
+    }
+
+    /// Returns the underlying `sled::Db`.
+    pub fn inner(&self) -> &sled::Db {
+        &self.inner
+    }
+
+    /// Open or create a [Tree] with the given name.
+    ///
+    /// Returns [Error::TypeMismatch] if the tree was registered with
+    /// different key or value types.
+    pub fn open_tree<K: KV, V: KV>(&self, name: &str) -> Result<Tree<K, V>> {
+        self.open_tree_with_schema(name, 0)
+    }
+
+    /// Open or create a [Tree] like [open_tree][Db::open_tree], whose key and value
+    /// types have the given schema version, see the [module documentation][self].
+    pub fn open_tree_with_schema<K: KV, V: KV>(
+        &self,
+        name: &str,
+        schema_version: u32,
+    ) -> Result<Tree<K, V>> {
+        // Tree<K, V> uses the same encoding as custom_serde::Tree<K, V, OrderedSerDe>.
+        let meta = TreeMeta::new::<K, V, serialize::OrderedSerDe>();
+        let physical = self.register(name, meta.with_schema_version(schema_version))?;
+        Tree::try_open(&self.inner, physical)
+    }
+
+    /// Open or create a [custom_serde::Tree] with the given name.
+    ///
+    /// Returns [Error::TypeMismatch] if the tree was registered with
+    /// different key or value types or a different `SerDe`.
+    pub fn open_custom_tree<K, V, SerDe>(
+        &self,
+        name: &str,
+    ) -> Result<custom_serde::Tree<K, V, SerDe>>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        self.open_custom_tree_with_schema(name, 0)
+    }
+
+    /// Open or create a [custom_serde::Tree] like [open_custom_tree][Db::open_custom_tree],
+    /// whose key and value types have the given schema version, see the
+    /// [module documentation][self].
+    pub fn open_custom_tree_with_schema<K, V, SerDe>(
+        &self,
+        name: &str,
+        schema_version: u32,
+    ) -> Result<custom_serde::Tree<K, V, SerDe>>
+    where
+        SerDe: serialize::SerDe<K, V>,
+    {
+        let meta = TreeMeta::new::<K, V, SerDe>().with_schema_version(schema_version);
+        let physical = self.register(name, meta)?;
+        custom_serde::Tree::try_open(&self.inner, physical)
+    }
+
+    /// Replace the registered types of a tree, e.g. after its entries were migrated
+    /// or if the type names changed with a new compiler version.
+    pub fn reregister_tree(&self, name: &str, meta: TreeMeta) -> Result<()> {
+        check_name(name)?;
+        loop {
+            let current = self.registry.get(name)?;
+            let physical = match &current {
+                Some(bytes) => self.decode_registration(name.as_bytes(), bytes)?.physical,
+                None => name.to_owned(),
+            };
+            let registration = Registration {
+                meta: meta.clone(),
+                physical,
+            };
+            let bytes = crate::encode_value(&registration)?;
+            // Compare and swap, so a concurrent conversion isn't undone.
+            if self
+                .registry
+                .compare_and_swap(name, current, Some(bytes))?
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns the metadata of the tree with the given name if it is registered.
+    pub fn tree_meta(&self, name: &str) -> Result<Option<TreeMeta>> {
+        self.registry
+            .get(name)?
+            .map(|bytes| Ok(self.decode_registration(name.as_bytes(), &bytes)?.meta))
+            .transpose()
+    }
+
+    /// Returns the names and metadata of all registered trees.
+    pub fn trees(&self) -> Result<Vec<(String, TreeMeta)>> {
+        self.registry
+            .iter()
+            .map(|res| {
+                let (name, bytes) = res?;
+                let registration = self.decode_registration(&name, &bytes)?;
+                Ok((
+                    String::from_utf8_lossy(&name).into_owned(),
+                    registration.meta,
+                ))
+            })
+            .collect()
+    }
+
+    /// Drop a tree and remove it from the registry.
+    /// Returns `true` if the tree existed.
+    pub fn drop_tree(&self, name: &str) -> Result<bool> {
+        check_name(name)?;
+        let dropped = self.inner.drop_tree(self.physical_name(name)?)?;
+        let registered = self.registry.remove(name)?.is_some();
+        Ok(dropped || registered)
+    }
+
+    /// Rename a tree, keeping its registered types.
+    ///
+    /// sled has no native rename, so the entries are copied into the new
+    /// tree in batches before the old one is dropped. This is not atomic:
+    /// while copying, the new tree is only partially filled, and if the process
+    /// crashes in between, both trees might exist afterwards, with the new one
+    /// only partially filled and not registered yet.
+    /// Fails if a tree named `to` already contains entries.
+    pub fn rename_tree(&self, from: &str, to: &str) -> Result<()> {
+        check_name(from)?;
+        check_name(to)?;
+        if from == to {
+            return Ok(());
+        }
+        let meta = self.tree_meta(from)?;
+        let to_meta = self.tree_meta(to)?;
+        let source = self.inner.open_tree(self.physical_name(from)?)?;
+        let target = self.inner.open_tree(to)?;
+        if !target.is_empty() || to_meta.is_some() {
+            return Err(sled::Error::Unsupported(format!(
+                "can't rename tree {:?} to {:?}: the target tree already exists",
+                from, to
+            ))
+            .into());
+        }
+
+        let mut batch = sled::Batch::default();
+        let mut batch_len = 0;
+        for res in source.iter() {
+            let (key, value) = res?;
+            batch.insert(key, value);
+            batch_len += 1;
+            if batch_len == RENAME_BATCH_SIZE {
+                target.apply_batch(std::mem::take(&mut batch))?;
+                batch_len = 0;
+            }
+        }
+        target.apply_batch(batch)?;
+
+        if let Some(meta) = meta {
+            let registration = Registration {
+                meta,
+                physical: to.to_owned(),
+            };
+            self.registry
+                .insert(to, crate::encode_value(&registration)?)?;
+        }
+        target.flush()?;
+        self.registry.flush()?;
+        self.drop_tree(from)?;
+        Ok(())
+    }
+
+    /// Flush the whole database, see [sled::Tree::flush].
+    pub fn flush(&self) -> Result<usize> {
+        Ok(self.inner.flush()?)
+    }
+
+    /// Register a tree, returning the name of the sled tree holding its entries.
+    fn register(&self, name: &str, meta: TreeMeta) -> Result<String> {
+        check_name(name)?;
+        let registration = Registration {
+            meta,
+            physical: name.to_owned(),
+        };
+        let bytes = crate::encode_value(&registration)?;
+        // Compare and swap, so only one of two concurrent registrations can win.
+        match self
+            .registry
+            .compare_and_swap(name, None as Option<&[u8]>, Some(bytes))?
+        {
+            Ok(()) => Ok(registration.physical),
+            Err(cas_err) => {
+                let stored = match cas_err.current {
+                    Some(current) => self.decode_registration(name.as_bytes(), &current)?,
+                    None => return self.register(name, registration.meta),
+                };
+                if stored.meta.is_compatible(&registration.meta) {
+                    Ok(stored.physical)
+                } else {
+                    Err(Error::TypeMismatch {
+                        tree: name.to_owned(),
+                        stored: Box::new(stored.meta),
+                        requested: Box::new(registration.meta),
+                    })
+                }
+            }
+        }
+    }
+
+    /// Returns the name of the sled tree holding the entries of a tree.
+    fn physical_name(&self, name: &str) -> Result<String> {
+        match self.registry.get(name)? {
+            Some(bytes) => Ok(self.decode_registration(name.as_bytes(), &bytes)?.physical),
+            None => Ok(name.to_owned()),
+        }
+    }
+
+    fn decode_registration(&self, name: &[u8], bytes: &[u8]) -> Result<Registration> {
+        crate::decode_value(REGISTRY_TREE.as_bytes(), name, bytes)
+    }
+}
+
+impl AsRef<sled::Db> for Db {
+    fn as_ref(&self) -> &sled::Db {
+        &self.inner
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.contains(RESERVED_NAME) {
+        Err(sled::Error::Unsupported(format!("the tree name {:?} is reserved", name)).into())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_serde::serialize::BincodeSerDe;
+
+    fn temporary_db() -> Db {
+        Db::new(sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let db = temporary_db();
+
+        let tree = db.open_tree::<String, u64>("tree").unwrap();
+        tree.insert(&"a".to_owned(), &1).unwrap();
+
+        assert!(db.open_tree::<String, u64>("tree").is_ok());
+        match db.open_tree::<u64, u64>("tree") {
+            Err(Error::TypeMismatch {
+                tree,
+                stored,
+                requested,
+            }) => {
+                assert_eq!(tree, "tree");
+                assert_eq!(stored.key_type, type_name::<String>());
+                assert_eq!(requested.key_type, type_name::<u64>());
+            }
+            other => panic!("expected a type mismatch, got {:?}", other.map(|_| ())),
+        }
+        // Same types, but a different codec.
+        assert!(matches!(
+            db.open_custom_tree::<String, u64, BincodeSerDe>("tree"),
+            Err(Error::TypeMismatch { .. })
+        ));
+        assert!(db
+            .open_custom_tree::<String, u64, serialize::OrderedSerDe>("tree")
+            .is_ok());
+        assert!(db.open_tree::<u64, u64>(REGISTRY_TREE).is_err());
+        assert!(db
+            .open_tree::<u64, u64>("__typed_sled_key_generators")
+            .is_err());
+        assert!(db
+            .open_tree::<u64, u64>("tree__typed_sled_ttl_expiry")
+            .is_err());
+        assert!(db.rename_tree("tree", "__typed_sled_convert").is_err());
+        assert!(db.drop_tree("__typed_sled_convert").is_err());
+        // Same types, but a different schema version.
+        assert!(matches!(
+            db.open_tree_with_schema::<String, u64>("tree", 1),
+            Err(Error::TypeMismatch { .. })
+        ));
+        db.reregister_tree(
+            "tree",
+            TreeMeta::new::<String, u64, serialize::OrderedSerDe>().with_schema_version(1),
+        )
+        .unwrap();
+        assert!(db.open_tree_with_schema::<String, u64>("tree", 1).is_ok());
+        assert!(db.open_tree::<String, u64>("tree").is_err());
+    }
+
+    #[test]
+    fn test_list_drop_rename() {
+        let db = temporary_db();
+
+        let tree = db.open_tree::<u32, String>("a").unwrap();
+        for i in 0..3000 {
+            tree.insert(&i, &i.to_string()).unwrap();
+        }
+        db.open_custom_tree::<u32, u32, BincodeSerDe>("b").unwrap();
+
+        let names: Vec<String> = db.trees().unwrap().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec!["a", "b"]);
+
+        assert!(db.rename_tree("a", "b").is_err());
+        db.rename_tree("a", "c").unwrap();
+        assert_eq!(db.tree_meta("a").unwrap(), None);
+        assert_eq!(
+            db.tree_meta("c").unwrap(),
+            Some(TreeMeta::new::<u32, String, serialize::OrderedSerDe>())
+        );
+        let renamed = db.open_tree::<u32, String>("c").unwrap();
+        assert_eq!(renamed.len(), 3000);
+        assert_eq!(renamed.get(&2999).unwrap(), Some("2999".to_owned()));
+        assert!(db.open_tree::<u32, String>("a").unwrap().is_empty());
+
+        assert!(db.drop_tree("b").unwrap());
+        assert!(!db.drop_tree("d").unwrap());
+        let names: Vec<String> = db.trees().unwrap().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec!["a", "c"]);
+    }
+}
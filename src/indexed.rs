@@ -0,0 +1,725 @@
+//! A [Tree] with secondary indexes that are maintained automatically.
+//!
+//! Every index is identified by a name and derives zero or more index keys
+//! from a value. The index entries are stored in separate sled trees and
+//! are updated in the same transaction as the tree itself.
+//!
+//! # Example
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use typed_sled::indexed::IndexedTree;
+//!
+//! #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+//! struct User {
+//!     email: String,
+//!     age: u8,
+//! }
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let db = sled::Config::new().temporary(true).open().unwrap();
+//!
+//!     let users = IndexedTree::<u64, User>::try_open(&db, "users")?
+//!         .with_unique_index("email", |user: &User| vec![user.email.clone()])?
+//!         .with_index("age", |user: &User| vec![user.age])?;
+//!
+//!     let ferris = User { email: "ferris@example.com".to_owned(), age: 7 };
+//!     users.insert(&1, &ferris)?;
+//!     users.insert(&2, &User { email: "corro@example.com".to_owned(), age: 12 })?;
+//!
+//!     assert_eq!(
+//!         users.get_by_index("email", &"ferris@example.com".to_owned())?,
+//!         vec![(1, ferris.clone())]
+//!     );
+//!     let young: Vec<u64> = users
+//!         .range_by_index("age", ..10u8)?
+//!         .map(|res| res.map(|(id, _)| id))
+//!         .collect::<Result<_, _>>()?;
+//!     assert_eq!(young, vec![1]);
+//!
+//!     // The email is already used by user 1.
+//!     assert!(users.insert(&3, &ferris).is_err());
+//!     Ok(())
+//! }
+//! ```
+use crate::{CompareAndSwapError, Error, Result, Tree, KV};
+use core::ops::{Bound, RangeBounds};
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+    TransactionalTree,
+};
+use sled::{IVec, Transactional};
+use std::any::type_name;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+type IndexKeys<V> = dyn Fn(&V) -> Result<Vec<Vec<u8>>> + Send + Sync;
+
+struct Index<V> {
+    name: String,
+    unique: bool,
+    key_type: &'static str,
+    tree: sled::Tree,
+    keys: Box<IndexKeys<V>>,
+}
+
+impl<V> Index<V> {
+    // Unique indexes map the index key to the key of the tree. Other indexes
+    // append the key of the tree to make the entry unique and store it as the value.
+    fn entry_key(&self, index_key: &[u8], key_bytes: &[u8]) -> Vec<u8> {
+        let mut entry_key = index_key.to_vec();
+        if !self.unique {
+            entry_key.extend_from_slice(key_bytes);
+        }
+        entry_key
+    }
+}
+
+/// The name of the sled tree storing the index `index` of `tree`.
+pub fn index_tree_name(tree: &str, index: &str) -> String {
+    format!("{}__typed_sled_index__{}", tree, index)
+}
+
+/// A [Tree] which keeps a set of secondary indexes up to date.
+///
+/// All writes have to go through the `IndexedTree`, writing to the
+/// underlying tree directly leaves the indexes out of date.
+/// Every handle writing to the tree must register the same indexes.
+pub struct IndexedTree<K, V> {
+    db: sled::Db,
+    tree: Tree<K, V>,
+    indexes: Vec<Arc<Index<V>>>,
+}
+
+impl<K, V> Clone for IndexedTree<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            tree: self.tree.clone(),
+            indexes: self.indexes.clone(),
+        }
+    }
+}
+
+impl<K, V> IndexedTree<K, V> {
+    /// Open a tree without any indexes. Add indexes with
+    /// [with_index][IndexedTree::with_index] and [with_unique_index][IndexedTree::with_unique_index].
+    ///
+    /// # Panics
+    ///
+    /// Panics if sled fails to open the tree. Use [try_open][IndexedTree::try_open]
+    /// to handle the error instead.
+    pub fn open<T: AsRef<str>>(db: &sled::Db, id: T) -> Self {
+        Self::try_open(db, id).unwrap()
+    }
+
+    /// Open a tree like [open][IndexedTree::open], but return an error
+    /// instead of panicking if sled fails to open the tree.
+    pub fn try_open<T: AsRef<str>>(db: &sled::Db, id: T) -> Result<Self> {
+        Ok(Self {
+            db: db.clone(),
+            tree: Tree::try_open(db, id)?,
+            indexes: Vec::new(),
+        })
+    }
+
+    /// Register an index which maps every value to the keys returned by `f`.
+    ///
+    /// If the index is empty but the tree is not, e.g. because the index is new,
+    /// it is built from the entries of the tree.
+    pub fn with_index<I, F>(self, name: &str, f: F) -> Result<Self>
+    where
+        K: KV,
+        V: KV,
+        I: KV,
+        F: Fn(&V) -> Vec<I> + Send + Sync + 'static,
+    {
+        self.add_index(name, false, f)
+    }
+
+    /// Register an index like [with_index][IndexedTree::with_index], which additionally
+    /// makes sure that no two entries of the tree share an index key.
+    /// Writes that would violate this fail with [Error::UniqueViolation].
+    pub fn with_unique_index<I, F>(self, name: &str, f: F) -> Result<Self>
+    where
+        K: KV,
+        V: KV,
+        I: KV,
+        F: Fn(&V) -> Vec<I> + Send + Sync + 'static,
+    {
+        self.add_index(name, true, f)
+    }
+
+    fn add_index<I, F>(mut self, name: &str, unique: bool, f: F) -> Result<Self>
+    where
+        K: KV,
+        V: KV,
+        I: KV,
+        F: Fn(&V) -> Vec<I> + Send + Sync + 'static,
+    {
+        let tree_name = String::from_utf8_lossy(&self.tree.name()).into_owned();
+        let index = Index {
+            name: name.to_owned(),
+            unique,
+            key_type: type_name::<I>(),
+            tree: self.db.open_tree(index_tree_name(&tree_name, name))?,
+            keys: Box::new(move |value: &V| f(value).iter().map(crate::encode_key).collect()),
+        };
+        let needs_rebuild = index.tree.is_empty() && !self.tree.is_empty();
+        self.indexes.retain(|index| index.name != name);
+        self.indexes.push(Arc::new(index));
+        if needs_rebuild {
+            self.rebuild_index(name)?;
+        }
+        Ok(self)
+    }
+
+    /// Returns the underlying tree.
+    /// Writing to it directly leaves the indexes out of date.
+    pub fn tree(&self) -> &Tree<K, V> {
+        &self.tree
+    }
+
+    /// Retrieve a value from the Tree if it exists.
+    pub fn get(&self, key: &K) -> Result<Option<V>>
+    where
+        K: KV,
+        V: KV,
+    {
+        self.tree.get(key)
+    }
+
+    /// Create a double-ended iterator over the tuples of keys and
+    /// values in this tree.
+    pub fn iter(&self) -> crate::Iter<K, V> {
+        self.tree.iter()
+    }
+
+    /// Insert a key to a new value and update the indexes, returning the last value if it was set.
+    pub fn insert(&self, key: &K, value: &V) -> Result<Option<V>>
+    where
+        K: KV,
+        V: KV,
+    {
+        let key_bytes = crate::encode_key(key)?;
+        let value_bytes = crate::encode_value(value)?;
+        let old_value =
+            self.transaction(|trees| self.write(trees, &key_bytes, Some((&value_bytes, value))))?;
+        self.decode_opt_value(&key_bytes, old_value)
+    }
+
+    /// Delete a value and its index entries, returning the old value if it existed.
+    pub fn remove(&self, key: &K) -> Result<Option<V>>
+    where
+        K: KV,
+        V: KV,
+    {
+        let key_bytes = crate::encode_key(key)?;
+        let old_value = self.transaction(|trees| self.write(trees, &key_bytes, None))?;
+        self.decode_opt_value(&key_bytes, old_value)
+    }
+
+    /// Compare and swap, see [Tree::compare_and_swap]. The indexes are
+    /// only updated if the swap succeeds.
+    pub fn compare_and_swap(
+        &self,
+        key: &K,
+        old: Option<&V>,
+        new: Option<&V>,
+    ) -> Result<core::result::Result<(), CompareAndSwapError<V>>>
+    where
+        K: KV,
+        V: KV,
+    {
+        let key_bytes = crate::encode_key(key)?;
+        let old_bytes = old.map(crate::encode_value).transpose()?;
+        let new_bytes = new.map(crate::encode_value).transpose()?;
+        let res = self.transaction(|trees| {
+            let current = trees[0].get(&key_bytes)?;
+            if current.as_deref() != old_bytes.as_deref() {
+                return Ok(Err(current));
+            }
+            self.write(trees, &key_bytes, new_bytes.as_ref().zip(new))?;
+            Ok(Ok(()))
+        })?;
+        match res {
+            Ok(()) => Ok(Ok(())),
+            Err(current) => Ok(Err(CompareAndSwapError {
+                current: self.decode_opt_value(&key_bytes, current)?,
+                proposed: self.decode_opt_value(&key_bytes, new_bytes.map(IVec::from))?,
+            })),
+        }
+    }
+
+    /// Create a new batch of updates which can be applied atomically
+    /// together with their index updates.
+    pub fn new_batch(&self) -> IndexedBatch<K, V> {
+        IndexedBatch::default()
+    }
+
+    /// Atomically apply a batch and update the indexes.
+    pub fn apply_batch(&self, batch: IndexedBatch<K, V>) -> Result<()>
+    where
+        K: KV,
+        V: KV,
+    {
+        self.transaction(|trees| {
+            for (key_bytes, value) in &batch.ops {
+                let value = value.as_ref().map(|(bytes, value)| (bytes, value));
+                self.write(trees, key_bytes, value)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Retrieve all entries whose values are mapped to `index_key` by the index `index`.
+    pub fn get_by_index<I: KV>(&self, index: &str, index_key: &I) -> Result<Vec<(K, V)>>
+    where
+        K: KV,
+        V: KV,
+    {
+        let index = self.index::<I>(index)?;
+        let index_key = crate::encode_key(index_key)?;
+        let mut entries = Vec::new();
+        let key_bytes = if index.unique {
+            index.tree.get(&index_key)?.into_iter().collect()
+        } else {
+            index
+                .tree
+                .scan_prefix(&index_key)
+                .values()
+                .collect::<sled::Result<Vec<_>>>()?
+        };
+        for key_bytes in key_bytes {
+            if let Some(entry) = self.get_kv_from_raw(&key_bytes)? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Create an iterator over the entries whose index keys of the index `index` fall
+    /// within the specified range, ordered by index key.
+    pub fn range_by_index<I: KV, R: RangeBounds<I>>(
+        &self,
+        index: &str,
+        range: R,
+    ) -> Result<impl DoubleEndedIterator<Item = Result<(K, V)>> + '_>
+    where
+        K: KV,
+        V: KV,
+    {
+        let index = self.index::<I>(index)?;
+        let start = crate::encode_bound(range.start_bound())?;
+        let end = crate::encode_bound(range.end_bound())?;
+        let iter = if index.unique {
+            index.tree.range((start, end))
+        } else {
+            // Entries of non-unique indexes are prefixed by the index key, so
+            // excluding a lower or including an upper index key means starting
+            // or ending before the successor of the prefix.
+            let lower = match start {
+                Bound::Excluded(start) => prefix_successor(&start).map(Bound::Included),
+                bound => Some(bound),
+            };
+            let upper = match end {
+                Bound::Included(end) => {
+                    prefix_successor(&end).map_or(Bound::Unbounded, Bound::Excluded)
+                }
+                bound => bound,
+            };
+            match lower {
+                Some(lower) => index.tree.range((lower, upper)),
+                // No index key comes after the excluded lower bound.
+                None => index.tree.range::<&[u8], _>(&[][..]..&[][..]),
+            }
+        };
+        Ok(iter.filter_map(move |res| {
+            let (_, key_bytes) = match res {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.get_kv_from_raw(&key_bytes).transpose()
+        }))
+    }
+
+    /// Clear the index `index` and rebuild it from the entries of the tree.
+    ///
+    /// This is not atomic, the tree shouldn't be written to while the index is rebuilt.
+    pub fn rebuild_index(&self, index: &str) -> Result<()>
+    where
+        K: KV,
+        V: KV,
+    {
+        let index = self
+            .indexes
+            .iter()
+            .find(|i| i.name == index)
+            .ok_or_else(|| Error::UnknownIndex {
+                index: index.to_owned(),
+            })?;
+        index.tree.clear()?;
+        let name = self.tree.name();
+        for res in self.tree.inner.iter() {
+            let (key_bytes, value_bytes) = res?;
+            let value: V = crate::decode_value(&name, &key_bytes, &value_bytes)?;
+            for index_key in (index.keys)(&value)? {
+                let entry_key = index.entry_key(&index_key, &key_bytes);
+                if let (true, Some(existing)) = (index.unique, index.tree.get(&entry_key)?) {
+                    if existing != key_bytes {
+                        return Err(Error::UniqueViolation {
+                            index: index.name.clone(),
+                            index_key,
+                        });
+                    }
+                }
+                index.tree.insert(entry_key, key_bytes.clone())?;
+            }
+        }
+        index.tree.flush()?;
+        Ok(())
+    }
+
+    fn index<I>(&self, name: &str) -> Result<&Index<V>> {
+        self.indexes
+            .iter()
+            .find(|index| index.name == name && index.key_type == type_name::<I>())
+            .map(|index| &**index)
+            .ok_or_else(|| Error::UnknownIndex {
+                index: name.to_owned(),
+            })
+    }
+
+    // Runs `f` in a transaction over the tree followed by the trees of all indexes.
+    fn transaction<A, F>(&self, f: F) -> Result<A>
+    where
+        F: Fn(&Vec<TransactionalTree>) -> ConflictableTransactionResult<A, Error>,
+    {
+        let mut trees = Vec::with_capacity(self.indexes.len() + 1);
+        trees.push(self.tree.inner.clone());
+        trees.extend(self.indexes.iter().map(|index| index.tree.clone()));
+        trees.as_slice().transaction(f).map_err(|e| match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        })
+    }
+
+    // Writes or removes a single entry and updates the indexes accordingly.
+    fn write(
+        &self,
+        trees: &[TransactionalTree],
+        key_bytes: &[u8],
+        new: Option<(&Vec<u8>, &V)>,
+    ) -> ConflictableTransactionResult<Option<IVec>, Error>
+    where
+        V: KV,
+    {
+        let old_value = match new {
+            Some((value_bytes, _)) => trees[0].insert(key_bytes, value_bytes.as_slice())?,
+            None => trees[0].remove(key_bytes)?,
+        };
+        let old_value_decoded = match &old_value {
+            Some(bytes) => Some(
+                crate::decode_value::<V>(&self.tree.name(), key_bytes, bytes)
+                    .map_err(ConflictableTransactionError::Abort)?,
+            ),
+            None => None,
+        };
+        for (index, tree) in self.indexes.iter().zip(&trees[1..]) {
+            if let Some(old_value) = &old_value_decoded {
+                for index_key in
+                    (index.keys)(old_value).map_err(ConflictableTransactionError::Abort)?
+                {
+                    tree.remove(index.entry_key(&index_key, key_bytes))?;
+                }
+            }
+            if let Some((_, value)) = new {
+                for index_key in (index.keys)(value).map_err(ConflictableTransactionError::Abort)? {
+                    let entry_key = index.entry_key(&index_key, key_bytes);
+                    if index.unique {
+                        if let Some(existing) = tree.get(&entry_key)? {
+                            if existing != key_bytes {
+                                return Err(ConflictableTransactionError::Abort(
+                                    Error::UniqueViolation {
+                                        index: index.name.clone(),
+                                        index_key,
+                                    },
+                                ));
+                            }
+                        }
+                    }
+                    tree.insert(entry_key, key_bytes)?;
+                }
+            }
+        }
+        Ok(old_value)
+    }
+
+    fn get_kv_from_raw(&self, key_bytes: &[u8]) -> Result<Option<(K, V)>>
+    where
+        K: KV,
+        V: KV,
+    {
+        self.tree.get_kv_from_raw(key_bytes)
+    }
+
+    fn decode_opt_value(&self, key_bytes: &[u8], value: Option<IVec>) -> Result<Option<V>>
+    where
+        V: KV,
+    {
+        value
+            .map(|v| crate::decode_value(&self.tree.name(), key_bytes, &v))
+            .transpose()
+    }
+}
+
+// The serialized key and, for inserts, the serialized and the original value.
+type BatchOp<V> = (Vec<u8>, Option<(Vec<u8>, V)>);
+
+/// A batch of updates for an [IndexedTree].
+pub struct IndexedBatch<K, V> {
+    ops: Vec<BatchOp<V>>,
+    _key: PhantomData<fn() -> K>,
+}
+
+impl<K, V> IndexedBatch<K, V> {
+    pub fn insert(&mut self, key: &K, value: V) -> Result<()>
+    where
+        K: KV,
+        V: KV,
+    {
+        let value_bytes = crate::encode_value(&value)?;
+        self.ops
+            .push((crate::encode_key(key)?, Some((value_bytes, value))));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &K) -> Result<()>
+    where
+        K: KV,
+    {
+        self.ops.push((crate::encode_key(key)?, None));
+        Ok(())
+    }
+}
+
+// Implementing Default manually to not require K and V to implement Default.
+impl<K, V> Default for IndexedBatch<K, V> {
+    fn default() -> Self {
+        Self {
+            ops: Vec::new(),
+            _key: PhantomData,
+        }
+    }
+}
+
+// The smallest key that is greater than all keys starting with `prefix`,
+// `None` if there is no such key.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut successor = prefix.to_vec();
+    while let Some(last) = successor.pop() {
+        if last < u8::MAX {
+            successor.push(last + 1);
+            return Some(successor);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    struct Order {
+        customer: String,
+        status: u8,
+        tags: Vec<String>,
+    }
+
+    fn order(customer: &str, status: u8, tags: &[&str]) -> Order {
+        Order {
+            customer: customer.to_owned(),
+            status,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    fn open(db: &sled::Db) -> IndexedTree<u32, Order> {
+        IndexedTree::open(db, "orders")
+            .with_index("status", |o: &Order| vec![o.status])
+            .unwrap()
+            .with_index("tag", |o: &Order| o.tags.clone())
+            .unwrap()
+            .with_unique_index("customer", |o: &Order| vec![o.customer.clone()])
+            .unwrap()
+    }
+
+    fn keys(entries: Vec<(u32, Order)>) -> Vec<u32> {
+        entries.into_iter().map(|(k, _)| k).collect()
+    }
+
+    #[test]
+    fn test_index_maintenance() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = open(&db);
+
+        tree.insert(&1, &order("a", 1, &["x", "y"])).unwrap();
+        tree.insert(&2, &order("b", 2, &["y"])).unwrap();
+        tree.insert(&3, &order("c", 1, &[])).unwrap();
+
+        assert_eq!(keys(tree.get_by_index("status", &1u8).unwrap()), vec![1, 3]);
+        assert_eq!(
+            keys(tree.get_by_index("tag", &"y".to_owned()).unwrap()),
+            vec![1, 2]
+        );
+        assert_eq!(
+            keys(tree.get_by_index("customer", &"b".to_owned()).unwrap()),
+            vec![2]
+        );
+
+        // Updating a value moves its index entries.
+        tree.insert(&1, &order("a", 2, &["x"])).unwrap();
+        assert_eq!(keys(tree.get_by_index("status", &1u8).unwrap()), vec![3]);
+        assert_eq!(
+            keys(tree.get_by_index("tag", &"y".to_owned()).unwrap()),
+            vec![2]
+        );
+
+        // Unique violations abort the whole write.
+        assert!(matches!(
+            tree.insert(&4, &order("a", 3, &[])),
+            Err(Error::UniqueViolation { .. })
+        ));
+        assert_eq!(tree.get(&4).unwrap(), None);
+        assert!(tree.get_by_index("status", &3u8).unwrap().is_empty());
+
+        tree.remove(&1).unwrap();
+        assert!(tree
+            .get_by_index("customer", &"a".to_owned())
+            .unwrap()
+            .is_empty());
+        assert!(tree
+            .get_by_index("tag", &"x".to_owned())
+            .unwrap()
+            .is_empty());
+
+        assert!(matches!(
+            tree.get_by_index("status", &1u32),
+            Err(Error::UnknownIndex { .. })
+        ));
+    }
+
+    #[test]
+    fn test_cas_and_batch() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = open(&db);
+
+        let first = order("a", 1, &[]);
+        tree.compare_and_swap(&1, None, Some(&first))
+            .unwrap()
+            .unwrap();
+        let conflict = tree
+            .compare_and_swap(&1, None, Some(&order("a", 2, &[])))
+            .unwrap();
+        assert_eq!(conflict.unwrap_err().current, Some(first.clone()));
+        assert_eq!(keys(tree.get_by_index("status", &1u8).unwrap()), vec![1]);
+        assert!(tree.get_by_index("status", &2u8).unwrap().is_empty());
+
+        let mut batch = tree.new_batch();
+        batch.remove(&1).unwrap();
+        batch.insert(&2, order("a", 5, &[])).unwrap();
+        batch.insert(&3, order("b", 5, &[])).unwrap();
+        tree.apply_batch(batch).unwrap();
+        assert_eq!(keys(tree.get_by_index("status", &5u8).unwrap()), vec![2, 3]);
+        assert!(tree.get_by_index("status", &1u8).unwrap().is_empty());
+
+        let mut batch = tree.new_batch();
+        batch.insert(&4, order("c", 6, &[])).unwrap();
+        batch.insert(&5, order("c", 6, &[])).unwrap();
+        assert!(tree.apply_batch(batch).is_err());
+        assert!(tree.get_by_index("status", &6u8).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_range_by_index() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = open(&db);
+        for (key, status) in [(1, 3u8), (2, 1), (3, 2), (4, 2), (5, 4)] {
+            tree.insert(&key, &order(&key.to_string(), status, &[]))
+                .unwrap();
+        }
+        let range = |start: Bound<u8>, end: Bound<u8>| -> Vec<u32> {
+            tree.range_by_index("status", (start, end))
+                .unwrap()
+                .map(|res| res.unwrap().0)
+                .collect()
+        };
+        assert_eq!(
+            range(Bound::Unbounded, Bound::Unbounded),
+            vec![2, 3, 4, 1, 5]
+        );
+        assert_eq!(range(Bound::Included(2), Bound::Included(3)), vec![3, 4, 1]);
+        assert_eq!(range(Bound::Excluded(2), Bound::Excluded(4)), vec![1]);
+        assert_eq!(range(Bound::Excluded(1), Bound::Included(2)), vec![3, 4]);
+        assert_eq!(
+            range(Bound::Included(2), Bound::Excluded(2)),
+            Vec::<u32>::new()
+        );
+
+        tree.insert(&6, &order("6", u8::MAX, &[])).unwrap();
+        assert_eq!(
+            range(Bound::Included(4), Bound::Included(u8::MAX)),
+            vec![5, 6]
+        );
+        assert_eq!(
+            range(Bound::Excluded(u8::MAX), Bound::Unbounded),
+            Vec::<u32>::new()
+        );
+
+        let customers: Vec<u32> = tree
+            .range_by_index("customer", "2".to_owned()..)
+            .unwrap()
+            .map(|res| res.unwrap().0)
+            .collect();
+        assert_eq!(customers, vec![2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(prefix_successor(&[1, 2]), Some(vec![1, 3]));
+        assert_eq!(prefix_successor(&[1, 0xFF, 0xFF]), Some(vec![2]));
+        assert_eq!(prefix_successor(&[0xFF]), None);
+        assert_eq!(prefix_successor(&[]), None);
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree: Tree<u32, Order> = Tree::open(&db, "orders");
+        tree.insert(&1, &order("a", 1, &[])).unwrap();
+        tree.insert(&2, &order("b", 1, &[])).unwrap();
+
+        // New indexes are built from the existing entries.
+        let indexed = open(&db);
+        assert_eq!(
+            keys(indexed.get_by_index("status", &1u8).unwrap()),
+            vec![1, 2]
+        );
+
+        // Writes bypassing the indexes can be repaired with a rebuild.
+        tree.insert(&3, &order("c", 1, &[])).unwrap();
+        assert_eq!(indexed.get_by_index("status", &1u8).unwrap().len(), 2);
+        indexed.rebuild_index("status").unwrap();
+        assert_eq!(
+            keys(indexed.get_by_index("status", &1u8).unwrap()),
+            vec![1, 2, 3]
+        );
+
+        tree.insert(&4, &order("c", 1, &[])).unwrap();
+        assert!(matches!(
+            indexed.rebuild_index("customer"),
+            Err(Error::UniqueViolation { .. })
+        ));
+    }
+}
@@ -0,0 +1,890 @@
+//! Order-preserving (de)serialization of keys.
+//!
+//! sled sorts keys by comparing their bytes lexicographically. bincode encodes
+//! integers in little-endian, so the byte order of bincode encoded keys does not
+//! match the [Ord] implementation of the key type. This module implements a serde
+//! format whose byte order matches the [Ord] implementation of the serialized type
+//! for the types commonly used as keys:
+//!
+//! * `bool`, unsigned and signed integers and `char`.
+//! * `f32` and `f64`, ordered like [f64::total_cmp].
+//! * `String`, `&str` and byte slices.
+//! * `Option`, tuples, structs, `Vec`s and enums, which are ordered like the
+//!   derived [Ord] implementation (fields in declaration order, variants by index).
+//!
+//! The format is not self-describing, so types that rely on
+//! `Deserializer::deserialize_any` (for example untagged enums or `#[serde(flatten)]`)
+//! are not supported.
+//!
+//! # Example
+//! ```
+//! use typed_sled::key_codec::{from_bytes, to_bytes};
+//!
+//! let small = to_bytes(&255u32).unwrap();
+//! let large = to_bytes(&256u32).unwrap();
+//! assert!(small < large);
+//! assert_eq!(from_bytes::<u32>(&large).unwrap(), 256);
+//! ```
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use std::fmt::Display;
+
+/// Marks the end of a string or byte slice.
+const TERMINATOR: [u8; 2] = [0x00, 0x00];
+/// A `0x00` byte inside a string or byte slice is escaped as `0x00 0xFF`.
+const ESCAPE: u8 = 0xFF;
+/// Precedes every element of a sequence or map.
+const ELEMENT: u8 = 0x01;
+/// Marks the end of a sequence or map.
+const END: u8 = 0x00;
+
+/// Errors that can occur while (de)serializing with the key codec.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Message(String),
+    #[error("unexpected end of input")]
+    Eof,
+    #[error("trailing bytes after deserialized value")]
+    TrailingBytes,
+    #[error("invalid encoding: {0}")]
+    InvalidEncoding(&'static str),
+    #[error("the key codec is not self-describing, deserialize_any is not supported")]
+    AnyNotSupported,
+}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Serialize a value with the order-preserving key codec.
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut serializer = Serializer { output: Vec::new() };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+/// Deserialize a value that was serialized with [to_bytes].
+pub fn from_bytes<'de, T: de::Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+    let mut deserializer = Deserializer { input: bytes };
+    let value = T::deserialize(&mut deserializer)?;
+    if deserializer.input.is_empty() {
+        Ok(value)
+    } else {
+        Err(Error::TrailingBytes)
+    }
+}
+
+/// A serde `Serializer` whose output preserves the ordering of the serialized values.
+pub struct Serializer {
+    output: Vec<u8>,
+}
+
+impl Serializer {
+    fn write_escaped(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.output.push(b);
+            if b == 0x00 {
+                self.output.push(ESCAPE);
+            }
+        }
+        self.output.extend_from_slice(&TERMINATOR);
+    }
+
+    fn write_variant(&mut self, variant_index: u32) {
+        self.output.extend_from_slice(&variant_index.to_be_bytes());
+    }
+}
+
+macro_rules! serialize_unsigned {
+    ($($method:ident: $ty:ty),*) => {
+        $(fn $method(self, v: $ty) -> Result<()> {
+            self.output.extend_from_slice(&v.to_be_bytes());
+            Ok(())
+        })*
+    };
+}
+
+macro_rules! serialize_signed {
+    ($($method:ident: $ty:ty => $unsigned:ty),*) => {
+        $(fn $method(self, v: $ty) -> Result<()> {
+            // Flipping the sign bit orders negative numbers before positive ones.
+            let v = (v as $unsigned) ^ (1 << (<$unsigned>::BITS - 1));
+            self.output.extend_from_slice(&v.to_be_bytes());
+            Ok(())
+        })*
+    };
+}
+
+impl ser::Serializer for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.output.push(v as u8);
+        Ok(())
+    }
+
+    serialize_unsigned!(
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128
+    );
+
+    serialize_signed!(
+        serialize_i8: i8 => u8,
+        serialize_i16: i16 => u16,
+        serialize_i32: i32 => u32,
+        serialize_i64: i64 => u64,
+        serialize_i128: i128 => u128
+    );
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        let bits = v.to_bits();
+        let bits = if bits >> 31 == 1 {
+            !bits
+        } else {
+            bits | 1 << 31
+        };
+        self.output.extend_from_slice(&bits.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        let bits = v.to_bits();
+        let bits = if bits >> 63 == 1 {
+            !bits
+        } else {
+            bits | 1 << 63
+        };
+        self.output.extend_from_slice(&bits.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.write_escaped(v.as_bytes());
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.write_escaped(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.output.push(0);
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        self.output.push(1);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<()> {
+        self.write_variant(variant_index);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.write_variant(variant_index);
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        self.write_variant(variant_index);
+        Ok(self)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        self.write_variant(variant_index);
+        Ok(self)
+    }
+}
+
+impl ser::SerializeSeq for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.output.push(ELEMENT);
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.output.push(ELEMENT);
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A serde `Deserializer` for bytes produced by [Serializer].
+pub struct Deserializer<'de> {
+    input: &'de [u8],
+}
+
+/// Either a slice borrowed from the input or an owned buffer,
+/// if the bytes contained escape sequences.
+enum Unescaped<'de> {
+    Borrowed(&'de [u8]),
+    Owned(Vec<u8>),
+}
+
+impl<'de> Deserializer<'de> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.input.len() < N {
+            return Err(Error::Eof);
+        }
+        let (head, rest) = self.input.split_at(N);
+        self.input = rest;
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(head);
+        Ok(bytes)
+    }
+
+    fn take_byte(&mut self) -> Result<u8> {
+        self.take::<1>().map(|[b]| b)
+    }
+
+    fn take_escaped(&mut self) -> Result<Unescaped<'de>> {
+        let input = self.input;
+        let mut owned: Option<Vec<u8>> = None;
+        let mut i = 0;
+        loop {
+            match input.get(i) {
+                None => return Err(Error::Eof),
+                Some(0x00) => match input.get(i + 1) {
+                    Some(0x00) => {
+                        self.input = &input[i + 2..];
+                        return Ok(match owned {
+                            Some(owned) => Unescaped::Owned(owned),
+                            None => Unescaped::Borrowed(&input[..i]),
+                        });
+                    }
+                    Some(&ESCAPE) => {
+                        owned.get_or_insert_with(|| input[..i].to_vec()).push(0x00);
+                        i += 2;
+                    }
+                    Some(_) => return Err(Error::InvalidEncoding("invalid escape sequence")),
+                    None => return Err(Error::Eof),
+                },
+                Some(&b) => {
+                    if let Some(owned) = owned.as_mut() {
+                        owned.push(b);
+                    }
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    fn take_variant(&mut self) -> Result<u32> {
+        self.take::<4>().map(u32::from_be_bytes)
+    }
+
+    fn take_element_marker(&mut self) -> Result<bool> {
+        match self.take_byte()? {
+            ELEMENT => Ok(true),
+            END => Ok(false),
+            _ => Err(Error::InvalidEncoding("invalid sequence marker")),
+        }
+    }
+}
+
+macro_rules! deserialize_unsigned {
+    ($($method:ident: $ty:ty => $visit:ident),*) => {
+        $(fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+            let v = <$ty>::from_be_bytes(self.take()?);
+            visitor.$visit(v)
+        })*
+    };
+}
+
+macro_rules! deserialize_signed {
+    ($($method:ident: $ty:ty => $unsigned:ty, $visit:ident),*) => {
+        $(fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+            let v = <$unsigned>::from_be_bytes(self.take()?) ^ (1 << (<$unsigned>::BITS - 1));
+            visitor.$visit(v as $ty)
+        })*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error::AnyNotSupported)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error::AnyNotSupported)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take_byte()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            _ => Err(Error::InvalidEncoding("invalid bool")),
+        }
+    }
+
+    deserialize_unsigned!(
+        deserialize_u8: u8 => visit_u8,
+        deserialize_u16: u16 => visit_u16,
+        deserialize_u32: u32 => visit_u32,
+        deserialize_u64: u64 => visit_u64,
+        deserialize_u128: u128 => visit_u128
+    );
+
+    deserialize_signed!(
+        deserialize_i8: i8 => u8, visit_i8,
+        deserialize_i16: i16 => u16, visit_i16,
+        deserialize_i32: i32 => u32, visit_i32,
+        deserialize_i64: i64 => u64, visit_i64,
+        deserialize_i128: i128 => u128, visit_i128
+    );
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let bits = u32::from_be_bytes(self.take()?);
+        let bits = if bits >> 31 == 1 {
+            bits & !(1 << 31)
+        } else {
+            !bits
+        };
+        visitor.visit_f32(f32::from_bits(bits))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let bits = u64::from_be_bytes(self.take()?);
+        let bits = if bits >> 63 == 1 {
+            bits & !(1 << 63)
+        } else {
+            !bits
+        };
+        visitor.visit_f64(f64::from_bits(bits))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let v = u32::from_be_bytes(self.take()?);
+        let c = char::from_u32(v).ok_or(Error::InvalidEncoding("invalid char"))?;
+        visitor.visit_char(c)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take_escaped()? {
+            Unescaped::Borrowed(bytes) => visitor.visit_borrowed_str(
+                std::str::from_utf8(bytes).map_err(|_| Error::InvalidEncoding("invalid utf-8"))?,
+            ),
+            Unescaped::Owned(bytes) => visitor.visit_string(
+                String::from_utf8(bytes).map_err(|_| Error::InvalidEncoding("invalid utf-8"))?,
+            ),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take_escaped()? {
+            Unescaped::Borrowed(bytes) => visitor.visit_borrowed_bytes(bytes),
+            Unescaped::Owned(bytes) => visitor.visit_byte_buf(bytes),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take_byte()? {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            _ => Err(Error::InvalidEncoding("invalid option tag")),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Delimited { de: self })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Fixed { de: self, len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_seq(Fixed { de: self, len })
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_map(Delimited { de: self })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_seq(Fixed {
+            de: self,
+            len: fields.len(),
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_u32(visitor)
+    }
+}
+
+/// Access to a sequence or map whose elements are each preceded by a marker.
+struct Delimited<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'de> de::SeqAccess<'de> for Delimited<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.de.take_element_marker()? {
+            seed.deserialize(&mut *self.de).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Delimited<'_, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.de.take_element_marker()? {
+            seed.deserialize(&mut *self.de).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+/// Access to a tuple or struct with a known number of fields.
+struct Fixed<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    len: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for Fixed<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let variant_index = self.take_variant()?;
+        let value = seed.deserialize(variant_index.into_deserializer())?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Fixed { de: self, len })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_seq(Fixed {
+            de: self,
+            len: fields.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+    use std::fmt::Debug;
+
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+    enum Status {
+        Pending,
+        Active,
+        Closed,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+    struct Composite {
+        status: Status,
+        id: i64,
+        name: String,
+    }
+
+    fn status() -> impl Strategy<Value = Status> {
+        prop_oneof![
+            Just(Status::Pending),
+            Just(Status::Active),
+            Just(Status::Closed)
+        ]
+    }
+
+    fn composite() -> impl Strategy<Value = Composite> {
+        (status(), any::<i64>(), ".*").prop_map(|(status, id, name)| Composite { status, id, name })
+    }
+
+    /// Inserts the values into a `BTreeMap` keyed by the values themselves and into a
+    /// `BTreeMap` keyed by their encoding, and checks that both iterate in the same order.
+    fn assert_same_order<T>(values: Vec<T>)
+    where
+        T: Ord + Serialize + for<'de> Deserialize<'de> + Clone + Debug,
+    {
+        let by_value: BTreeMap<T, ()> = values.iter().cloned().map(|v| (v, ())).collect();
+        let by_bytes: BTreeMap<Vec<u8>, T> = values
+            .iter()
+            .cloned()
+            .map(|v| (to_bytes(&v).unwrap(), v))
+            .collect();
+
+        let expected: Vec<&T> = by_value.keys().collect();
+        let actual: Vec<&T> = by_bytes.values().collect();
+        assert_eq!(expected, actual);
+
+        for (bytes, value) in by_bytes.iter() {
+            assert_eq!(&from_bytes::<T>(bytes).unwrap(), value);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn unsigned_order(values: Vec<(u8, u16, u32)>, large: Vec<(u64, u128)>) {
+            assert_same_order(values);
+            assert_same_order(large);
+        }
+
+        #[test]
+        fn signed_order(values: Vec<(i8, i16, i32)>, large: Vec<(i64, i128)>) {
+            assert_same_order(values);
+            assert_same_order(large);
+        }
+
+        #[test]
+        fn float_order(values: Vec<f64>, small: Vec<f32>) {
+            let mut expected = values.clone();
+            expected.sort_by(f64::total_cmp);
+            let mut actual = values;
+            actual.sort_by_key(|v| to_bytes(v).unwrap());
+            prop_assert_eq!(
+                expected.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+                actual.iter().map(|v| v.to_bits()).collect::<Vec<_>>()
+            );
+
+            let mut expected = small.clone();
+            expected.sort_by(f32::total_cmp);
+            let mut actual = small;
+            actual.sort_by_key(|v| to_bytes(v).unwrap());
+            prop_assert_eq!(
+                expected.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+                actual.iter().map(|v| v.to_bits()).collect::<Vec<_>>()
+            );
+        }
+
+        #[test]
+        fn string_order(values: Vec<String>, with_nul in prop::collection::vec("[a\\x00b]{0,4}", 0..20)) {
+            assert_same_order(values);
+            assert_same_order(with_nul);
+        }
+
+        #[test]
+        fn bytes_order(values: Vec<Vec<u8>>, chars: Vec<char>, bools: Vec<bool>) {
+            assert_same_order(values);
+            assert_same_order(chars);
+            assert_same_order(bools);
+        }
+
+        #[test]
+        fn option_order(values: Vec<Option<i32>>, nested: Vec<Option<(String, Option<u8>)>>) {
+            assert_same_order(values);
+            assert_same_order(nested);
+        }
+
+        #[test]
+        fn tuple_order(values: Vec<(String, u32, i16)>, units: Vec<((), u8)>) {
+            assert_same_order(values);
+            assert_same_order(units);
+        }
+
+        #[test]
+        fn enum_and_struct_order(
+            statuses in prop::collection::vec(status(), 0..10),
+            composites in prop::collection::vec(composite(), 0..50),
+        ) {
+            assert_same_order(statuses);
+            assert_same_order(composites);
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = to_bytes(&1u32).unwrap();
+        bytes.push(0);
+        assert_eq!(from_bytes::<u32>(&bytes), Err(Error::TrailingBytes));
+    }
+
+    #[test]
+    fn borrows_unescaped_str() {
+        let bytes = to_bytes("hello").unwrap();
+        assert_eq!(from_bytes::<&str>(&bytes).unwrap(), "hello");
+    }
+}
@@ -1,5 +1,17 @@
 //! Create [Tree]s with automatically generated keys.
 //!
+//! [CounterTree] continues after the last key when it is opened, [PersistentCounterTree]
+//! persists the generated keys so they are never reused.
+//!
+//! Keys which can be generated by several databases and merged later are available with features:
+//! * `uuid`: [UuidV7Tree] with UUID v7 keys and [UuidV4Tree] with random UUID v4 keys.
+//! * `ulid`: [UlidTree] with ULID keys.
+//! * `snowflake`: [SnowflakeTree] with 64 bit [Snowflake] keys made of a timestamp, a node id and a sequence number.
+//! * `timestamp-key`: [TimestampKeyTree] with [TimestampKey]s made of a timestamp and a counter,
+//!   which are only unique within one tree.
+//!
+//! Except for UUID v4 the keys are ordered by their creation time.
+//!
 //! # Example
 //! ```
 //! use typed_sled::key_generating::CounterTree;
@@ -21,12 +33,20 @@
 //!     Ok(())
 //! }
 //! ```
+use crate::transaction::{
+    ConflictableTransactionResult, TransactionResult, TransactionTarget, TransactionalTree,
+};
+use crate::Result;
 use crate::{Batch, Tree, KV};
-use sled::transaction::{ConflictableTransactionResult, TransactionResult};
-use sled::Result;
-use std::ops::Deref;
+#[cfg(any(feature = "snowflake", feature = "timestamp-key"))]
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, Range};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "ulid")]
+pub use ulid::Ulid;
+#[cfg(feature = "uuid")]
+pub use uuid::Uuid;
 
 /// Wraps a type that implements KeyGenerating and uses it to
 /// generate the keys for a typed_sled::Tree.
@@ -39,14 +59,26 @@ pub struct KeyGeneratingTree<KG: KeyGenerating<V>, V> {
 }
 
 impl<KG: KeyGenerating<V>, V> KeyGeneratingTree<KG, V> {
+    /// Open a tree and initialize its key generator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if sled fails to open the tree or the key generator fails to
+    /// initialize. Use [try_open][Self::try_open] to handle the error instead.
     pub fn open<T: AsRef<str>>(db: &sled::Db, id: T) -> Self {
-        let tree = Tree::open(db, id);
-        let key_generator = KG::initialize(&tree);
+        Self::try_open(db, id).unwrap()
+    }
+
+    /// Open a tree and initialize its key generator like [open][Self::open],
+    /// but return an error instead of panicking.
+    pub fn try_open<T: AsRef<str>>(db: &sled::Db, id: T) -> Result<Self> {
+        let tree = Tree::try_open(db, id)?;
+        let key_generator = KG::initialize(db, &tree)?;
 
-        Self {
+        Ok(Self {
             key_generator,
             inner: tree,
-        }
+        })
     }
 
     /// Insert a generated key to a new value, returning the key and the last value if it was set.
@@ -55,7 +87,7 @@ impl<KG: KeyGenerating<V>, V> KeyGeneratingTree<KG, V> {
         KG::Key: KV,
         V: KV,
     {
-        let key = self.key_generator.next_key();
+        let key = self.key_generator.next_key()?;
         let res = self.inner.insert(&key, value);
         res.map(|opt_v| (key, opt_v))
     }
@@ -73,7 +105,8 @@ impl<KG: KeyGenerating<V>, V> KeyGeneratingTree<KG, V> {
         self.inner.insert(key, value)
     }
 
-    pub fn next_key(&self) -> KG::Key {
+    /// Generate a key without inserting a value, see [insert_with_key][Self::insert_with_key].
+    pub fn next_key(&self) -> Result<KG::Key> {
         self.key_generator.next_key()
     }
 
@@ -85,20 +118,20 @@ impl<KG: KeyGenerating<V>, V> KeyGeneratingTree<KG, V> {
         KG::Key: KV,
         V: KV,
     {
-        let key = self.key_generator.next_key();
+        let key = self.key_generator.next_key()?;
         let value = f(&key);
         let res = self.insert_with_key(&key, &value);
         res.map(|opt_v| (key, opt_v))
     }
 
-    pub fn transaction<F, A, E>(&self, f: F) -> TransactionResult<A, E>
+    pub fn transaction<F, A>(&self, f: F) -> TransactionResult<A>
     where
-        F: Fn(&KeyGeneratingTransactionalTree<KG, V>) -> ConflictableTransactionResult<A, E>,
+        F: Fn(&KeyGeneratingTransactionalTree<KG, V>) -> ConflictableTransactionResult<A>,
     {
         self.inner.transaction(|transactional_tree| {
             f(&KeyGeneratingTransactionalTree {
                 key_generator: self.key_generator(),
-                inner: transactional_tree,
+                inner: TransactionalTree::new(transactional_tree.inner, transactional_tree.tree),
             })
         })
     }
@@ -107,7 +140,7 @@ impl<KG: KeyGenerating<V>, V> KeyGeneratingTree<KG, V> {
         &self.key_generator
     }
 
-    pub fn new_batch(&self) -> KeyGeneratingBatch<KG, V> {
+    pub fn new_batch(&self) -> KeyGeneratingBatch<'_, KG, V> {
         KeyGeneratingBatch {
             key_generator: self.key_generator(),
             inner: Batch::default(),
@@ -131,12 +164,14 @@ impl<KG: KeyGenerating<V>, V> Deref for KeyGeneratingTree<KG, V> {
 /// for a typed_sled::Tree.
 ///
 /// See CounterTree for a specific example of how to use this trait.
-pub trait KeyGenerating<V> {
+pub trait KeyGenerating<V>: Sized {
     type Key;
 
-    fn initialize(tree: &Tree<Self::Key, V>) -> Self;
+    /// Initialize the key generator of `tree`, which was opened in `db`.
+    fn initialize(db: &sled::Db, tree: &Tree<Self::Key, V>) -> Result<Self>;
 
-    fn next_key(&self) -> Self::Key;
+    /// Generate the next key. Keys which were generated once must never be generated again.
+    fn next_key(&self) -> Result<Self::Key>;
 }
 
 #[derive(Clone, Debug)]
@@ -146,15 +181,15 @@ pub struct KeyGeneratingBatch<'a, KG: KeyGenerating<V>, V> {
 }
 
 impl<'a, KG: KeyGenerating<V, Key = K>, K, V> KeyGeneratingBatch<'a, KG, V> {
-    pub fn insert(&mut self, value: &V)
+    pub fn insert(&mut self, value: &V) -> Result<()>
     where
         K: KV,
         V: KV,
     {
-        self.inner.insert(&self.key_generator.next_key(), value);
+        self.inner.insert(&self.key_generator.next_key()?, value)
     }
 
-    pub fn remove(&mut self, key: &K)
+    pub fn remove(&mut self, key: &K) -> Result<()>
     where
         K: KV,
     {
@@ -165,57 +200,712 @@ impl<'a, KG: KeyGenerating<V, Key = K>, K, V> KeyGeneratingBatch<'a, KG, V> {
 /// A typed_sled::Tree with automatically generated and continuously increasing u64 keys.
 pub type CounterTree<V> = KeyGeneratingTree<Counter, V>;
 
+/// Generates the keys of a [CounterTree], continuing after the last key of the tree.
+///
+/// The counter only lives in memory, so the keys after the last key are generated again
+/// once the tree is reopened, even if they were removed before. Use [PersistentCounter]
+/// if keys must never be reused.
 #[derive(Debug, Clone)]
 pub struct Counter(Arc<AtomicU64>);
 
 impl<V: KV> KeyGenerating<V> for Counter {
     type Key = u64;
 
-    fn initialize(tree: &Tree<Self::Key, V>) -> Self {
-        if let Some((key, _)) = tree
-            .last()
-            .expect("KeyGenerating Counter failed to access sled Tree.")
-        {
-            Counter(Arc::new(AtomicU64::new(key + 1)))
-        } else {
-            Counter(Arc::new(AtomicU64::new(0)))
+    fn initialize(_db: &sled::Db, tree: &Tree<Self::Key, V>) -> Result<Self> {
+        let next = match tree.last()? {
+            Some((key, _)) => key_after(&tree.name(), key)?,
+            None => 0,
+        };
+        Ok(Counter(Arc::new(AtomicU64::new(next))))
+    }
+
+    fn next_key(&self) -> Result<Self::Key> {
+        Ok(self.0.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A typed_sled::Tree with automatically generated u64 keys which are never reused, see [PersistentCounter].
+pub type PersistentCounterTree<V, const BLOCK_SIZE: u64 = DEFAULT_BLOCK_SIZE> =
+    KeyGeneratingTree<PersistentCounter<BLOCK_SIZE>, V>;
+
+// The key a counter continues with after `key`, the largest key of `tree`.
+pub(crate) fn key_after(tree: &[u8], key: u64) -> Result<u64> {
+    key.checked_add(1)
+        .ok_or_else(|| crate::Error::KeysExhausted {
+            tree: String::from_utf8_lossy(tree).into_owned(),
+        })
+}
+
+/// The number of keys a [PersistentCounter] reserves at once by default.
+pub const DEFAULT_BLOCK_SIZE: u64 = 64;
+
+/// The name of the sled tree in which [PersistentCounter]s store the reserved keys of every tree.
+pub const KEY_GENERATOR_TREE: &str = "__typed_sled_key_generators";
+
+/// Generates increasing u64 keys which are never reused, not even after the last key
+/// was removed or the database was reopened.
+///
+/// The counter reserves blocks of `BLOCK_SIZE` keys by storing the end of the block in
+/// the [KEY_GENERATOR_TREE] and flushing it before the first key of the block is used.
+/// Larger blocks need fewer writes, but the unused keys of a block are skipped when the
+/// database is reopened. Keys generated for transactions which were aborted or retried
+/// are skipped as well, so there can be gaps between the keys. Once all keys below
+/// `u64::MAX` are used, generating a key fails with [Error::KeysExhausted][crate::Error::KeysExhausted].
+///
+/// If a tree which was created with another key generator is opened with a
+/// `PersistentCounter` for the first time, the counter continues after its last key.
+#[derive(Debug, Clone)]
+pub struct PersistentCounter<const BLOCK_SIZE: u64 = DEFAULT_BLOCK_SIZE>(Arc<Reservation>);
+
+impl<const BLOCK_SIZE: u64> PersistentCounter<BLOCK_SIZE> {
+    // `next` is only called if the key generator tree doesn't contain the tree yet.
+    pub(crate) fn open(
+        db: &sled::Db,
+        tree: sled::IVec,
+        next: impl FnOnce() -> Result<u64>,
+    ) -> Result<Self> {
+        let reserved = db.open_tree(KEY_GENERATOR_TREE)?;
+        if !reserved.contains_key(&tree)? {
+            let next = crate::encode_value(&next()?)?;
+            // If another counter initialized the tree concurrently its value is kept.
+            let _ = reserved.compare_and_swap(&tree, None as Option<&[u8]>, Some(next))?;
         }
+        Ok(Self(Arc::new(Reservation {
+            reserved,
+            tree,
+            block: Mutex::new(0..0),
+        })))
     }
 
-    fn next_key(&self) -> Self::Key {
-        self.0.fetch_add(1, Ordering::Relaxed)
+    pub(crate) fn next(&self) -> Result<u64> {
+        let mut block = self.0.block.lock().unwrap();
+        if block.is_empty() {
+            *block = self.0.reserve(BLOCK_SIZE)?;
+        }
+        let key = block.start;
+        block.start += 1;
+        Ok(key)
     }
 }
 
-pub struct KeyGeneratingTransactionalTree<'a, KG: KeyGenerating<V>, V> {
-    key_generator: &'a KG,
-    inner: &'a crate::transaction::TransactionalTree<'a, KG::Key, V>,
+impl<V: KV, const BLOCK_SIZE: u64> KeyGenerating<V> for PersistentCounter<BLOCK_SIZE> {
+    type Key = u64;
+
+    fn initialize(db: &sled::Db, tree: &Tree<Self::Key, V>) -> Result<Self> {
+        Self::open(db, tree.name(), || match tree.last()? {
+            Some((key, _)) => key_after(&tree.name(), key),
+            None => Ok(0),
+        })
+    }
+
+    fn next_key(&self) -> Result<Self::Key> {
+        self.next()
+    }
+}
+
+#[derive(Debug)]
+struct Reservation {
+    reserved: sled::Tree,
+    tree: sled::IVec,
+    block: Mutex<Range<u64>>,
+}
+
+impl Reservation {
+    fn reserve(&self, block_size: u64) -> Result<Range<u64>> {
+        loop {
+            let current = self.reserved.get(&self.tree)?;
+            let start: u64 = match &current {
+                Some(bytes) => {
+                    crate::decode_value(KEY_GENERATOR_TREE.as_bytes(), &self.tree, bytes)?
+                }
+                None => 0,
+            };
+            let end = start.saturating_add(block_size.max(1));
+            if start == end {
+                return Err(crate::Error::KeysExhausted {
+                    tree: String::from_utf8_lossy(&self.tree).into_owned(),
+                });
+            }
+            let cas = self.reserved.compare_and_swap(
+                &self.tree,
+                current,
+                Some(crate::encode_value(&end)?),
+            )?;
+            if cas.is_ok() {
+                self.reserved.flush()?;
+                return Ok(start..end);
+            }
+        }
+    }
+}
+
+/// A typed_sled::Tree with random UUID v4 keys. The keys aren't ordered, use [UuidV7Tree] for keys which are.
+#[cfg(feature = "uuid")]
+pub type UuidV4Tree<V> = KeyGeneratingTree<UuidV4, V>;
+
+/// Generates random UUID v4 keys.
+#[cfg(feature = "uuid")]
+#[derive(Debug, Clone, Copy)]
+pub struct UuidV4;
+
+#[cfg(feature = "uuid")]
+impl<V: KV> KeyGenerating<V> for UuidV4 {
+    type Key = Uuid;
+
+    fn initialize(_db: &sled::Db, _tree: &Tree<Self::Key, V>) -> Result<Self> {
+        Ok(UuidV4)
+    }
+
+    fn next_key(&self) -> Result<Self::Key> {
+        Ok(Uuid::new_v4())
+    }
+}
+
+/// A typed_sled::Tree with UUID v7 keys, which are ordered by their creation time.
+#[cfg(feature = "uuid")]
+pub type UuidV7Tree<V> = KeyGeneratingTree<UuidV7, V>;
+
+/// Generates UUID v7 keys, which start with the milliseconds since the unix epoch
+/// followed by random bits.
+///
+/// UUIDs are stored as their 16 bytes, so the keys are ordered by their creation time.
+/// The keys generated by one process are strictly increasing.
+#[cfg(feature = "uuid")]
+#[derive(Debug, Clone, Copy)]
+pub struct UuidV7;
+
+#[cfg(feature = "uuid")]
+impl<V: KV> KeyGenerating<V> for UuidV7 {
+    type Key = Uuid;
+
+    fn initialize(_db: &sled::Db, _tree: &Tree<Self::Key, V>) -> Result<Self> {
+        Ok(UuidV7)
+    }
+
+    fn next_key(&self) -> Result<Self::Key> {
+        Ok(Uuid::now_v7())
+    }
+}
+
+/// A typed_sled::Tree with ULID keys, which are ordered by their creation time.
+#[cfg(feature = "ulid")]
+pub type UlidTree<V> = KeyGeneratingTree<UlidGenerator, V>;
+
+/// Generates ULID keys, which start with the milliseconds since the unix epoch
+/// followed by random bits.
+///
+/// ULIDs are stored as their Crockford base32 string, which is ordered like the ULID,
+/// so the keys are ordered by their creation time. The keys generated by one
+/// generator are strictly increasing.
+#[cfg(feature = "ulid")]
+#[derive(Clone)]
+pub struct UlidGenerator(Arc<Mutex<ulid::Generator>>);
+
+#[cfg(feature = "ulid")]
+impl std::fmt::Debug for UlidGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UlidGenerator").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "ulid")]
+impl<V: KV> KeyGenerating<V> for UlidGenerator {
+    type Key = Ulid;
+
+    fn initialize(_db: &sled::Db, _tree: &Tree<Self::Key, V>) -> Result<Self> {
+        Ok(UlidGenerator(Arc::new(Mutex::new(ulid::Generator::new()))))
+    }
+
+    fn next_key(&self) -> Result<Self::Key> {
+        let mut generator = self.0.lock().unwrap();
+        loop {
+            match generator.generate() {
+                Ok(ulid) => return Ok(ulid),
+                // The random bits of this millisecond are used up.
+                Err(ulid::MonotonicError::Overflow) => std::thread::yield_now(),
+            }
+        }
+    }
+}
+
+/// A typed_sled::Tree with [Snowflake] keys, which are ordered by their creation time.
+#[cfg(feature = "snowflake")]
+pub type SnowflakeTree<V> = KeyGeneratingTree<SnowflakeGenerator, V>;
+
+/// The start of the timestamps of [Snowflake]s, 2020-01-01 in milliseconds since the unix epoch.
+#[cfg(feature = "snowflake")]
+pub const SNOWFLAKE_EPOCH: u64 = 1_577_836_800_000;
+
+/// The largest node id of a [Snowflake].
+#[cfg(feature = "snowflake")]
+pub const MAX_NODE_ID: u16 = (1 << SNOWFLAKE_NODE_ID_BITS) - 1;
+
+#[cfg(feature = "snowflake")]
+const SNOWFLAKE_NODE_ID_BITS: u32 = 10;
+#[cfg(feature = "snowflake")]
+const SNOWFLAKE_SEQUENCE_BITS: u32 = 12;
+#[cfg(feature = "snowflake")]
+const MAX_SEQUENCE: u16 = (1 << SNOWFLAKE_SEQUENCE_BITS) - 1;
+#[cfg(feature = "snowflake")]
+const SNOWFLAKE_NODE_ID_KEY: &str = "__typed_sled_snowflake_node_id";
+
+/// A 64 bit key made of a 42 bit timestamp in milliseconds since [SNOWFLAKE_EPOCH], a 10 bit
+/// node id and a 12 bit sequence number.
+///
+/// Snowflakes generated by different nodes never collide, so trees of several databases with
+/// different node ids can be merged.
+#[cfg(feature = "snowflake")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Snowflake(pub u64);
+
+#[cfg(feature = "snowflake")]
+impl Snowflake {
+    /// Create a snowflake from a timestamp in milliseconds since the unix epoch, a node id and a sequence number.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node id is larger than [MAX_NODE_ID] or the sequence number doesn't fit into 12 bits.
+    pub fn new(timestamp: u64, node_id: u16, sequence: u16) -> Self {
+        assert!(node_id <= MAX_NODE_ID, "node id {} is too large", node_id);
+        assert!(
+            sequence <= MAX_SEQUENCE,
+            "sequence {} is too large",
+            sequence
+        );
+        Snowflake(
+            (timestamp.saturating_sub(SNOWFLAKE_EPOCH)
+                << (SNOWFLAKE_NODE_ID_BITS + SNOWFLAKE_SEQUENCE_BITS))
+                | (u64::from(node_id) << SNOWFLAKE_SEQUENCE_BITS)
+                | u64::from(sequence),
+        )
+    }
+
+    /// The milliseconds since the unix epoch when the snowflake was created.
+    pub fn timestamp(&self) -> u64 {
+        (self.0 >> (SNOWFLAKE_NODE_ID_BITS + SNOWFLAKE_SEQUENCE_BITS)) + SNOWFLAKE_EPOCH
+    }
+
+    pub fn node_id(&self) -> u16 {
+        ((self.0 >> SNOWFLAKE_SEQUENCE_BITS) & u64::from(MAX_NODE_ID)) as u16
+    }
+
+    pub fn sequence(&self) -> u16 {
+        (self.0 & u64::from(MAX_SEQUENCE)) as u16
+    }
+}
+
+/// Generates [Snowflake] keys with the node id of the database, see [set_node_id][SnowflakeGenerator::set_node_id].
+///
+/// The keys generated by one generator are strictly increasing, even if the clock goes backwards.
+/// If more than 4096 keys are generated in one millisecond, the generator continues with the
+/// next millisecond.
+#[cfg(feature = "snowflake")]
+#[derive(Debug, Clone)]
+pub struct SnowflakeGenerator {
+    node_id: u16,
+    last: Arc<Mutex<Option<Snowflake>>>,
+}
+
+#[cfg(feature = "snowflake")]
+impl SnowflakeGenerator {
+    /// Store the node id used by the snowflake generators of all trees of `db`.
+    /// Every database whose trees are merged must have a different node id.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node id is larger than [MAX_NODE_ID].
+    pub fn set_node_id(db: &sled::Db, node_id: u16) -> Result<()> {
+        assert!(node_id <= MAX_NODE_ID, "node id {} is too large", node_id);
+        db.open_tree(KEY_GENERATOR_TREE)?
+            .insert(SNOWFLAKE_NODE_ID_KEY, crate::encode_value(&node_id)?)?;
+        Ok(())
+    }
+
+    /// The node id stored with [set_node_id][SnowflakeGenerator::set_node_id].
+    pub fn node_id(db: &sled::Db) -> Result<Option<u16>> {
+        db.open_tree(KEY_GENERATOR_TREE)?
+            .get(SNOWFLAKE_NODE_ID_KEY)?
+            .map(|bytes| {
+                crate::decode_value(
+                    KEY_GENERATOR_TREE.as_bytes(),
+                    SNOWFLAKE_NODE_ID_KEY.as_bytes(),
+                    &bytes,
+                )
+            })
+            .transpose()
+    }
+}
+
+#[cfg(feature = "snowflake")]
+impl<V: KV> KeyGenerating<V> for SnowflakeGenerator {
+    type Key = Snowflake;
+
+    /// Fails with [sled::Error::Unsupported] if no node id was set for `db`.
+    fn initialize(db: &sled::Db, tree: &Tree<Self::Key, V>) -> Result<Self> {
+        let node_id = Self::node_id(db)?.ok_or_else(|| {
+            sled::Error::Unsupported(
+                "no snowflake node id was set, see SnowflakeGenerator::set_node_id".to_owned(),
+            )
+        })?;
+        // Continue after the last key, even if the clock went backwards.
+        let last = tree
+            .last()?
+            .map(|(key, _)| Snowflake::new(key.timestamp(), node_id, MAX_SEQUENCE));
+        Ok(SnowflakeGenerator {
+            node_id,
+            last: Arc::new(Mutex::new(last)),
+        })
+    }
+
+    fn next_key(&self) -> Result<Self::Key> {
+        let mut last = self.last.lock().unwrap();
+        let now = crate::now_millis().max(SNOWFLAKE_EPOCH);
+        let key = match *last {
+            Some(last) if last.timestamp() >= now => {
+                if last.sequence() == MAX_SEQUENCE {
+                    Snowflake::new(last.timestamp() + 1, self.node_id, 0)
+                } else {
+                    Snowflake::new(last.timestamp(), self.node_id, last.sequence() + 1)
+                }
+            }
+            _ => Snowflake::new(now, self.node_id, 0),
+        };
+        *last = Some(key);
+        Ok(key)
+    }
+}
+
+/// A typed_sled::Tree with [TimestampKey]s, which are ordered by their creation time.
+#[cfg(feature = "timestamp-key")]
+pub type TimestampKeyTree<V> = KeyGeneratingTree<TimestampKeyGenerator, V>;
+
+/// A key made of the milliseconds since the unix epoch and a counter of the keys
+/// created in the same millisecond.
+///
+/// The fields are stored in this order, so the keys are ordered by their creation time.
+/// Keys generated for different trees can collide, use [Snowflake]s or ULIDs if
+/// trees are merged.
+#[cfg(feature = "timestamp-key")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TimestampKey {
+    pub timestamp: u64,
+    pub counter: u32,
+}
+
+/// Generates [TimestampKey]s, which are strictly increasing even if the clock goes backwards.
+/// When the tree is opened, the generator continues after its last key.
+#[cfg(feature = "timestamp-key")]
+#[derive(Debug, Clone)]
+pub struct TimestampKeyGenerator(Arc<Mutex<Option<TimestampKey>>>);
+
+#[cfg(feature = "timestamp-key")]
+impl<V: KV> KeyGenerating<V> for TimestampKeyGenerator {
+    type Key = TimestampKey;
+
+    fn initialize(_db: &sled::Db, tree: &Tree<Self::Key, V>) -> Result<Self> {
+        let last = tree.last()?.map(|(key, _)| key);
+        Ok(TimestampKeyGenerator(Arc::new(Mutex::new(last))))
+    }
+
+    fn next_key(&self) -> Result<Self::Key> {
+        let mut last = self.0.lock().unwrap();
+        let now = crate::now_millis();
+        let key = match *last {
+            Some(last) if last.timestamp >= now => match last.counter.checked_add(1) {
+                Some(counter) => TimestampKey {
+                    timestamp: last.timestamp,
+                    counter,
+                },
+                None => TimestampKey {
+                    timestamp: last.timestamp + 1,
+                    counter: 0,
+                },
+            },
+            _ => TimestampKey {
+                timestamp: now,
+                counter: 0,
+            },
+        };
+        *last = Some(key);
+        Ok(key)
+    }
+}
+
+/// The view of a [KeyGeneratingTree] inside of a transaction.
+///
+/// `'a` is the lifetime of the transaction and `'g` the lifetime of the key generator.
+pub struct KeyGeneratingTransactionalTree<'a, 'g, KG: KeyGenerating<V>, V> {
+    key_generator: &'g KG,
+    inner: TransactionalTree<'a, KG::Key, V>,
 }
 
-impl<'a, KG: KeyGenerating<V>, V> KeyGeneratingTransactionalTree<'a, KG, V> {
+impl<'a, 'g, KG: KeyGenerating<V>, V> KeyGeneratingTransactionalTree<'a, 'g, KG, V> {
+    /// Generate a key, e.g. to insert a value which refers to it with [insert_with_key][Self::insert_with_key].
+    /// Keys generated by a transaction which is retried or aborted aren't reused.
+    pub fn next_key(
+        &self,
+    ) -> std::result::Result<KG::Key, crate::transaction::UnabortableTransactionError> {
+        Ok(self.key_generator.next_key()?)
+    }
+
     pub fn insert(
         &self,
         value: &V,
-    ) -> std::result::Result<Option<V>, sled::transaction::UnabortableTransactionError>
+    ) -> std::result::Result<Option<V>, crate::transaction::UnabortableTransactionError>
+    where
+        KG::Key: KV,
+        V: KV,
+    {
+        self.inner.insert(&self.key_generator.next_key()?, value)
+    }
+
+    /// Insert a value at a key returned by [next_key][Self::next_key].
+    pub fn insert_with_key(
+        &self,
+        key: &KG::Key,
+        value: &V,
+    ) -> std::result::Result<Option<V>, crate::transaction::UnabortableTransactionError>
     where
         KG::Key: KV,
         V: KV,
     {
-        self.inner.insert(&self.key_generator.next_key(), value)
+        self.inner.insert(key, value)
     }
 
     pub fn apply_batch(
         &self,
         batch: &KeyGeneratingBatch<KG, V>,
-    ) -> std::result::Result<(), sled::transaction::UnabortableTransactionError> {
+    ) -> std::result::Result<(), crate::transaction::UnabortableTransactionError> {
         self.inner.apply_batch(&batch.inner)
     }
 }
 
-impl<'a, KG: KeyGenerating<V>, V> Deref for KeyGeneratingTransactionalTree<'a, KG, V> {
-    type Target = crate::transaction::TransactionalTree<'a, KG::Key, V>;
+impl<'a, 'g, KG: KeyGenerating<V>, V> Deref for KeyGeneratingTransactionalTree<'a, 'g, KG, V> {
+    type Target = TransactionalTree<'a, KG::Key, V>;
 
     fn deref(&self) -> &Self::Target {
-        self.inner
+        &self.inner
+    }
+}
+
+impl<'g, KG: KeyGenerating<V>, V> TransactionTarget for &'g KeyGeneratingTree<KG, V> {
+    type View<'a> = KeyGeneratingTransactionalTree<'a, 'g, KG, V>;
+
+    fn sled_tree(&self) -> &sled::Tree {
+        &self.inner.inner
     }
+
+    fn view<'a>(
+        &self,
+        tree: &'a sled::transaction::TransactionalTree,
+        name: &'a [u8],
+    ) -> Self::View<'a> {
+        KeyGeneratingTransactionalTree {
+            key_generator: &self.key_generator,
+            inner: TransactionalTree::new(tree, name),
+        }
+    }
+}
+
+#[test]
+fn test_try_open_reports_initialization_errors() {
+    let config = sled::Config::new().temporary(true);
+    let db = config.open().unwrap();
+
+    let tree = CounterTree::<u32>::try_open(&db, "test_tree").unwrap();
+    tree.insert(&1).unwrap();
+    assert_eq!(
+        CounterTree::<u32>::try_open(&db, "test_tree")
+            .unwrap()
+            .next_key()
+            .unwrap(),
+        1
+    );
+
+    // The counter is initialized from the last entry, which can't be deserialized.
+    db.open_tree("test_tree")
+        .unwrap()
+        .insert(crate::serialize_key(&1u64).unwrap(), &[1])
+        .unwrap();
+    assert!(matches!(
+        CounterTree::<u32>::try_open(&db, "test_tree"),
+        Err(crate::Error::Deserialize { .. })
+    ));
+}
+
+#[test]
+fn test_persistent_counter() {
+    let config = sled::Config::new().temporary(true);
+    let db = config.open().unwrap();
+
+    // Keys of trees which were created with a `Counter` aren't generated again.
+    let tree = CounterTree::<u32>::open(&db, "test_tree");
+    tree.insert(&1).unwrap();
+    tree.insert(&2).unwrap();
+
+    let tree = PersistentCounterTree::<u32, 4>::open(&db, "test_tree");
+    assert_eq!(tree.insert(&3).unwrap().0, 2);
+    assert_eq!(tree.insert(&4).unwrap().0, 3);
+    assert_eq!(tree.remove(&3).unwrap(), Some(4));
+
+    // The rest of the reserved block is skipped after reopening the tree.
+    let tree = PersistentCounterTree::<u32, 4>::open(&db, "test_tree");
+    let keys: Vec<u64> = (0..5).map(|i| tree.insert(&i).unwrap().0).collect();
+    assert_eq!(keys, vec![6, 7, 8, 9, 10]);
+
+    // Counters of the same tree never generate the same key.
+    let other = PersistentCounterTree::<u32, 4>::open(&db, "test_tree");
+    assert_eq!(other.next_key().unwrap(), 14);
+    assert_eq!(tree.next_key().unwrap(), 11);
+}
+
+#[test]
+fn test_persistent_counter_overflow() {
+    let config = sled::Config::new().temporary(true);
+    let db = config.open().unwrap();
+
+    db.open_tree(KEY_GENERATOR_TREE)
+        .unwrap()
+        .insert("test_tree", crate::encode_value(&(u64::MAX - 2)).unwrap())
+        .unwrap();
+    let tree = PersistentCounterTree::<u32, 4>::open(&db, "test_tree");
+    assert_eq!(tree.insert(&1).unwrap().0, u64::MAX - 2);
+    assert_eq!(tree.insert(&2).unwrap().0, u64::MAX - 1);
+    match tree.insert(&3) {
+        Err(crate::Error::KeysExhausted { tree }) => assert_eq!(tree, "test_tree"),
+        other => panic!("expected KeysExhausted, got {:?}", other),
+    }
+    assert_eq!(tree.len(), 2);
+}
+
+#[test]
+fn test_persistent_counter_survives_restart() {
+    let path = std::env::temp_dir().join(format!(
+        "typed_sled_persistent_counter_{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&path);
+    {
+        let db = sled::open(&path).unwrap();
+        let tree = PersistentCounterTree::<u32>::open(&db, "test_tree");
+        assert_eq!(tree.insert(&1).unwrap().0, 0);
+        tree.remove(&0).unwrap();
+    }
+    // sled releases the lock of the database in the background.
+    let start = std::time::Instant::now();
+    let db = loop {
+        match sled::open(&path) {
+            Ok(db) => break db,
+            Err(_) if start.elapsed() < std::time::Duration::from_secs(10) => {
+                std::thread::sleep(std::time::Duration::from_millis(10))
+            }
+            Err(e) => panic!("{}", e),
+        }
+    };
+    let tree = PersistentCounterTree::<u32>::open(&db, "test_tree");
+    assert_eq!(tree.insert(&1).unwrap().0, DEFAULT_BLOCK_SIZE);
+    drop(db);
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+// Generates keys in a tight loop, so several keys are created in the same millisecond.
+#[cfg(all(
+    test,
+    any(
+        feature = "uuid",
+        feature = "ulid",
+        feature = "snowflake",
+        feature = "timestamp-key"
+    )
+))]
+fn assert_sorted_by_creation<KG>(tree: &KeyGeneratingTree<KG, u32>)
+where
+    KG: KeyGenerating<u32>,
+    KG::Key: KV + Ord + Clone + std::fmt::Debug + Send + Sync,
+{
+    let keys: Vec<KG::Key> = (0..1000).map(|i| tree.insert(&i).unwrap().0).collect();
+    let stored: Vec<KG::Key> = tree.iter().keys().map(|key| key.unwrap()).collect();
+    assert_eq!(stored, keys);
+    let values: Vec<u32> = tree.iter().values().map(|value| value.unwrap()).collect();
+    assert_eq!(values, (0..1000).collect::<Vec<_>>());
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn test_uuid_keys() {
+    let config = sled::Config::new().temporary(true);
+    let db = config.open().unwrap();
+
+    assert_sorted_by_creation(&UuidV7Tree::open(&db, "v7"));
+
+    let tree = UuidV4Tree::<u32>::open(&db, "v4");
+    let (key, _) = tree.insert(&1).unwrap();
+    assert_eq!(key.get_version_num(), 4);
+    assert_eq!(tree.get(&key).unwrap(), Some(1));
+}
+
+#[cfg(feature = "ulid")]
+#[test]
+fn test_ulid_keys() {
+    let config = sled::Config::new().temporary(true);
+    let db = config.open().unwrap();
+
+    assert_sorted_by_creation(&UlidTree::open(&db, "test_tree"));
+}
+
+#[cfg(feature = "snowflake")]
+#[test]
+fn test_snowflake_keys() {
+    let config = sled::Config::new().temporary(true);
+    let db = config.open().unwrap();
+
+    assert!(SnowflakeTree::<u32>::try_open(&db, "test_tree").is_err());
+    SnowflakeGenerator::set_node_id(&db, 7).unwrap();
+    assert_eq!(SnowflakeGenerator::node_id(&db).unwrap(), Some(7));
+
+    let tree = SnowflakeTree::open(&db, "test_tree");
+    assert_sorted_by_creation(&tree);
+
+    let key = tree.next_key().unwrap();
+    assert_eq!(key.node_id(), 7);
+    assert!(key.timestamp() >= crate::now_millis() - 1000);
+    let snowflake = Snowflake::new(SNOWFLAKE_EPOCH + 42, MAX_NODE_ID, 3);
+    assert_eq!(
+        (
+            snowflake.timestamp(),
+            snowflake.node_id(),
+            snowflake.sequence()
+        ),
+        (SNOWFLAKE_EPOCH + 42, MAX_NODE_ID, 3)
+    );
+
+    // Keys of a reopened tree continue after the last key, even if it is in the future.
+    let future = Snowflake::new(crate::now_millis() + 60_000, 3, 0);
+    tree.insert_with_key(&future, &0).unwrap();
+    let tree = SnowflakeTree::<u32>::open(&db, "test_tree");
+    assert!(tree.next_key().unwrap() > future);
+}
+
+#[cfg(feature = "timestamp-key")]
+#[test]
+fn test_timestamp_keys() {
+    let config = sled::Config::new().temporary(true);
+    let db = config.open().unwrap();
+
+    let tree = TimestampKeyTree::open(&db, "test_tree");
+    assert_sorted_by_creation(&tree);
+
+    let future = TimestampKey {
+        timestamp: crate::now_millis() + 60_000,
+        counter: u32::MAX,
+    };
+    tree.insert_with_key(&future, &0).unwrap();
+    let tree = TimestampKeyTree::<u32>::open(&db, "test_tree");
+    assert_eq!(
+        tree.next_key().unwrap(),
+        TimestampKey {
+            timestamp: future.timestamp + 1,
+            counter: 0
+        }
+    );
 }
@@ -7,10 +7,23 @@
 //! # features
 //! Multiple features for common use cases are also available:
 //! * [search]: `SearchEngine` on top of a `Tree`.
+//! * [searchable]: Derive the search schema and documents from the value type.
 //! * [key_generating]: Create `Tree`s with automatically generated keys.
 //! * [convert]: Convert any `Tree` into another `Tree` with different key and value types.
 //! * [custom_serde]: Create `Tree`s with custom (de)serialization. This for example makes
-//!                   lazy or zero-copy (de)serialization possible.
+//!   lazy or zero-copy (de)serialization possible.
+//! * [db]: A typed `Db` which detects trees opened with mismatching types.
+//! * [versioned]: Store values with a version tag and upgrade old values on read.
+//! * [indexed]: `IndexedTree` which maintains secondary indexes in the same transaction as the tree.
+//! * [ttl]: `TtlTree` whose entries expire after a time-to-live.
+//! * [multi_tree]: `MultiTree` which stores an ordered set of values per key.
+//! * [queue]: Persistent FIFO and priority queues with leases.
+//! * [merge]: Ready-made merge operators for counters, lists, sets, max, min and last-writer-wins.
+//!
+//! # Key ordering
+//! Keys are serialized with an order-preserving encoding (see [key_codec]), so iterating
+//! over a `Tree` as well as `range`, `first`, `last`, `get_lt`, `get_gt`, `pop_min` and
+//! `pop_max` follow the `Ord` implementation of the key type. Values are serialized with bincode.
 //!
 //! # Example
 //! ```
@@ -35,16 +48,92 @@
 //! ```
 //! [sled]: https://docs.rs/sled/latest/sled/
 
+// Lets the derive macros refer to `::typed_sled` from within this crate.
+extern crate self as typed_sled;
+
+pub use db::Db;
 pub use sled::{open, Config};
-use transaction::TransactionalTree;
+use transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+    TransactionResult, TransactionalTree,
+};
+
+/// Checks `range` of a tree with `i32` keys and `u32` values against a
+/// [BTreeMap][std::collections::BTreeMap] for all combinations of bounds, forwards and backwards.
+#[cfg(test)]
+macro_rules! check_range_bounds {
+    ($tree:expr) => {{
+        use std::collections::BTreeMap;
+        use std::ops::Bound;
+
+        let tree = &$tree;
+        let mut map = BTreeMap::new();
+        for k in (-6..=6).step_by(2) {
+            tree.insert(&k, &(k as u32)).unwrap();
+            map.insert(k, k as u32);
+        }
+
+        let mut bounds = vec![Bound::Unbounded];
+        for k in -8..=8 {
+            bounds.push(Bound::Included(k));
+            bounds.push(Bound::Excluded(k));
+        }
+
+        for start in &bounds {
+            for end in &bounds {
+                let range = (*start, *end);
+                let forward: Vec<_> = tree.range(range).unwrap().map(|r| r.unwrap()).collect();
+                let backward: Vec<_> = tree
+                    .range(range)
+                    .unwrap()
+                    .rev()
+                    .map(|r| r.unwrap())
+                    .collect();
+
+                let expected: Vec<(i32, u32)> = match (start, end) {
+                    // BTreeMap::range panics on these, sled returns an empty range.
+                    (
+                        Bound::Included(s) | Bound::Excluded(s),
+                        Bound::Included(e) | Bound::Excluded(e),
+                    ) if s > e => {
+                        vec![]
+                    }
+                    (Bound::Excluded(s), Bound::Excluded(e)) if s == e => vec![],
+                    _ => map.range(range).map(|(k, v)| (*k, *v)).collect(),
+                };
+                let mut expected_rev = expected.clone();
+                expected_rev.reverse();
+
+                assert_eq!(forward, expected, "forward {:?}", range);
+                assert_eq!(backward, expected_rev, "backward {:?}", range);
+            }
+        }
+    }};
+}
 
 #[cfg(feature = "convert")]
 pub mod convert;
+pub mod db;
+#[cfg(feature = "indexed")]
+pub mod indexed;
+pub mod key_codec;
 #[cfg(feature = "key-generating")]
 pub mod key_generating;
+#[cfg(feature = "merge")]
+pub mod merge;
+#[cfg(feature = "multi-tree")]
+pub mod multi_tree;
+#[cfg(feature = "queue")]
+pub mod queue;
 #[cfg(feature = "search")]
 pub mod search;
+#[cfg(feature = "search")]
+pub mod searchable;
 pub mod transaction;
+#[cfg(feature = "ttl")]
+pub mod ttl;
+#[cfg(feature = "versioned")]
+pub mod versioned;
 
 pub mod custom_serde;
 
@@ -52,10 +141,7 @@ use core::fmt;
 use core::iter::{DoubleEndedIterator, Iterator};
 use core::ops::{Bound, RangeBounds};
 use serde::Serialize;
-use sled::{
-    transaction::{ConflictableTransactionResult, TransactionResult},
-    IVec, Result,
-};
+use sled::IVec;
 use std::marker::PhantomData;
 
 // pub trait Bin = DeserializeOwned + Serialize + Clone + Send + Sync;
@@ -118,6 +204,84 @@ pub trait KV: serde::de::DeserializeOwned + Serialize {}
 
 impl<T: serde::de::DeserializeOwned + Serialize> KV for T {}
 
+/// The result type returned by typed-sled.
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// The error type of the (de)serializers used by typed-sled.
+pub type SerdeError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Errors returned by typed-sled.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// An error of the underlying sled database.
+    #[error("sled error: {0}")]
+    Sled(#[from] sled::Error),
+    /// A key or value could not be serialized.
+    #[error("serialization failed: {0}")]
+    Serialize(#[source] SerdeError),
+    /// A key or value read from the database could not be deserialized.
+    /// This usually happens if the type stored in a tree has changed.
+    #[error(
+        "deserialization of the entry with key {key_bytes:?} in tree {tree:?} failed: {source}"
+    )]
+    Deserialize {
+        /// The name of the tree the entry was read from.
+        tree: String,
+        /// The serialized key of the entry.
+        key_bytes: Vec<u8>,
+        #[source]
+        source: SerdeError,
+    },
+    /// A tree was opened through a [Db] with types that don't match the registered ones.
+    #[error("tree {tree:?} is registered as {stored} but was opened as {requested}")]
+    TypeMismatch {
+        /// The name of the tree.
+        tree: String,
+        /// The registered types.
+        stored: Box<db::TreeMeta>,
+        /// The types the tree was opened with.
+        requested: Box<db::TreeMeta>,
+    },
+    /// A write would have added a second entry to a unique index.
+    #[error("unique index {index:?} already contains the key {index_key:?}")]
+    UniqueViolation {
+        /// The name of the index.
+        index: String,
+        /// The serialized index key.
+        index_key: Vec<u8>,
+    },
+    /// An index was used that isn't registered, or is registered with a different key type.
+    #[error("no index {index:?} with the requested key type")]
+    UnknownIndex {
+        /// The name of the index.
+        index: String,
+    },
+    /// A key generator ran out of keys.
+    #[error("the key generator of tree {tree:?} has no keys left")]
+    KeysExhausted {
+        /// The name of the tree.
+        tree: String,
+    },
+}
+
+impl Error {
+    pub(crate) fn serialize(source: impl Into<SerdeError>) -> Self {
+        Error::Serialize(source.into())
+    }
+
+    pub(crate) fn deserialize(
+        tree: &[u8],
+        key_bytes: &[u8],
+        source: impl Into<SerdeError>,
+    ) -> Self {
+        Error::Deserialize {
+            tree: String::from_utf8_lossy(tree).into_owned(),
+            key_bytes: key_bytes.to_vec(),
+            source: source.into(),
+        }
+    }
+}
+
 /// Compare and swap error.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct CompareAndSwapError<V> {
@@ -161,12 +325,23 @@ impl<K, V> Tree<K, V> {
     ///     Ok(())
     /// }
     /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if sled fails to open the tree. Use [try_open][Tree::try_open]
+    /// to handle the error instead.
     pub fn open<T: AsRef<str>>(db: &sled::Db, id: T) -> Self {
-        Self {
-            inner: db.open_tree(id.as_ref()).unwrap(),
+        Self::try_open(db, id).unwrap()
+    }
+
+    /// Initialize a typed tree like [open][Tree::open], but return an
+    /// error instead of panicking if sled fails to open the tree.
+    pub fn try_open<T: AsRef<str>>(db: &sled::Db, id: T) -> Result<Self> {
+        Ok(Self {
+            inner: db.open_tree(id.as_ref())?,
             _key: PhantomData,
             _value: PhantomData,
-        }
+        })
     }
 
     /// Insert a key to a new value, returning the last value if it was set.
@@ -175,26 +350,33 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .insert(serialize(key), serialize(value))
-            .map(|opt| opt.map(|old_value| deserialize(&old_value)))
+        let key_bytes = encode_key(key)?;
+        let old_value = self.inner.insert(&key_bytes, encode_value(value)?)?;
+        self.decode_opt_value(&key_bytes, old_value)
     }
 
     /// Perform a multi-key serializable transaction.
-    pub fn transaction<F, A, E>(&self, f: F) -> TransactionResult<A, E>
+    ///
+    /// Errors of the [TransactionalTree] can be returned with `?` and the
+    /// transaction can be aborted with [transaction::abort].
+    pub fn transaction<F, A>(&self, f: F) -> TransactionResult<A>
     where
-        F: Fn(&TransactionalTree<K, V>) -> ConflictableTransactionResult<A, E>,
+        F: Fn(&TransactionalTree<K, V>) -> ConflictableTransactionResult<A>,
     {
-        self.inner.transaction(|sled_transactional_tree| {
-            f(&TransactionalTree::new(sled_transactional_tree))
-        })
+        let name = self.inner.name();
+        self.inner
+            .transaction(|sled_transactional_tree| {
+                f(&TransactionalTree::new(sled_transactional_tree, &name))
+                    .map_err(ConflictableTransactionError::into_sled)
+            })
+            .map_err(TransactionError::from_sled)
     }
 
     /// Create a new batched update that can be atomically applied.
     ///
     /// It is possible to apply a Batch in a transaction as well, which is the way you can apply a Batch to multiple Trees atomically.
     pub fn apply_batch(&self, batch: Batch<K, V>) -> Result<()> {
-        self.inner.apply_batch(batch.inner)
+        Ok(self.inner.apply_batch(batch.inner)?)
     }
 
     /// Retrieve a value from the Tree if it exists.
@@ -203,9 +385,7 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .get(serialize(key))
-            .map(|opt| opt.map(|v| deserialize(&v)))
+        self.get_from_raw(encode_key(key)?)
     }
 
     /// Retrieve a value from the Tree if it exists. The key must be in serialized form.
@@ -214,9 +394,8 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .get(key_bytes.as_ref())
-            .map(|opt| opt.map(|v| deserialize(&v)))
+        let value = self.inner.get(key_bytes.as_ref())?;
+        self.decode_opt_value(key_bytes.as_ref(), value)
     }
 
     /// Deserialize a key and retrieve it's value from the Tree if it exists.
@@ -227,8 +406,9 @@ impl<K, V> Tree<K, V> {
         V: KV,
     {
         self.inner
-            .get(key_bytes.as_ref())
-            .map(|opt| opt.map(|v| (deserialize(key_bytes.as_ref()), deserialize(&v))))
+            .get(key_bytes.as_ref())?
+            .map(|v| decode_kv(&self.inner.name(), key_bytes.as_ref(), &v))
+            .transpose()
     }
 
     /// Delete a value, returning the old value if it existed.
@@ -237,9 +417,9 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .remove(serialize(key))
-            .map(|opt| opt.map(|v| deserialize(&v)))
+        let key_bytes = encode_key(key)?;
+        let old_value = self.inner.remove(&key_bytes)?;
+        self.decode_opt_value(&key_bytes, old_value)
     }
 
     /// Compare and swap. Capable of unique creation, conditional modification, or deletion. If old is None, this will only set the value if it doesn't exist yet. If new is None, will delete the value if old is correct. If both old and new are Some, will modify the value if old is correct.
@@ -257,21 +437,25 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .compare_and_swap(
-                serialize(key),
-                old.map(|old| serialize(old)),
-                new.map(|new| serialize(new)),
-            )
-            .map(|cas_res| {
-                cas_res.map_err(|cas_err| CompareAndSwapError {
-                    current: cas_err.current.as_ref().map(|b| deserialize(b)),
-                    proposed: cas_err.proposed.as_ref().map(|b| deserialize(b)),
-                })
-            })
+        let key_bytes = encode_key(key)?;
+        let cas_res = self.inner.compare_and_swap(
+            &key_bytes,
+            old.map(encode_value).transpose()?,
+            new.map(encode_value).transpose()?,
+        )?;
+        match cas_res {
+            Ok(()) => Ok(Ok(())),
+            Err(cas_err) => Ok(Err(CompareAndSwapError {
+                current: self.decode_opt_value(&key_bytes, cas_err.current)?,
+                proposed: self.decode_opt_value(&key_bytes, cas_err.proposed)?,
+            })),
+        }
     }
 
     /// Fetch the value, apply a function to it and return the result.
+    ///
+    /// If the stored value can't be deserialized or the new value can't be serialized,
+    /// the stored value is left untouched and the error is returned.
     // not sure if implemented correctly (different trait bound for F)
     pub fn update_and_fetch<F>(&self, key: &K, mut f: F) -> Result<Option<V>>
     where
@@ -279,14 +463,22 @@ impl<K, V> Tree<K, V> {
         V: KV,
         F: FnMut(Option<V>) -> Option<V>,
     {
-        self.inner
-            .update_and_fetch(serialize(&key), |opt_value| {
-                f(opt_value.map(|v| deserialize(v))).map(|v| serialize(&v))
-            })
-            .map(|res| res.map(|v| deserialize(&v)))
+        let key_bytes = encode_key(key)?;
+        let name = self.inner.name();
+        let mut error = None;
+        let res = self.inner.update_and_fetch(&key_bytes, |opt_value| {
+            update(&name, &key_bytes, opt_value, &mut f, &mut error)
+        })?;
+        match error {
+            Some(e) => Err(e),
+            None => self.decode_opt_value(&key_bytes, res),
+        }
     }
 
     /// Fetch the value, apply a function to it and return the previous value.
+    ///
+    /// If the stored value can't be deserialized or the new value can't be serialized,
+    /// the stored value is left untouched and the error is returned.
     // not sure if implemented correctly (different trait bound for F)
     pub fn fetch_and_update<F>(&self, key: &K, mut f: F) -> Result<Option<V>>
     where
@@ -294,11 +486,16 @@ impl<K, V> Tree<K, V> {
         V: KV,
         F: FnMut(Option<V>) -> Option<V>,
     {
-        self.inner
-            .fetch_and_update(serialize(key), |opt_value| {
-                f(opt_value.map(|v| deserialize(v))).map(|v| serialize(&v))
-            })
-            .map(|res| res.map(|v| deserialize(&v)))
+        let key_bytes = encode_key(key)?;
+        let name = self.inner.name();
+        let mut error = None;
+        let res = self.inner.fetch_and_update(&key_bytes, |opt_value| {
+            update(&name, &key_bytes, opt_value, &mut f, &mut error)
+        })?;
+        match error {
+            Some(e) => Err(e),
+            None => self.decode_opt_value(&key_bytes, res),
+        }
     }
 
     /// Subscribe to `Event`s that happen to keys that have
@@ -310,11 +507,14 @@ impl<K, V> Tree<K, V> {
     /// to block. There is a buffer of 1024 items per
     /// `Subscriber`. This can be used to build reactive
     /// and replicated systems.
-    pub fn watch_prefix(&self, prefix: &K) -> Subscriber<K, V>
+    pub fn watch_prefix(&self, prefix: &K) -> Result<Subscriber<K, V>>
     where
         K: KV,
     {
-        Subscriber::from_sled(self.inner.watch_prefix(serialize(prefix)))
+        Ok(Subscriber::from_sled(
+            self.inner.watch_prefix(encode_key(prefix)?),
+            self.inner.name(),
+        ))
     }
 
     /// Subscribe to  all`Event`s. Events for particular keys are
@@ -329,7 +529,7 @@ impl<K, V> Tree<K, V> {
     where
         K: KV,
     {
-        Subscriber::from_sled(self.inner.watch_prefix(vec![]))
+        Subscriber::from_sled(self.inner.watch_prefix(vec![]), self.inner.name())
     }
 
     /// Synchronously flushes all dirty IO buffers and calls
@@ -343,7 +543,7 @@ impl<K, V> Tree<K, V> {
     /// realistic sustained workloads running on realistic
     /// hardware.
     pub fn flush(&self) -> Result<usize> {
-        self.inner.flush()
+        Ok(self.inner.flush()?)
     }
 
     /// Asynchronously flushes all dirty IO buffers
@@ -357,7 +557,7 @@ impl<K, V> Tree<K, V> {
     /// using it on realistic sustained workloads
     /// running on realistic hardware.
     pub async fn flush_async(&self) -> Result<usize> {
-        self.inner.flush_async().await
+        Ok(self.inner.flush_async().await?)
     }
 
     /// Returns `true` if the `Tree` contains a value for
@@ -366,7 +566,7 @@ impl<K, V> Tree<K, V> {
     where
         K: KV,
     {
-        self.inner.contains_key(serialize(key))
+        Ok(self.inner.contains_key(encode_key(key)?)?)
     }
 
     /// Retrieve the key and value before the provided key,
@@ -376,9 +576,8 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .get_lt(serialize(key))
-            .map(|res| res.map(|(k, v)| (deserialize(&k), deserialize(&v))))
+        let kv = self.inner.get_lt(encode_key(key)?)?;
+        self.decode_opt_kv(kv)
     }
 
     /// Retrieve the next key and value from the `Tree` after the
@@ -388,9 +587,8 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .get_gt(serialize(key))
-            .map(|res| res.map(|(k, v)| (deserialize(&k), deserialize(&v))))
+        let kv = self.inner.get_gt(encode_key(key)?)?;
+        self.decode_opt_kv(kv)
     }
 
     /// Merge state directly into a given key's value using the
@@ -405,14 +603,19 @@ impl<K, V> Tree<K, V> {
     /// Merge operators are shared by all instances of a particular
     /// `Tree`. Different merge operators may be set on different
     /// `Tree`s.
+    ///
+    /// Returns [Error::Deserialize] without merging if the stored value
+    /// can't be deserialized, as the merge operator would drop the value.
     pub fn merge(&self, key: &K, value: &V) -> Result<Option<V>>
     where
         K: KV,
         V: KV,
     {
-        self.inner
-            .merge(serialize(key), serialize(value))
-            .map(|res| res.map(|old_v| deserialize(&old_v)))
+        let key_bytes = encode_key(key)?;
+        let value_bytes = encode_value(value)?;
+        self.decode_opt_value(&key_bytes, self.inner.get(&key_bytes)?)?;
+        let old_value = self.inner.merge(&key_bytes, value_bytes)?;
+        self.decode_opt_value(&key_bytes, old_value)
     }
 
     // TODO: implement using own MergeOperator trait
@@ -424,6 +627,12 @@ impl<K, V> Tree<K, V> {
     /// Merge operators can be used to implement arbitrary data
     /// structures.
     ///
+    /// sled doesn't allow merge operators to fail, so if the key, the
+    /// stored value or the merged value can't be (de)serialized, the
+    /// stored value is left untouched. [merge][Tree::merge] checks the
+    /// stored value beforehand, so this only happens if the value is
+    /// replaced concurrently.
+    ///
     /// # Panics
     ///
     /// Calling `merge` will panic if no merge operator has been
@@ -435,66 +644,47 @@ impl<K, V> Tree<K, V> {
     {
         self.inner
             .set_merge_operator(move |key: &[u8], old_v: Option<&[u8]>, value: &[u8]| {
-                let opt_v = merge_operator(
-                    deserialize(key),
-                    old_v.map(|v| deserialize(v)),
-                    deserialize(value),
-                );
-                opt_v.map(|v| serialize(&v))
+                let merge = || -> core::result::Result<Option<Vec<u8>>, SerdeError> {
+                    let opt_v = merge_operator(
+                        deserialize_key(key)?,
+                        old_v.map(|v| deserialize(v)).transpose()?,
+                        deserialize(value)?,
+                    );
+                    Ok(opt_v.map(|v| serialize(&v)).transpose()?)
+                };
+                merge().unwrap_or_else(|_| old_v.map(|v| v.to_vec()))
             });
     }
 
     /// Create a double-ended iterator over the tuples of keys and
     /// values in this tree.
     pub fn iter(&self) -> Iter<K, V> {
-        Iter::from_sled(self.inner.iter())
+        Iter::from_sled(self.inner.iter(), self.inner.name())
     }
 
     /// Create a double-ended iterator over tuples of keys and values,
     /// where the keys fall within the specified range.
-    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<K, V>
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Result<Iter<K, V>>
     where
         K: KV + std::fmt::Debug,
     {
-        match (range.start_bound(), range.end_bound()) {
-            (Bound::Unbounded, Bound::Unbounded) => {
-                Iter::from_sled(self.inner.range::<&[u8], _>(..))
-            }
-            (Bound::Unbounded, Bound::Excluded(b)) => {
-                Iter::from_sled(self.inner.range(..serialize(b)))
-            }
-            (Bound::Unbounded, Bound::Included(b)) => {
-                Iter::from_sled(self.inner.range(..=serialize(b)))
-            }
-            // FIX: This is not excluding lower bound.
-            (Bound::Excluded(b), Bound::Unbounded) => {
-                Iter::from_sled(self.inner.range(serialize(b)..))
-            }
-            (Bound::Excluded(b), Bound::Excluded(bb)) => {
-                Iter::from_sled(self.inner.range(serialize(b)..serialize(bb)))
-            }
-            (Bound::Excluded(b), Bound::Included(bb)) => {
-                Iter::from_sled(self.inner.range(serialize(b)..=serialize(bb)))
-            }
-            (Bound::Included(b), Bound::Unbounded) => {
-                Iter::from_sled(self.inner.range(serialize(b)..))
-            }
-            (Bound::Included(b), Bound::Excluded(bb)) => {
-                Iter::from_sled(self.inner.range(serialize(b)..serialize(bb)))
-            }
-            (Bound::Included(b), Bound::Included(bb)) => {
-                Iter::from_sled(self.inner.range(serialize(b)..=serialize(bb)))
-            }
-        }
+        let iter = self.inner.range((
+            encode_bound(range.start_bound())?,
+            encode_bound(range.end_bound())?,
+        ));
+        Ok(Iter::from_sled(iter, self.inner.name()))
     }
 
     /// Create an iterator over tuples of keys and values,
     /// where the all the keys starts with the given prefix.
-    pub fn scan_prefix(&self, prefix: &K) -> Iter<K, V>
+    pub fn scan_prefix(&self, prefix: &K) -> Result<Iter<K, V>>
     where
         K: KV,
     {
-        Iter::from_sled(self.inner.scan_prefix(serialize(prefix)))
+        Ok(Iter::from_sled(
+            self.inner.scan_prefix(encode_key(prefix)?),
+            self.inner.name(),
+        ))
     }
 
     /// Returns the first key and value in the `Tree`, or
@@ -504,9 +694,8 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .first()
-            .map(|res| res.map(|(k, v)| (deserialize(&k), deserialize(&v))))
+        let kv = self.inner.first()?;
+        self.decode_opt_kv(kv)
     }
 
     /// Returns the last key and value in the `Tree`, or
@@ -516,9 +705,8 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .last()
-            .map(|res| res.map(|(k, v)| (deserialize(&k), deserialize(&v))))
+        let kv = self.inner.last()?;
+        self.decode_opt_kv(kv)
     }
 
     /// Atomically removes the maximum item in the `Tree` instance.
@@ -527,9 +715,8 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .pop_max()
-            .map(|res| res.map(|(k, v)| (deserialize(&k), deserialize(&v))))
+        let kv = self.inner.pop_max()?;
+        self.decode_opt_kv(kv)
     }
 
     /// Atomically removes the minimum item in the `Tree` instance.
@@ -538,9 +725,8 @@ impl<K, V> Tree<K, V> {
         K: KV,
         V: KV,
     {
-        self.inner
-            .pop_min()
-            .map(|res| res.map(|(k, v)| (deserialize(&k), deserialize(&v))))
+        let kv = self.inner.pop_min()?;
+        self.decode_opt_kv(kv)
     }
 
     /// Returns the number of elements in this tree.
@@ -557,7 +743,7 @@ impl<K, V> Tree<K, V> {
     ///
     /// Note that this is not atomic.
     pub fn clear(&self) -> Result<()> {
-        self.inner.clear()
+        Ok(self.inner.clear()?)
     }
 
     /// Returns the name of the tree.
@@ -571,7 +757,50 @@ impl<K, V> Tree<K, V> {
     /// This is O(N) and locks the underlying tree
     /// for the duration of the entire scan.
     pub fn checksum(&self) -> Result<u32> {
-        self.inner.checksum()
+        Ok(self.inner.checksum()?)
+    }
+
+    fn decode_opt_value(&self, key_bytes: &[u8], value: Option<IVec>) -> Result<Option<V>>
+    where
+        V: KV,
+    {
+        value
+            .map(|v| decode_value(&self.inner.name(), key_bytes, &v))
+            .transpose()
+    }
+
+    fn decode_opt_kv(&self, kv: Option<(IVec, IVec)>) -> Result<Option<(K, V)>>
+    where
+        K: KV,
+        V: KV,
+    {
+        kv.map(|(k, v)| decode_kv(&self.inner.name(), &k, &v))
+            .transpose()
+    }
+}
+
+/// Runs the user supplied function of `update_and_fetch` and `fetch_and_update`.
+/// sled doesn't allow the function to fail, so on errors the stored value is
+/// returned unchanged and the error is stored in `error`.
+fn update<V: KV>(
+    tree: &[u8],
+    key_bytes: &[u8],
+    opt_value: Option<&[u8]>,
+    f: &mut impl FnMut(Option<V>) -> Option<V>,
+    error: &mut Option<Error>,
+) -> Option<Vec<u8>> {
+    // sled may call the function multiple times, only the last call counts.
+    *error = None;
+    let res = opt_value
+        .map(|v| decode_value(tree, key_bytes, v))
+        .transpose()
+        .and_then(|old| f(old).map(|new| encode_value(&new)).transpose());
+    match res {
+        Ok(new) => new,
+        Err(e) => {
+            *error = Some(e);
+            opt_value.map(|v| v.to_vec())
+        }
     }
 }
 
@@ -626,6 +855,7 @@ impl<K, V, F> MergeOperator<K, V> for F where F: Fn(K, Option<V>, V) -> Option<V
 
 pub struct Iter<K, V> {
     inner: sled::Iter,
+    tree: IVec,
     _key: PhantomData<fn() -> K>,
     _value: PhantomData<fn() -> V>,
 }
@@ -634,30 +864,33 @@ impl<K: KV, V: KV> Iterator for Iter<K, V> {
     type Item = Result<(K, V)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner
-            .next()
-            .map(|res| res.map(|(k, v)| (deserialize(&k), deserialize(&v))))
+        self.inner.next().map(|res| {
+            let (k, v) = res?;
+            decode_kv(&self.tree, &k, &v)
+        })
     }
 
     fn last(mut self) -> Option<Self::Item> {
-        self.inner
-            .next_back()
-            .map(|res| res.map(|(k, v)| (deserialize(&k), deserialize(&v))))
+        self.next_back()
     }
 }
 
 impl<K: KV, V: KV> DoubleEndedIterator for Iter<K, V> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.inner
-            .next_back()
-            .map(|res| res.map(|(k, v)| (deserialize(&k), deserialize(&v))))
+        self.inner.next_back().map(|res| {
+            let (k, v) = res?;
+            decode_kv(&self.tree, &k, &v)
+        })
     }
 }
 
 impl<K, V> Iter<K, V> {
-    pub fn from_sled(iter: sled::Iter) -> Self {
+    /// Wrap a `sled::Iter` over the tree with the given name.
+    /// The name is only used for error reporting.
+    pub fn from_sled(iter: sled::Iter, tree: IVec) -> Self {
         Iter {
             inner: iter,
+            tree,
             _key: PhantomData,
             _value: PhantomData,
         }
@@ -689,19 +922,21 @@ pub struct Batch<K, V> {
 }
 
 impl<K, V> Batch<K, V> {
-    pub fn insert(&mut self, key: &K, value: &V)
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<()>
     where
         K: KV,
         V: KV,
     {
-        self.inner.insert(serialize(key), serialize(value));
+        self.inner.insert(encode_key(key)?, encode_value(value)?);
+        Ok(())
     }
 
-    pub fn remove(&mut self, key: &K)
+    pub fn remove(&mut self, key: &K) -> Result<()>
     where
         K: KV,
     {
-        self.inner.remove(serialize(key))
+        self.inner.remove(encode_key(key)?);
+        Ok(())
     }
 }
 
@@ -721,6 +956,7 @@ use pin_project::pin_project;
 pub struct Subscriber<K, V> {
     #[pin]
     inner: sled::Subscriber,
+    tree: IVec,
     _key: PhantomData<fn() -> K>,
     _value: PhantomData<fn() -> V>,
 }
@@ -729,19 +965,22 @@ impl<K, V> Subscriber<K, V> {
     pub fn next_timeout(
         &mut self,
         timeout: core::time::Duration,
-    ) -> core::result::Result<Event<K, V>, std::sync::mpsc::RecvTimeoutError>
+    ) -> core::result::Result<Result<Event<K, V>>, std::sync::mpsc::RecvTimeoutError>
     where
         K: KV,
         V: KV,
     {
         self.inner
             .next_timeout(timeout)
-            .map(|e| Event::from_sled(&e))
+            .map(|e| Event::from_sled(&e, &self.tree))
     }
 
-    pub fn from_sled(subscriber: sled::Subscriber) -> Self {
+    /// Wrap a `sled::Subscriber` of the tree with the given name.
+    /// The name is only used for error reporting.
+    pub fn from_sled(subscriber: sled::Subscriber, tree: IVec) -> Self {
         Self {
             inner: subscriber,
+            tree,
             _key: PhantomData,
             _value: PhantomData,
         }
@@ -752,21 +991,22 @@ use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll};
 impl<K: KV + Unpin, V: KV + Unpin> Future for Subscriber<K, V> {
-    type Output = Option<Event<K, V>>;
+    type Output = Option<Result<Event<K, V>>>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.project()
-            .inner
+        let this = self.project();
+        let tree = this.tree;
+        this.inner
             .poll(cx)
-            .map(|opt| opt.map(|e| Event::from_sled(&e)))
+            .map(|opt| opt.map(|e| Event::from_sled(&e, tree)))
     }
 }
 
 impl<K: KV, V: KV> Iterator for Subscriber<K, V> {
-    type Item = Event<K, V>;
+    type Item = Result<Event<K, V>>;
 
-    fn next(&mut self) -> Option<Event<K, V>> {
-        self.inner.next().map(|e| Event::from_sled(&e))
+    fn next(&mut self) -> Option<Result<Event<K, V>>> {
+        self.inner.next().map(|e| Event::from_sled(&e, &self.tree))
     }
 }
 
@@ -785,37 +1025,113 @@ impl<K, V> Event<K, V> {
         }
     }
 
-    pub fn from_sled(event: &sled::Event) -> Self
+    /// Deserialize a `sled::Event` of the tree with the given name.
+    /// The name is only used for error reporting.
+    pub fn from_sled(event: &sled::Event, tree: &[u8]) -> Result<Self>
     where
         K: KV,
         V: KV,
     {
         match event {
-            sled::Event::Insert { key, value } => Self::Insert {
-                key: deserialize(key),
-                value: deserialize(value),
-            },
-            sled::Event::Remove { key } => Self::Remove {
-                key: deserialize(key),
-            },
+            sled::Event::Insert { key, value } => {
+                let (key, value) = decode_kv(tree, key, value)?;
+                Ok(Self::Insert { key, value })
+            }
+            sled::Event::Remove { key } => Ok(Self::Remove {
+                key: decode_key(tree, key)?,
+            }),
         }
     }
 }
 
-/// The function which is used to deserialize all keys and values.
-pub fn deserialize<'a, T>(bytes: &'a [u8]) -> T
+/// The function which is used to deserialize all values.
+pub fn deserialize<'a, T>(bytes: &'a [u8]) -> bincode::Result<T>
+where
+    T: serde::de::Deserialize<'a>,
+{
+    bincode::deserialize(bytes)
+}
+
+/// The function which is used to serialize all values.
+pub fn serialize<T>(value: &T) -> bincode::Result<Vec<u8>>
+where
+    T: serde::Serialize,
+{
+    bincode::serialize(value)
+}
+
+/// The function which is used to deserialize all keys.
+/// See [key_codec] for details on the encoding.
+pub fn deserialize_key<'a, T>(bytes: &'a [u8]) -> key_codec::Result<T>
 where
     T: serde::de::Deserialize<'a>,
 {
-    bincode::deserialize(bytes).expect("deserialization failed, did the type serialized change?")
+    key_codec::from_bytes(bytes)
 }
 
-/// The function which is used to serialize all keys and values.
-pub fn serialize<T>(value: &T) -> Vec<u8>
+/// The function which is used to serialize all keys. The byte order of
+/// the serialized keys matches the `Ord` implementation of the key type,
+/// see [key_codec] for details.
+pub fn serialize_key<T>(value: &T) -> key_codec::Result<Vec<u8>>
 where
     T: serde::Serialize,
 {
-    bincode::serialize(value).expect("serialization failed, did the type serialized change?")
+    key_codec::to_bytes(value)
+}
+
+pub(crate) fn encode_key<K: Serialize>(key: &K) -> Result<Vec<u8>> {
+    serialize_key(key).map_err(Error::serialize)
+}
+
+pub(crate) fn encode_bound<K: Serialize>(bound: Bound<&K>) -> Result<Bound<Vec<u8>>> {
+    Ok(match bound {
+        Bound::Included(key) => Bound::Included(encode_key(key)?),
+        Bound::Excluded(key) => Bound::Excluded(encode_key(key)?),
+        Bound::Unbounded => Bound::Unbounded,
+    })
+}
+
+pub(crate) fn encode_value<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+    serialize(value).map_err(Error::serialize)
+}
+
+pub(crate) fn decode_key<K: KV>(tree: &[u8], key_bytes: &[u8]) -> Result<K> {
+    deserialize_key(key_bytes).map_err(|e| Error::deserialize(tree, key_bytes, e))
+}
+
+pub(crate) fn decode_value<V: KV>(tree: &[u8], key_bytes: &[u8], value_bytes: &[u8]) -> Result<V> {
+    deserialize(value_bytes).map_err(|e| Error::deserialize(tree, key_bytes, e))
+}
+
+pub(crate) fn decode_kv<K: KV, V: KV>(
+    tree: &[u8],
+    key_bytes: &[u8],
+    value_bytes: &[u8],
+) -> Result<(K, V)> {
+    Ok((
+        decode_key(tree, key_bytes)?,
+        decode_value(tree, key_bytes, value_bytes)?,
+    ))
+}
+
+// Milliseconds since the unix epoch, used for deadlines stored in sled.
+#[cfg(any(
+    feature = "ttl",
+    feature = "queue",
+    feature = "merge",
+    feature = "snowflake",
+    feature = "timestamp-key"
+))]
+pub(crate) fn now_millis() -> u64 {
+    use std::convert::TryInto;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .try_into()
+        .unwrap_or(u64::MAX)
 }
 
 #[cfg(test)]
@@ -838,11 +1154,104 @@ mod tests {
 
         let expect_results = [(6, 2), (10, 2)];
 
-        for (i, result) in tree.range(6..11).enumerate() {
+        for (i, result) in tree.range(6..11).unwrap().enumerate() {
             assert_eq!(result.unwrap(), expect_results[i]);
         }
     }
 
+    #[test]
+    fn test_range_bounds() {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().unwrap();
+
+        let tree: Tree<i32, u32> = Tree::open(&db, "test_tree");
+        check_range_bounds!(tree);
+    }
+
+    #[test]
+    fn test_key_order() {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().unwrap();
+
+        let tree: Tree<i32, u32> = Tree::open(&db, "test_tree");
+        let mut expected = std::collections::BTreeMap::new();
+
+        for (i, key) in [300, -1, 255, 0, 256, -300, 65536, 1].iter().enumerate() {
+            tree.insert(key, &(i as u32)).unwrap();
+            expected.insert(*key, i as u32);
+        }
+
+        let keys: Vec<i32> = tree.iter().keys().map(|k| k.unwrap()).collect();
+        assert_eq!(keys, expected.keys().copied().collect::<Vec<_>>());
+        assert_eq!(tree.first().unwrap().unwrap().0, -300);
+        assert_eq!(tree.last().unwrap().unwrap().0, 65536);
+        assert_eq!(tree.get_lt(&256).unwrap().unwrap().0, 255);
+        assert_eq!(tree.get_gt(&256).unwrap().unwrap().0, 300);
+
+        let range: Vec<i32> = tree
+            .range(0..=256)
+            .unwrap()
+            .keys()
+            .map(|k| k.unwrap())
+            .collect();
+        assert_eq!(range, vec![0, 1, 255, 256]);
+        assert_eq!(tree.pop_max().unwrap().unwrap().0, 65536);
+        assert_eq!(tree.pop_min().unwrap().unwrap().0, -300);
+    }
+
+    #[test]
+    fn test_corrupt_entry() {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().unwrap();
+
+        let tree: Tree<u32, String> = Tree::open(&db, "test_tree");
+        tree.insert(&1, &"one".to_string()).unwrap();
+        db.open_tree("test_tree")
+            .unwrap()
+            .insert(serialize_key(&2u32).unwrap(), &[1, 2, 3])
+            .unwrap();
+
+        assert_eq!(tree.get(&1).unwrap(), Some("one".to_string()));
+        match tree.get(&2) {
+            Err(Error::Deserialize {
+                tree, key_bytes, ..
+            }) => {
+                assert_eq!(tree, "test_tree");
+                assert_eq!(key_bytes, serialize_key(&2u32).unwrap());
+            }
+            other => panic!("expected a deserialization error, got {:?}", other),
+        }
+        let results: Vec<_> = tree.iter().collect();
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn test_merge_corrupt_entry() {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().unwrap();
+
+        let tree: Tree<u32, String> = Tree::open(&db, "test_tree");
+        tree.set_merge_operator(|_key, old: Option<String>, new| {
+            Some(old.unwrap_or_default() + &new)
+        });
+        tree.merge(&1, &"a".to_owned()).unwrap();
+        tree.merge(&1, &"b".to_owned()).unwrap();
+        assert_eq!(tree.get(&1).unwrap(), Some("ab".to_owned()));
+
+        let raw = db.open_tree("test_tree").unwrap();
+        raw.insert(serialize_key(&2u32).unwrap(), &[1, 2, 3])
+            .unwrap();
+        assert!(matches!(
+            tree.merge(&2, &"a".to_owned()),
+            Err(Error::Deserialize { .. })
+        ));
+        assert_eq!(
+            raw.get(serialize_key(&2u32).unwrap()).unwrap().unwrap(),
+            [1, 2, 3]
+        );
+    }
+
     #[test]
     fn test_cas() {
         let config = sled::Config::new().temporary(true);
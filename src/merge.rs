@@ -0,0 +1,425 @@
+//! Ready-made merge operators and trees which merge with them.
+//!
+//! The functions of this module can be passed to [Tree::set_merge_operator] directly:
+//! * [sum] adds the merged value to the stored value, e.g. for counters.
+//! * [append] appends the merged elements to a `Vec` or any other collection.
+//! * [union] adds the merged elements to a set.
+//! * [max] and [min] keep the largest or smallest value.
+//! * [last_writer_wins] keeps the [Timestamped] value with the latest timestamp.
+//!
+//! [MergeTree] opens a [Tree] and sets its merge operator in one step, so values can't be
+//! merged into a tree which has no or a different merge operator. The merge operators are
+//! selected by a type implementing [Merge], e.g. [Sum], and there are aliases with typed
+//! helpers for the common cases, like [CounterTree::increment] and [AppendTree::append].
+//!
+//! sled doesn't persist merge operators, so a tree which was created as a [MergeTree]
+//! should always be opened as a [MergeTree] with the same merge operator.
+//!
+//! # Example
+//! ```
+//! use typed_sled::merge::{AppendTree, CounterTree};
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let db = sled::Config::new().temporary(true).open().unwrap();
+//!
+//!     let visits = CounterTree::<String>::open(&db, "visits");
+//!     visits.increment(&"/index.html".to_owned(), 1)?;
+//!     assert_eq!(visits.increment(&"/index.html".to_owned(), 2)?, 3);
+//!
+//!     let log = AppendTree::<u64, String>::open(&db, "log");
+//!     log.append(&1, &["started".to_owned()])?;
+//!     log.append(&1, &["stopped".to_owned()])?;
+//!     assert_eq!(
+//!         log.get(&1)?,
+//!         Some(vec!["started".to_owned(), "stopped".to_owned()])
+//!     );
+//!     Ok(())
+//! }
+//! ```
+use crate::{Result, Tree, KV};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::marker::PhantomData;
+
+/// Merge operator adding the merged value to the stored value.
+///
+/// Integers saturate at their bounds instead of overflowing, as merge operators
+/// must not panic.
+pub fn sum<K, V: SaturatingAdd>(_key: K, old: Option<V>, new: V) -> Option<V> {
+    Some(match old {
+        Some(old) => old.saturating_add(new),
+        None => new,
+    })
+}
+
+/// The addition used by [sum].
+pub trait SaturatingAdd {
+    /// Add `other` to `self`, saturating at the bounds of the type instead of overflowing.
+    fn saturating_add(self, other: Self) -> Self;
+}
+
+macro_rules! impl_saturating_add {
+    ($($t:ty),+) => {
+        $(
+            impl SaturatingAdd for $t {
+                fn saturating_add(self, other: Self) -> Self {
+                    <$t>::saturating_add(self, other)
+                }
+            }
+        )+
+    };
+}
+
+impl_saturating_add!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+impl SaturatingAdd for f32 {
+    fn saturating_add(self, other: Self) -> Self {
+        self + other
+    }
+}
+
+impl SaturatingAdd for f64 {
+    fn saturating_add(self, other: Self) -> Self {
+        self + other
+    }
+}
+
+/// Merge operator appending the elements of the merged collection to the stored collection.
+pub fn append<K, C>(_key: K, old: Option<C>, new: C) -> Option<C>
+where
+    C: IntoIterator + Extend<<C as IntoIterator>::Item>,
+{
+    Some(match old {
+        Some(mut old) => {
+            old.extend(new);
+            old
+        }
+        None => new,
+    })
+}
+
+/// Merge operator adding the elements of the merged set to the stored set.
+///
+/// This is [append] for sets, so it works with any set type, e.g. `HashSet`.
+pub fn union<K, S>(key: K, old: Option<S>, new: S) -> Option<S>
+where
+    S: IntoIterator + Extend<<S as IntoIterator>::Item>,
+{
+    append(key, old, new)
+}
+
+/// Merge operator keeping the larger of the stored and the merged value.
+pub fn max<K, V: Ord>(_key: K, old: Option<V>, new: V) -> Option<V> {
+    Some(match old {
+        Some(old) => std::cmp::max(old, new),
+        None => new,
+    })
+}
+
+/// Merge operator keeping the smaller of the stored and the merged value.
+pub fn min<K, V: Ord>(_key: K, old: Option<V>, new: V) -> Option<V> {
+    Some(match old {
+        Some(old) => std::cmp::min(old, new),
+        None => new,
+    })
+}
+
+/// Merge operator keeping the value with the latest timestamp.
+///
+/// If both timestamps are equal the merged value wins.
+pub fn last_writer_wins<K, T>(
+    _key: K,
+    old: Option<Timestamped<T>>,
+    new: Timestamped<T>,
+) -> Option<Timestamped<T>> {
+    Some(match old {
+        Some(old) if old.timestamp > new.timestamp => old,
+        _ => new,
+    })
+}
+
+/// A value tagged with a timestamp for [last_writer_wins].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Timestamped<T> {
+    /// Milliseconds since the unix epoch, or any other monotonic clock shared by the writers.
+    pub timestamp: u64,
+    pub value: T,
+}
+
+impl<T> Timestamped<T> {
+    pub fn new(timestamp: u64, value: T) -> Self {
+        Self { timestamp, value }
+    }
+
+    /// Tag a value with the current time in milliseconds since the unix epoch.
+    pub fn now(value: T) -> Self {
+        Self::new(crate::now_millis(), value)
+    }
+}
+
+/// Selects the merge operator of a [MergeTree].
+pub trait Merge<V> {
+    fn merge(old: Option<V>, new: V) -> Option<V>;
+}
+
+/// Merges with [sum].
+pub struct Sum;
+
+impl<V: SaturatingAdd> Merge<V> for Sum {
+    fn merge(old: Option<V>, new: V) -> Option<V> {
+        sum((), old, new)
+    }
+}
+
+/// Merges with [append].
+pub struct Append;
+
+impl<C> Merge<C> for Append
+where
+    C: IntoIterator + Extend<<C as IntoIterator>::Item>,
+{
+    fn merge(old: Option<C>, new: C) -> Option<C> {
+        append((), old, new)
+    }
+}
+
+/// Merges with [union].
+pub struct Union;
+
+impl<S> Merge<S> for Union
+where
+    S: IntoIterator + Extend<<S as IntoIterator>::Item>,
+{
+    fn merge(old: Option<S>, new: S) -> Option<S> {
+        union((), old, new)
+    }
+}
+
+/// Merges with [max].
+pub struct Max;
+
+impl<V: Ord> Merge<V> for Max {
+    fn merge(old: Option<V>, new: V) -> Option<V> {
+        max((), old, new)
+    }
+}
+
+/// Merges with [min].
+pub struct Min;
+
+impl<V: Ord> Merge<V> for Min {
+    fn merge(old: Option<V>, new: V) -> Option<V> {
+        min((), old, new)
+    }
+}
+
+/// Merges with [last_writer_wins].
+pub struct LastWriterWins;
+
+impl<T> Merge<Timestamped<T>> for LastWriterWins {
+    fn merge(old: Option<Timestamped<T>>, new: Timestamped<T>) -> Option<Timestamped<T>> {
+        last_writer_wins((), old, new)
+    }
+}
+
+/// Counters which are incremented with [increment][CounterTree::increment].
+pub type CounterTree<K, N = i64> = MergeTree<K, N, Sum>;
+/// Lists which are extended with [append][AppendTree::append].
+pub type AppendTree<K, T> = MergeTree<K, Vec<T>, Append>;
+/// Sets which are extended with [insert][SetTree::insert].
+pub type SetTree<K, T> = MergeTree<K, BTreeSet<T>, Union>;
+/// Keeps the largest value merged into a key.
+pub type MaxTree<K, V> = MergeTree<K, V, Max>;
+/// Keeps the smallest value merged into a key.
+pub type MinTree<K, V> = MergeTree<K, V, Min>;
+/// Registers which are written with [set][LwwTree::set].
+pub type LwwTree<K, T> = MergeTree<K, Timestamped<T>, LastWriterWins>;
+
+/// A [Tree] whose merge operator is set to `M` when it is opened, see the [module documentation][self].
+pub struct MergeTree<K, V, M> {
+    tree: Tree<K, V>,
+    _merge: PhantomData<fn() -> M>,
+}
+
+impl<K, V, M> Clone for MergeTree<K, V, M> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            _merge: PhantomData,
+        }
+    }
+}
+
+impl<K, V, M> MergeTree<K, V, M>
+where
+    K: KV + 'static,
+    V: KV + 'static,
+    M: Merge<V> + 'static,
+{
+    /// Initialize a tree like [Tree::open] and set its merge operator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if sled fails to open the tree. Use [try_open][MergeTree::try_open]
+    /// to handle the error instead.
+    pub fn open<T: AsRef<str>>(db: &sled::Db, id: T) -> Self {
+        Self::try_open(db, id).unwrap()
+    }
+
+    /// Initialize a tree like [open][MergeTree::open], but return an
+    /// error instead of panicking if sled fails to open the tree.
+    pub fn try_open<T: AsRef<str>>(db: &sled::Db, id: T) -> Result<Self> {
+        let tree = Tree::try_open(db, id)?;
+        tree.set_merge_operator(|_key: K, old: Option<V>, new: V| M::merge(old, new));
+        Ok(Self {
+            tree,
+            _merge: PhantomData,
+        })
+    }
+}
+
+impl<K: KV, V: KV, M> MergeTree<K, V, M> {
+    /// Returns the underlying tree, which has its merge operator set.
+    pub fn tree(&self) -> &Tree<K, V> {
+        &self.tree
+    }
+
+    /// Merge a value into the value of a key and return the merged value, see [Tree::merge].
+    pub fn merge(&self, key: &K, value: &V) -> Result<Option<V>> {
+        self.tree.merge(key, value)
+    }
+
+    /// Retrieve the value of a key.
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        self.tree.get(key)
+    }
+
+    /// Delete the value of a key, returning the old value if it existed.
+    pub fn remove(&self, key: &K) -> Result<Option<V>> {
+        self.tree.remove(key)
+    }
+
+    // Merge a value which serializes like `V` without converting it to `V` first.
+    fn merge_as<T: Serialize>(&self, key: &K, value: &T) -> Result<Option<V>> {
+        let key_bytes = crate::encode_key(key)?;
+        let merged = self
+            .tree
+            .inner
+            .merge(&key_bytes, crate::encode_value(value)?)?;
+        merged
+            .map(|v| crate::decode_value(&self.tree.inner.name(), &key_bytes, &v))
+            .transpose()
+    }
+}
+
+impl<K: KV, N: KV + Default> MergeTree<K, N, Sum> {
+    /// Add `n` to the counter of a key, which starts at zero, and return the new count.
+    pub fn increment(&self, key: &K, n: N) -> Result<N> {
+        Ok(self.tree.merge(key, &n)?.unwrap_or_default())
+    }
+}
+
+impl<K: KV, T: KV> MergeTree<K, Vec<T>, Append> {
+    /// Append elements to the list of a key and return the whole list.
+    pub fn append(&self, key: &K, values: &[T]) -> Result<Vec<T>> {
+        Ok(self.merge_as(key, &values)?.unwrap_or_default())
+    }
+}
+
+impl<K: KV, T: KV + Ord> MergeTree<K, BTreeSet<T>, Union> {
+    /// Add an element to the set of a key and return the whole set.
+    pub fn insert(&self, key: &K, value: &T) -> Result<BTreeSet<T>> {
+        Ok(self
+            .merge_as(key, &std::slice::from_ref(value))?
+            .unwrap_or_default())
+    }
+}
+
+impl<K: KV, T: KV> MergeTree<K, Timestamped<T>, LastWriterWins> {
+    /// Write the value of a key with the current time, unless the stored value has a later timestamp.
+    /// Returns the value which won.
+    pub fn set(&self, key: &K, value: &T) -> Result<Option<Timestamped<T>>> {
+        self.merge_as(key, &Timestamped::now(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(sum((), None, 2), Some(2));
+        assert_eq!(sum((), Some(2), -3), Some(-1));
+        assert_eq!(sum((), Some(0.5), 0.25), Some(0.75));
+        assert_eq!(sum((), Some(i64::MAX), 1), Some(i64::MAX));
+        assert_eq!(sum((), Some(1u8), 255), Some(255));
+
+        assert_eq!(append((), Some(vec![1, 2]), vec![3]), Some(vec![1, 2, 3]));
+        let set: HashSet<_> = vec![1, 2].into_iter().collect();
+        assert_eq!(
+            union((), Some(set), vec![2, 3].into_iter().collect()),
+            Some(vec![1, 2, 3].into_iter().collect::<HashSet<_>>())
+        );
+
+        assert_eq!(max((), Some(3), 2), Some(3));
+        assert_eq!(max((), Some(3), 4), Some(4));
+        assert_eq!(min((), Some(3), 2), Some(2));
+        assert_eq!(min((), None, 4), Some(4));
+
+        let old = Timestamped::new(2, "old");
+        assert_eq!(
+            last_writer_wins((), Some(old.clone()), Timestamped::new(1, "new")),
+            Some(old.clone())
+        );
+        assert_eq!(
+            last_writer_wins((), Some(old), Timestamped::new(2, "new")),
+            Some(Timestamped::new(2, "new"))
+        );
+    }
+
+    #[test]
+    fn test_merge_trees() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+
+        let counters = CounterTree::<String, u64>::open(&db, "counters");
+        assert_eq!(counters.increment(&"a".to_owned(), 2).unwrap(), 2);
+        assert_eq!(counters.increment(&"a".to_owned(), 3).unwrap(), 5);
+        assert_eq!(counters.get(&"b".to_owned()).unwrap(), None);
+
+        let lists = AppendTree::<u32, String>::open(&db, "lists");
+        lists.append(&1, &["a".to_owned()]).unwrap();
+        assert_eq!(
+            lists.append(&1, &["b".to_owned(), "c".to_owned()]).unwrap(),
+            vec!["a".to_owned(), "b".to_owned(), "c".to_owned()]
+        );
+
+        let sets = SetTree::<u32, u32>::open(&db, "sets");
+        sets.insert(&1, &3).unwrap();
+        sets.insert(&1, &1).unwrap();
+        assert_eq!(
+            sets.insert(&1, &3).unwrap(),
+            vec![1, 3].into_iter().collect()
+        );
+
+        let maxima = MaxTree::<u32, i32>::open(&db, "maxima");
+        maxima.merge(&1, &3).unwrap();
+        assert_eq!(maxima.merge(&1, &-1).unwrap(), Some(3));
+        let minima = MinTree::<u32, i32>::open(&db, "minima");
+        minima.merge(&1, &3).unwrap();
+        assert_eq!(minima.merge(&1, &-1).unwrap(), Some(-1));
+
+        let registers = LwwTree::<u32, String>::open(&db, "registers");
+        let future = Timestamped::new(u64::MAX, "future".to_owned());
+        registers.merge(&1, &future).unwrap();
+        assert_eq!(registers.set(&1, &"now".to_owned()).unwrap(), Some(future));
+        let now = registers.set(&2, &"now".to_owned()).unwrap().unwrap();
+        assert_eq!(now.value, "now");
+
+        // The merge operator is set again when the tree is reopened.
+        drop(counters);
+        let counters = CounterTree::<String, u64>::open(&db, "counters");
+        assert_eq!(counters.increment(&"a".to_owned(), 1).unwrap(), 6);
+    }
+}
@@ -0,0 +1,526 @@
+//! A multimap storing an ordered set of values per key.
+//!
+//! Every key value pair is stored as a single sled key, the key codec encoding
+//! of the tuple `(K, V)`, with an empty value. Since the encoding of a key is a
+//! prefix of the encoding of all its pairs, the values of a key are stored next
+//! to each other and ordered by the [Ord] implementation of `V`.
+//!
+//! Single pairs can be inserted, removed and looked up in transactions with
+//! [TransactionalMultiTree], and inserted and removed in batches with [MultiBatch].
+//! sled transactions can't iterate over a tree, so [get_all][MultiTree::get_all],
+//! [count][MultiTree::count] and [remove_all][MultiTree::remove_all] aren't available
+//! inside of transactions, and a transaction applies a [PairBatch], which can't
+//! contain a [remove_all][MultiBatch::remove_all], instead of a [MultiBatch].
+//!
+//! [remove_all][MultiTree::remove_all] and [MultiBatch::remove_all] collect the values
+//! to remove before removing them, so values inserted concurrently might be kept.
+//!
+//! # Example
+//! ```
+//! use typed_sled::multi_tree::MultiTree;
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let db = sled::Config::new().temporary(true).open().unwrap();
+//!
+//!     let tags = MultiTree::<u64, String>::open(&db, "user_tags");
+//!     tags.insert(&1, &"rust".to_owned())?;
+//!     tags.insert(&1, &"db".to_owned())?;
+//!     tags.insert(&2, &"rust".to_owned())?;
+//!
+//!     let user_tags: Vec<String> = tags.get_all(&1)?.collect::<Result<_, _>>()?;
+//!     assert_eq!(user_tags, vec!["db".to_owned(), "rust".to_owned()]);
+//!     assert!(tags.contains(&2, &"rust".to_owned())?);
+//!     assert_eq!(tags.remove_all(&1)?, 2);
+//!     assert_eq!(tags.count(&1)?, 0);
+//!     Ok(())
+//! }
+//! ```
+use crate::transaction::{
+    self, ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+    TransactionResult, TransactionalTree,
+};
+use crate::{Result, Tree, KV};
+use sled::IVec;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+/// A multimap built on top of a single sled tree, see the [module documentation][self].
+pub struct MultiTree<K, V> {
+    tree: Tree<(K, V), ()>,
+}
+
+impl<K, V> Clone for MultiTree<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<K, V> MultiTree<K, V> {
+    /// Initialize a multimap like [Tree::open].
+    ///
+    /// # Panics
+    ///
+    /// Panics if sled fails to open the tree. Use [try_open][MultiTree::try_open]
+    /// to handle the error instead.
+    pub fn open<T: AsRef<str>>(db: &sled::Db, id: T) -> Self {
+        Self::try_open(db, id).unwrap()
+    }
+
+    /// Initialize a multimap like [open][MultiTree::open], but return an
+    /// error instead of panicking if sled fails to open the tree.
+    pub fn try_open<T: AsRef<str>>(db: &sled::Db, id: T) -> Result<Self> {
+        Ok(Self {
+            tree: Tree::try_open(db, id)?,
+        })
+    }
+
+    /// Returns the underlying tree, mapping every key value pair to `()`.
+    ///
+    /// Use it to include the multimap in a transaction over several trees
+    /// and convert its view into a [TransactionalMultiTree].
+    pub fn tree(&self) -> &Tree<(K, V), ()> {
+        &self.tree
+    }
+
+    /// Add a value to the values of a key, returning `false` if it was already present.
+    pub fn insert(&self, key: &K, value: &V) -> Result<bool>
+    where
+        K: KV,
+        V: KV,
+    {
+        let pair = encode_pair(key, value)?;
+        Ok(self.tree.inner.insert(pair, IVec::default())?.is_none())
+    }
+
+    /// Remove a value from the values of a key, returning `false` if it wasn't present.
+    pub fn remove(&self, key: &K, value: &V) -> Result<bool>
+    where
+        K: KV,
+        V: KV,
+    {
+        let pair = encode_pair(key, value)?;
+        Ok(self.tree.inner.remove(pair)?.is_some())
+    }
+
+    /// Returns `true` if the value is one of the values of the key.
+    pub fn contains(&self, key: &K, value: &V) -> Result<bool>
+    where
+        K: KV,
+        V: KV,
+    {
+        let pair = encode_pair(key, value)?;
+        Ok(self.tree.inner.contains_key(pair)?)
+    }
+
+    /// Create a double-ended iterator over the values of a key, in ascending order.
+    pub fn get_all(&self, key: &K) -> Result<impl DoubleEndedIterator<Item = Result<V>>>
+    where
+        K: KV,
+        V: KV,
+    {
+        let name = self.tree.name();
+        let iter = self.tree.inner.scan_prefix(crate::encode_key(key)?).keys();
+        Ok(iter.map(move |res| {
+            let pair = res?;
+            let (_, value): (K, V) = crate::decode_key(&name, &pair)?;
+            Ok(value)
+        }))
+    }
+
+    /// Returns the number of values of a key.
+    pub fn count(&self, key: &K) -> Result<usize>
+    where
+        K: KV,
+    {
+        let mut count = 0;
+        for res in self.tree.inner.scan_prefix(crate::encode_key(key)?).keys() {
+            res?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Remove all values of a key, returning the number of removed values.
+    ///
+    /// The values are collected before they are removed in a single batch,
+    /// values inserted concurrently might be kept.
+    pub fn remove_all(&self, key: &K) -> Result<usize>
+    where
+        K: KV,
+    {
+        let mut batch = sled::Batch::default();
+        let mut count = 0;
+        for res in self.tree.inner.scan_prefix(crate::encode_key(key)?).keys() {
+            batch.remove(res?);
+            count += 1;
+        }
+        self.tree.inner.apply_batch(batch)?;
+        Ok(count)
+    }
+
+    /// Create a double-ended iterator over all key value pairs,
+    /// ordered by key and then by value.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Result<(K, V)>>
+    where
+        K: KV,
+        V: KV,
+    {
+        let name = self.tree.name();
+        self.tree
+            .inner
+            .iter()
+            .keys()
+            .map(move |res| crate::decode_key(&name, &res?))
+    }
+
+    /// Returns `true` if the multimap contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Run a transaction on this multimap, see [Tree::transaction].
+    pub fn transaction<F, A>(&self, f: F) -> TransactionResult<A>
+    where
+        F: Fn(&TransactionalMultiTree<K, V>) -> ConflictableTransactionResult<A>,
+    {
+        self.tree
+            .inner
+            .transaction(|tree| {
+                f(&TransactionalMultiTree::new(tree))
+                    .map_err(ConflictableTransactionError::into_sled)
+            })
+            .map_err(TransactionError::from_sled)
+    }
+
+    /// Create a new batch of updates.
+    pub fn new_batch(&self) -> MultiBatch<K, V> {
+        MultiBatch::default()
+    }
+
+    /// Create a new batch of updates of single pairs, which can be applied
+    /// inside of a transaction with [TransactionalMultiTree::apply_batch].
+    pub fn new_pair_batch(&self) -> PairBatch<K, V> {
+        PairBatch::default()
+    }
+
+    /// Apply a batch.
+    ///
+    /// All updates are applied atomically, but the values removed by
+    /// [MultiBatch::remove_all] are collected before, so values inserted
+    /// concurrently might be kept.
+    pub fn apply_batch(&self, batch: MultiBatch<K, V>) -> Result<()> {
+        // Later operations on the same pair override earlier ones.
+        let mut pairs = BTreeMap::new();
+        for op in batch.ops {
+            match op {
+                BatchOp::Insert(pair) => {
+                    pairs.insert(pair, true);
+                }
+                BatchOp::Remove(pair) => {
+                    pairs.insert(pair, false);
+                }
+                BatchOp::RemoveAll(prefix) => {
+                    pairs
+                        .range_mut(prefix.clone()..)
+                        .take_while(|(pair, _)| pair.starts_with(&prefix))
+                        .for_each(|(_, insert)| *insert = false);
+                    for res in self.tree.inner.scan_prefix(&prefix).keys() {
+                        pairs.insert(res?.to_vec(), false);
+                    }
+                }
+            }
+        }
+        let mut sled_batch = sled::Batch::default();
+        for (pair, insert) in pairs {
+            if insert {
+                sled_batch.insert(pair, IVec::default());
+            } else {
+                sled_batch.remove(pair);
+            }
+        }
+        Ok(self.tree.inner.apply_batch(sled_batch)?)
+    }
+}
+
+/// A multimap inside of a transaction.
+///
+/// Convert the view of [MultiTree::tree] in a transaction over several trees
+/// with `TransactionalMultiTree::from`.
+pub struct TransactionalMultiTree<'a, K, V> {
+    inner: &'a sled::transaction::TransactionalTree,
+    _key: PhantomData<fn() -> K>,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<'a, K, V> TransactionalMultiTree<'a, K, V> {
+    fn new(inner: &'a sled::transaction::TransactionalTree) -> Self {
+        Self {
+            inner,
+            _key: PhantomData,
+            _value: PhantomData,
+        }
+    }
+
+    /// Add a value to the values of a key, returning `false` if it was already present.
+    pub fn insert(&self, key: &K, value: &V) -> transaction::Result<bool>
+    where
+        K: KV,
+        V: KV,
+    {
+        let pair = encode_pair(key, value)?;
+        Ok(self.inner.insert(pair, IVec::default())?.is_none())
+    }
+
+    /// Remove a value from the values of a key, returning `false` if it wasn't present.
+    pub fn remove(&self, key: &K, value: &V) -> transaction::Result<bool>
+    where
+        K: KV,
+        V: KV,
+    {
+        let pair = encode_pair(key, value)?;
+        Ok(self.inner.remove(pair)?.is_some())
+    }
+
+    /// Returns `true` if the value is one of the values of the key.
+    pub fn contains(&self, key: &K, value: &V) -> transaction::Result<bool>
+    where
+        K: KV,
+        V: KV,
+    {
+        let pair = encode_pair(key, value)?;
+        Ok(self.inner.get(pair)?.is_some())
+    }
+
+    /// Atomically apply a batch of updates of single pairs.
+    pub fn apply_batch(&self, batch: &PairBatch<K, V>) -> transaction::Result<()> {
+        Ok(self.inner.apply_batch(&batch.inner)?)
+    }
+}
+
+impl<K, V> transaction::TransactionTarget for &MultiTree<K, V> {
+    type View<'a> = TransactionalMultiTree<'a, K, V>;
+
+    fn sled_tree(&self) -> &sled::Tree {
+        &self.tree.inner
+    }
+
+    fn view<'a>(
+        &self,
+        tree: &'a sled::transaction::TransactionalTree,
+        _name: &'a [u8],
+    ) -> Self::View<'a> {
+        TransactionalMultiTree::new(tree)
+    }
+}
+
+impl<'a, K, V> From<TransactionalTree<'a, (K, V), ()>> for TransactionalMultiTree<'a, K, V> {
+    fn from(tree: TransactionalTree<'a, (K, V), ()>) -> Self {
+        Self::new(tree.inner)
+    }
+}
+
+enum BatchOp {
+    Insert(Vec<u8>),
+    Remove(Vec<u8>),
+    // The encoded key whose values are removed.
+    RemoveAll(Vec<u8>),
+}
+
+/// A batch of updates for a [MultiTree].
+pub struct MultiBatch<K, V> {
+    ops: Vec<BatchOp>,
+    _key: PhantomData<fn() -> K>,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<K, V> MultiBatch<K, V> {
+    /// Add a value to the values of a key when the batch is applied.
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<()>
+    where
+        K: KV,
+        V: KV,
+    {
+        self.ops.push(BatchOp::Insert(encode_pair(key, value)?));
+        Ok(())
+    }
+
+    /// Remove a value from the values of a key when the batch is applied.
+    pub fn remove(&mut self, key: &K, value: &V) -> Result<()>
+    where
+        K: KV,
+        V: KV,
+    {
+        self.ops.push(BatchOp::Remove(encode_pair(key, value)?));
+        Ok(())
+    }
+
+    /// Remove all values of a key, including the values inserted earlier in this batch.
+    pub fn remove_all(&mut self, key: &K) -> Result<()>
+    where
+        K: KV,
+    {
+        self.ops.push(BatchOp::RemoveAll(crate::encode_key(key)?));
+        Ok(())
+    }
+}
+
+// Implementing Default manually to not require K and V to implement Default.
+impl<K, V> Default for MultiBatch<K, V> {
+    fn default() -> Self {
+        Self {
+            ops: Vec::new(),
+            _key: PhantomData,
+            _value: PhantomData,
+        }
+    }
+}
+
+/// A batch of inserts and removes of single pairs for a [MultiTree].
+///
+/// Unlike a [MultiBatch], it can be applied inside of a transaction, since
+/// it never needs to look up the values of a key.
+pub struct PairBatch<K, V> {
+    inner: sled::Batch,
+    _key: PhantomData<fn() -> K>,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<K, V> PairBatch<K, V> {
+    /// Add a value to the values of a key when the batch is applied.
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<()>
+    where
+        K: KV,
+        V: KV,
+    {
+        self.inner.insert(encode_pair(key, value)?, IVec::default());
+        Ok(())
+    }
+
+    /// Remove a value from the values of a key when the batch is applied.
+    pub fn remove(&mut self, key: &K, value: &V) -> Result<()>
+    where
+        K: KV,
+        V: KV,
+    {
+        self.inner.remove(encode_pair(key, value)?);
+        Ok(())
+    }
+}
+
+// Implementing Default manually to not require K and V to implement Default.
+impl<K, V> Default for PairBatch<K, V> {
+    fn default() -> Self {
+        Self {
+            inner: sled::Batch::default(),
+            _key: PhantomData,
+            _value: PhantomData,
+        }
+    }
+}
+
+// The key codec encodes tuples by concatenating their elements, so the
+// encoded key is a prefix of the encoded pair.
+fn encode_pair<K: KV, V: KV>(key: &K, value: &V) -> Result<Vec<u8>> {
+    crate::encode_key(&(key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transactional;
+
+    fn values(tree: &MultiTree<String, u32>, key: &str) -> Vec<u32> {
+        tree.get_all(&key.to_owned())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_multi_tree() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = MultiTree::<String, u32>::open(&db, "multi");
+
+        assert!(tree.insert(&"a".to_owned(), &3).unwrap());
+        assert!(tree.insert(&"a".to_owned(), &1).unwrap());
+        assert!(!tree.insert(&"a".to_owned(), &1).unwrap());
+        assert!(tree.insert(&"ab".to_owned(), &2).unwrap());
+        assert!(tree.insert(&"".to_owned(), &0).unwrap());
+
+        // A key isn't a prefix of a longer key.
+        assert_eq!(values(&tree, "a"), vec![1, 3]);
+        assert_eq!(values(&tree, "ab"), vec![2]);
+        assert_eq!(values(&tree, ""), vec![0]);
+        assert_eq!(tree.count(&"a".to_owned()).unwrap(), 2);
+        assert!(tree.contains(&"a".to_owned(), &3).unwrap());
+        assert!(!tree.contains(&"ab".to_owned(), &3).unwrap());
+        let reversed: Vec<u32> = tree
+            .get_all(&"a".to_owned())
+            .unwrap()
+            .rev()
+            .map(|res| res.unwrap())
+            .collect();
+        assert_eq!(reversed, vec![3, 1]);
+
+        assert!(tree.remove(&"a".to_owned(), &3).unwrap());
+        assert!(!tree.remove(&"a".to_owned(), &3).unwrap());
+        assert_eq!(tree.remove_all(&"ab".to_owned()).unwrap(), 1);
+        let pairs: Vec<(String, u32)> = tree.iter().map(|res| res.unwrap()).collect();
+        assert_eq!(pairs, vec![("".to_owned(), 0), ("a".to_owned(), 1)]);
+    }
+
+    #[test]
+    fn test_batch() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = MultiTree::<String, u32>::open(&db, "multi");
+        tree.insert(&"a".to_owned(), &1).unwrap();
+        tree.insert(&"b".to_owned(), &1).unwrap();
+
+        let mut batch = tree.new_batch();
+        batch.insert(&"a".to_owned(), &2).unwrap();
+        batch.remove_all(&"a".to_owned()).unwrap();
+        batch.insert(&"a".to_owned(), &3).unwrap();
+        batch.insert(&"b".to_owned(), &2).unwrap();
+        batch.remove(&"b".to_owned(), &1).unwrap();
+        tree.apply_batch(batch).unwrap();
+
+        assert_eq!(values(&tree, "a"), vec![3]);
+        assert_eq!(values(&tree, "b"), vec![2]);
+    }
+
+    #[test]
+    fn test_transaction() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = MultiTree::<String, u32>::open(&db, "multi");
+        let counts = Tree::<String, u32>::open(&db, "counts");
+
+        tree.transaction(|tx| {
+            tx.insert(&"a".to_owned(), &1)?;
+            tx.insert(&"a".to_owned(), &2)?;
+            let mut batch = PairBatch::default();
+            batch.insert(&"b".to_owned(), &1).unwrap();
+            batch.insert(&"b".to_owned(), &2).unwrap();
+            batch.remove(&"b".to_owned(), &2).unwrap();
+            tx.apply_batch(&batch)?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(values(&tree, "a"), vec![1, 2]);
+        assert_eq!(values(&tree, "b"), vec![1]);
+
+        (tree.tree(), &counts)
+            .transaction(|(pairs, counts)| {
+                let pairs = TransactionalMultiTree::from(pairs);
+                if pairs.remove(&"a".to_owned(), &1)? {
+                    counts.insert(&"a".to_owned(), &1)?;
+                }
+                assert!(!pairs.contains(&"a".to_owned(), &1)?);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(values(&tree, "a"), vec![2]);
+        assert_eq!(counts.get(&"a".to_owned()).unwrap(), Some(1));
+    }
+}
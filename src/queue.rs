@@ -0,0 +1,490 @@
+//! Persistent FIFO and priority queues with leases.
+//!
+//! Entries are stored in a [Tree] keyed by their priority followed by a sequence
+//! number from [sled::Db::generate_id], so entries with the same priority are
+//! dequeued in insertion order and the lowest priority is dequeued first.
+//!
+//! Besides removing entries with [pop][PriorityQueueTree::pop], entries can be
+//! leased for a visibility timeout with [lease][PriorityQueueTree::lease]. Leased
+//! entries are moved to a companion sled tree ordered by deadline. They are removed
+//! for good with [ack][PriorityQueueTree::ack], put back with [nack][PriorityQueueTree::nack]
+//! or redelivered once the timeout has passed, e.g. because the consumer crashed.
+//! All state lives in sled, so leases survive restarts.
+//!
+//! # Example
+//! ```
+//! use std::time::Duration;
+//! use typed_sled::queue::QueueTree;
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let db = sled::Config::new().temporary(true).open().unwrap();
+//!
+//!     let jobs = QueueTree::<String>::try_open(&db, "jobs")?;
+//!     jobs.push(&"first".to_owned())?;
+//!     jobs.push(&"second".to_owned())?;
+//!
+//!     assert_eq!(jobs.pop()?, Some("first".to_owned()));
+//!
+//!     let lease = jobs.lease(Duration::from_secs(30))?.unwrap();
+//!     assert_eq!(lease.value, "second");
+//!     // Process the job and acknowledge it, otherwise it is redelivered after 30 seconds.
+//!     assert!(jobs.ack(&lease)?);
+//!
+//!     assert_eq!(jobs.pop_blocking(Duration::from_millis(10))?, None);
+//!     Ok(())
+//! }
+//! ```
+use crate::{now_millis, Error, Result, Tree, KV};
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+    TransactionalTree,
+};
+use sled::{IVec, Transactional};
+use std::convert::TryInto;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A FIFO queue, a [PriorityQueueTree] where all entries have the same priority.
+pub type QueueTree<T> = PriorityQueueTree<(), T>;
+
+/// The name of the sled tree storing the leased entries of `queue`.
+pub fn lease_tree_name(queue: &str) -> String {
+    format!("{}__typed_sled_queue_leases", queue)
+}
+
+/// A persistent queue ordered by priority, see the [module documentation][self].
+pub struct PriorityQueueTree<P, T> {
+    db: sled::Db,
+    // (priority, sequence number) -> value
+    ready: Tree<(P, u64), T>,
+    // Deadline followed by the sequence number -> ready key and value
+    leases: sled::Tree,
+}
+
+impl<P, T> Clone for PriorityQueueTree<P, T> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            ready: self.ready.clone(),
+            leases: self.leases.clone(),
+        }
+    }
+}
+
+/// An entry leased from a queue with [PriorityQueueTree::lease].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease<P, T> {
+    pub priority: P,
+    pub value: T,
+    sequence: u64,
+    deadline: u64,
+}
+
+impl<P, T> Lease<P, T> {
+    /// The time at which the entry is redelivered if it isn't acknowledged.
+    pub fn deadline(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.deadline)
+    }
+}
+
+impl<T> PriorityQueueTree<(), T> {
+    /// Append a value to the queue.
+    pub fn push(&self, value: &T) -> Result<()>
+    where
+        T: KV,
+    {
+        self.push_with_priority(&(), value)
+    }
+}
+
+impl<P, T> PriorityQueueTree<P, T> {
+    /// Open the queue together with its lease tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if sled fails to open the trees. Use [try_open][PriorityQueueTree::try_open]
+    /// to handle the error instead.
+    pub fn open<S: AsRef<str>>(db: &sled::Db, id: S) -> Self {
+        Self::try_open(db, id).unwrap()
+    }
+
+    /// Open the queue like [open][PriorityQueueTree::open], but return an error
+    /// instead of panicking if sled fails to open the trees.
+    pub fn try_open<S: AsRef<str>>(db: &sled::Db, id: S) -> Result<Self> {
+        Ok(Self {
+            db: db.clone(),
+            ready: Tree::try_open(db, id.as_ref())?,
+            leases: db.open_tree(lease_tree_name(id.as_ref()))?,
+        })
+    }
+
+    /// Returns the tree of the entries which aren't leased.
+    pub fn tree(&self) -> &Tree<(P, u64), T> {
+        &self.ready
+    }
+
+    /// Add a value to the queue. It is dequeued after all values with a lower
+    /// priority and all values with the same priority that were pushed before.
+    pub fn push_with_priority(&self, priority: &P, value: &T) -> Result<()>
+    where
+        P: KV,
+        T: KV,
+    {
+        let key = crate::encode_key(&(priority, self.db.generate_id()?))?;
+        self.ready.inner.insert(key, crate::encode_value(value)?)?;
+        Ok(())
+    }
+
+    /// Remove and return the next value, redelivering expired leases first.
+    pub fn pop(&self) -> Result<Option<T>>
+    where
+        P: KV,
+        T: KV,
+    {
+        self.requeue_expired()?;
+        Ok(self.ready.pop_min()?.map(|(_, value)| value))
+    }
+
+    /// Like [pop][Self::pop], but wait up to `timeout` for a value to be pushed
+    /// if the queue is empty.
+    pub fn pop_blocking(&self, timeout: Duration) -> Result<Option<T>>
+    where
+        P: KV,
+        T: KV,
+    {
+        self.blocking(timeout, || self.pop())
+    }
+
+    /// Remove the next value from the queue until the returned [Lease] is
+    /// acknowledged. If it isn't acknowledged within `visibility_timeout`,
+    /// the value is put back into the queue at its original position.
+    pub fn lease(&self, visibility_timeout: Duration) -> Result<Option<Lease<P, T>>>
+    where
+        P: KV,
+        T: KV,
+    {
+        self.requeue_expired()?;
+        let name = self.ready.name();
+        loop {
+            let (key, value) = match self.ready.inner.first()? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            let (priority, sequence): (P, u64) = crate::decode_key(&name, &key)?;
+            let deadline = now_millis().saturating_add(
+                visibility_timeout
+                    .as_millis()
+                    .try_into()
+                    .unwrap_or(u64::MAX),
+            );
+            let lease = crate::encode_value(&(key.as_ref(), value.as_ref()))?;
+            let leased = self.transaction(|ready, leases| {
+                // Another consumer might have taken the entry in the meantime.
+                if ready.remove(&key)?.is_none() {
+                    return Ok(false);
+                }
+                leases.insert(lease_key(deadline, sequence).to_vec(), lease.as_slice())?;
+                Ok(true)
+            })?;
+            if leased {
+                return Ok(Some(Lease {
+                    priority,
+                    value: crate::decode_value(&name, &key, &value)?,
+                    sequence,
+                    deadline,
+                }));
+            }
+        }
+    }
+
+    /// Like [lease][Self::lease], but wait up to `timeout` for a value to be pushed
+    /// if the queue is empty.
+    pub fn lease_blocking(
+        &self,
+        timeout: Duration,
+        visibility_timeout: Duration,
+    ) -> Result<Option<Lease<P, T>>>
+    where
+        P: KV,
+        T: KV,
+    {
+        self.blocking(timeout, || self.lease(visibility_timeout))
+    }
+
+    /// Acknowledge a lease, removing its value for good.
+    ///
+    /// Returns `false` if the lease has expired and its value was put back
+    /// into the queue, or if it was already acknowledged.
+    pub fn ack(&self, lease: &Lease<P, T>) -> Result<bool> {
+        Ok(self
+            .leases
+            .remove(lease_key(lease.deadline, lease.sequence))?
+            .is_some())
+    }
+
+    /// Put the value of a lease back into the queue at its original position.
+    ///
+    /// Returns `false` if the lease has expired and its value was put back
+    /// into the queue, or if it was already acknowledged.
+    pub fn nack(&self, lease: &Lease<P, T>) -> Result<bool> {
+        let lease_key = lease_key(lease.deadline, lease.sequence);
+        let lease_tree = self.leases.name();
+        self.transaction(|ready, leases| match leases.remove(&lease_key)? {
+            Some(lease) => {
+                let (key, value) = decode_lease(&lease_tree, &lease_key, &lease)?;
+                ready.insert(key, value)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        })
+    }
+
+    /// Put the values of all expired leases back into the queue, returning
+    /// their number. This is done by [pop][Self::pop] and [lease][Self::lease]
+    /// as well, so it usually doesn't need to be called.
+    pub fn requeue_expired(&self) -> Result<usize> {
+        // All leases with a deadline up to and including now.
+        let end = now_millis().saturating_add(1).to_be_bytes();
+        let expired = self
+            .leases
+            .range(..end)
+            .keys()
+            .collect::<sled::Result<Vec<IVec>>>()?;
+        if expired.is_empty() {
+            return Ok(0);
+        }
+        let lease_tree = self.leases.name();
+        self.transaction(|ready, leases| {
+            let mut requeued = 0;
+            for lease_key in &expired {
+                // The lease might have been acknowledged in the meantime.
+                if let Some(lease) = leases.remove(lease_key)? {
+                    let (key, value) = decode_lease(&lease_tree, lease_key, &lease)?;
+                    ready.insert(key, value)?;
+                    requeued += 1;
+                }
+            }
+            Ok(requeued)
+        })
+    }
+
+    /// Returns the number of values in the queue, not counting leased values.
+    pub fn len(&self) -> usize {
+        self.ready.len()
+    }
+
+    /// Returns `true` if there are no values in the queue, not counting leased values.
+    pub fn is_empty(&self) -> bool {
+        self.ready.is_empty()
+    }
+
+    /// Returns the number of leased values, including expired leases.
+    pub fn leased_len(&self) -> usize {
+        self.leases.len()
+    }
+
+    // Calls `f` until it returns a value or `timeout` has passed, waking up
+    // whenever the queue changes or the next lease expires.
+    fn blocking<A>(
+        &self,
+        timeout: Duration,
+        f: impl Fn() -> Result<Option<A>>,
+    ) -> Result<Option<A>> {
+        let start = Instant::now();
+        // Subscribing before looking at the queue, so no push is missed.
+        let mut subscriber = self.ready.inner.watch_prefix(vec![]);
+        loop {
+            if let Some(value) = f()? {
+                return Ok(Some(value));
+            }
+            let remaining = match timeout.checked_sub(start.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => remaining,
+                _ => return Ok(None),
+            };
+            let next_deadline = self.leases.first()?.map(|(lease_key, _)| {
+                let deadline = decode_deadline(&lease_key);
+                Duration::from_millis(deadline.saturating_sub(now_millis()))
+            });
+            let wait = next_deadline.map_or(remaining, |until| until.min(remaining));
+            match subscriber.next_timeout(wait) {
+                // sled also reports events which aren't filled in time as disconnected.
+                Ok(_) | Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {}
+            }
+        }
+    }
+
+    // Runs `f` in a transaction over the ready and the lease tree.
+    fn transaction<A, F>(&self, f: F) -> Result<A>
+    where
+        F: Fn(&TransactionalTree, &TransactionalTree) -> ConflictableTransactionResult<A, Error>,
+    {
+        (&self.ready.inner, &self.leases)
+            .transaction(|(ready, leases)| f(ready, leases))
+            .map_err(|e| match e {
+                TransactionError::Abort(e) => e,
+                TransactionError::Storage(e) => e.into(),
+            })
+    }
+}
+
+// Deadlines are stored big-endian so the lease tree is ordered by deadline.
+fn lease_key(deadline: u64, sequence: u64) -> [u8; 16] {
+    let mut key = [0; 16];
+    key[..8].copy_from_slice(&deadline.to_be_bytes());
+    key[8..].copy_from_slice(&sequence.to_be_bytes());
+    key
+}
+
+fn decode_deadline(lease_key: &[u8]) -> u64 {
+    let mut deadline = [0; 8];
+    deadline.copy_from_slice(&lease_key[..8]);
+    u64::from_be_bytes(deadline)
+}
+
+// The key in the ready tree and the value of a lease.
+fn decode_lease(
+    tree: &[u8],
+    lease_key: &[u8],
+    lease: &[u8],
+) -> ConflictableTransactionResult<(Vec<u8>, Vec<u8>), Error> {
+    bincode::deserialize(lease)
+        .map_err(|e| ConflictableTransactionError::Abort(Error::deserialize(tree, lease_key, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_fifo() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let queue = QueueTree::<u32>::open(&db, "queue");
+
+        for i in (0..5).rev() {
+            queue.push(&i).unwrap();
+        }
+        assert_eq!(queue.len(), 5);
+        let values: Vec<u32> = std::iter::from_fn(|| queue.pop().unwrap()).collect();
+        assert_eq!(values, vec![4, 3, 2, 1, 0]);
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop().unwrap(), None);
+    }
+
+    #[test]
+    fn test_priority() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let queue = PriorityQueueTree::<u8, String>::open(&db, "queue");
+
+        queue.push_with_priority(&2, &"low".to_owned()).unwrap();
+        queue.push_with_priority(&1, &"high 1".to_owned()).unwrap();
+        queue.push_with_priority(&1, &"high 2".to_owned()).unwrap();
+
+        let lease = queue.lease(Duration::from_secs(60)).unwrap().unwrap();
+        assert_eq!((lease.priority, lease.value.as_str()), (1, "high 1"));
+        assert_eq!(queue.pop().unwrap(), Some("high 2".to_owned()));
+        assert_eq!(queue.pop().unwrap(), Some("low".to_owned()));
+    }
+
+    #[test]
+    fn test_lease() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let queue = QueueTree::<u32>::open(&db, "queue");
+        queue.push(&1).unwrap();
+        queue.push(&2).unwrap();
+
+        let first = queue.lease(Duration::from_secs(60)).unwrap().unwrap();
+        let second = queue.lease(Duration::from_secs(60)).unwrap().unwrap();
+        assert_eq!((first.value, second.value), (1, 2));
+        assert!(queue.is_empty());
+        assert_eq!(queue.leased_len(), 2);
+        assert!(queue.lease(Duration::from_secs(60)).unwrap().is_none());
+
+        assert!(queue.ack(&second).unwrap());
+        assert!(!queue.ack(&second).unwrap());
+        assert!(queue.nack(&first).unwrap());
+        assert!(!queue.nack(&first).unwrap());
+        assert_eq!(queue.leased_len(), 0);
+
+        // Leases which aren't acknowledged in time are redelivered.
+        queue.push(&3).unwrap();
+        let expired = queue.lease(Duration::ZERO).unwrap().unwrap();
+        assert_eq!(expired.value, 1);
+        let redelivered = queue.lease(Duration::from_secs(60)).unwrap().unwrap();
+        assert_eq!(redelivered.value, 1);
+        assert!(!queue.ack(&expired).unwrap());
+        assert!(queue.ack(&redelivered).unwrap());
+        assert_eq!(queue.pop().unwrap(), Some(3));
+        assert_eq!(queue.leased_len(), 0);
+    }
+
+    #[test]
+    fn test_leases_survive_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "typed_sled_test_leases_survive_restart_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        {
+            let db = sled::open(&path).unwrap();
+            let queue = QueueTree::<u32>::open(&db, "queue");
+            queue.push(&1).unwrap();
+            queue.lease(Duration::from_millis(50)).unwrap().unwrap();
+            db.flush().unwrap();
+        }
+        // sled releases the lock of the database in the background.
+        let start = Instant::now();
+        let db = loop {
+            match sled::open(&path) {
+                Ok(db) => break db,
+                Err(_) if start.elapsed() < Duration::from_secs(10) => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                Err(e) => panic!("{}", e),
+            }
+        };
+        let queue = QueueTree::<u32>::open(&db, "queue");
+        assert_eq!(queue.leased_len(), 1);
+        // Waits for the lease to expire.
+        assert_eq!(
+            queue.pop_blocking(Duration::from_secs(10)).unwrap(),
+            Some(1)
+        );
+        drop(queue);
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_pop_blocking() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let queue = QueueTree::<u32>::open(&db, "queue");
+
+        let start = Instant::now();
+        assert_eq!(queue.pop_blocking(Duration::from_millis(50)).unwrap(), None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let producer = queue.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            producer.push(&1).unwrap();
+        });
+        assert_eq!(
+            queue.pop_blocking(Duration::from_secs(10)).unwrap(),
+            Some(1)
+        );
+        handle.join().unwrap();
+
+        let producer = queue.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            producer.push(&2).unwrap();
+        });
+        let lease = queue
+            .lease_blocking(Duration::from_secs(10), Duration::from_secs(60))
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease.value, 2);
+        handle.join().unwrap();
+    }
+}
@@ -43,92 +43,51 @@
 //!     let search_results = search_engine.search("life", 10)?;
 //!
 //!     for result in search_results.iter() {
-//!         println!("Found Blog Post with score {}:\n{:#?}", result.0, result.1);
+//!         println!("Found Blog Post with score {}:\n{:#?}", result.score, result.value);
 //!     }
 //!     Ok(())
 //! }
 //! ```
 //!
 //! [tantivy]: https://docs.rs/tantivy/latest/tantivy/
-use crate::{serialize, Event, Tree, KV};
+use crate::custom_serde::{self, serialize::OrderedSerDe};
+use crate::searchable::Searchable;
+use crate::{Tree, KV};
 
-use std::fs::create_dir_all;
-use std::iter::Iterator;
-use std::thread;
-use std::{marker::PhantomData, path::Path};
-use tantivy::collector::TopDocs;
-use tantivy::DocAddress;
-use tantivy::{
-    collector::Collector,
-    directory::MmapDirectory,
-    query::{Query, QueryParser},
-    schema::{BytesOptions, Field, SchemaBuilder, Value},
-    Document, Index, IndexReader, Score, Term,
+use std::ops::Deref;
+use std::path::Path;
+use tantivy::{schema::SchemaBuilder, Document};
+
+pub use crate::custom_serde::search::{
+    CommitPolicy, FacetRequest, FacetResult, FacetedResults, SearchCursor, SearchError, SearchHit,
+    SearchPage, SearchResults,
 };
 
 /// A search engine for a single tree, using `tantivy` under the hood.
-/// # Example
-/// ```
-/// use typed_sled::search::SearchEngine;
-/// use tantivy::{
-///     doc,
-///     schema::{Schema, TEXT},
-/// };
-/// use serde::{Deserialize, Serialize};
-///
-/// #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-/// struct BlogPost {
-///     author: String,
-///     title: String,
-///     body: String,
-/// }
-///
-/// pub fn main() -> Result<(), Box<dyn std::error::Error>> {
-///     let config = sled::Config::new().temporary(true);
-///     let db = config.open().unwrap();
-///
-///     let tree = typed_sled::Tree::<u64, BlogPost>::open(&db, "unique_id");
 ///
-///     let post = BlogPost {
-///         author: "Mike".to_string(),
-///         title: "The life of the disillusioned".to_string(),
-///         body: "Long story short, he didn't have fun.".to_string(),
-///     };
-///     tree.insert(&0, &post)?;
+/// See the [module documentation][crate::search] for an example.
 ///
-///     let mut schema_builder = Schema::builder();
-///     let author = schema_builder.add_text_field("author", TEXT);
-///     let title = schema_builder.add_text_field("title", TEXT);
-///     let body = schema_builder.add_text_field("body", TEXT);
-///
-///     let search_engine = SearchEngine::new_temp(&tree, schema_builder, move |_k, v| {
-///         doc!(
-///             author => v.author.to_owned(),
-///             title => v.title.to_owned(),
-///             body => v.body.to_owned()
-///         )
-///     })?;
-///     let search_results = search_engine.search("life", 10)?;
-///
-///     for result in search_results.iter() {
-///         println!("Found Blog Post with score {}:\n{:#?}", result.0, result.1);
-///     }
-///     Ok(())
-/// }
-/// ```
-#[derive(Clone)]
+/// This is a [custom_serde::search::SearchEngine] for the encoding used by
+/// [Tree], all of its methods are available through `Deref`.
 pub struct SearchEngine<K, V> {
-    tree: Tree<K, V>,
-    pub index: Index,
-    index_reader: IndexReader,
-    key_field: Field,
-    phantom_key: PhantomData<fn() -> K>,
-    phantom_value: PhantomData<fn() -> V>,
+    inner: custom_serde::search::SearchEngine<K, V, OrderedSerDe>,
+}
+
+impl<K, V> Clone for SearchEngine<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
 }
 
 impl<K, V> SearchEngine<K, V> {
     /// Create a new search engine or if the path already exists
     /// open an existing search engine.
+    ///
+    /// An existing index is rebuilt if it might not match the tree anymore,
+    /// e.g. because the tree was modified while the search engine wasn't
+    /// running or the previous search engine wasn't shut down cleanly.
     pub fn new<P: AsRef<Path> + Clone, F>(
         path: P,
         tree: &Tree<K, V>,
@@ -140,7 +99,7 @@ impl<K, V> SearchEngine<K, V> {
         K: KV + 'static,
         V: KV + 'static,
     {
-        Self::new_with_options(Some(path), tree, schema_builder, f)
+        Self::new_with_options(Some(path), tree, schema_builder, CommitPolicy::default(), f)
     }
 
     /// Create a new temporary search engine.
@@ -154,15 +113,16 @@ impl<K, V> SearchEngine<K, V> {
         K: KV + 'static,
         V: KV + 'static,
     {
-        Self::new_with_options::<&str, _>(None, tree, schema_builder, f)
+        Self::new_with_options::<&str, _>(None, tree, schema_builder, CommitPolicy::default(), f)
     }
 
-    // Change unwraps
     /// Create a new search engine with more options.
-    fn new_with_options<P: AsRef<Path> + Clone, F>(
+    /// If `path` is `None` a temporary search engine is created.
+    pub fn new_with_options<P: AsRef<Path> + Clone, F>(
         path: Option<P>,
         tree: &Tree<K, V>,
-        mut schema_builder: SchemaBuilder,
+        schema_builder: SchemaBuilder,
+        commit_policy: CommitPolicy,
         f: F,
     ) -> Result<Self, SearchError>
     where
@@ -170,265 +130,99 @@ impl<K, V> SearchEngine<K, V> {
         K: KV + 'static,
         V: KV + 'static,
     {
-        let key_field = schema_builder.add_bytes_field(
-            "_typed_sled_key",
-            BytesOptions::default()
-                .set_indexed()
-                .set_stored()
-                .set_fast(),
-        );
-        let schema = schema_builder.build();
-
-        let f = move |k: &K, v: &V| {
-            let mut document = f(k, v);
-            document.add_bytes(key_field, serialize(k));
-            document
-        };
-
-        let from_new;
-        let index = if let Some(path) = path {
-            // Todo: this from_new isn't reliable
-            from_new = create_dir_all(path.clone()).is_ok();
-            Index::open_or_create(
-                MmapDirectory::open(path).expect("SearchEngine: failed to open directory"),
-                schema,
-            )?
-        } else {
-            from_new = true;
-            Index::create_from_tempdir(schema)?
-        };
-
-        if from_new {
-            let mut index_writer = index.writer(100_000_000)?;
-            for r in tree.iter() {
-                let (k, v) = r?;
-                index_writer.add_document(f(&k, &v))?;
-            }
-            index_writer.commit()?;
-        }
-
-        let subscriber = tree.watch_all();
-        let mut index_writer = index.writer(5_000_000)?;
-        thread::spawn(move || {
-            for e in subscriber {
-                match e {
-                    Event::Insert { key, value } => {
-                        index_writer
-                            .add_document(f(&key, &value))
-                            .expect("SearchEngine: failed to add document");
-                        index_writer
-                            .commit()
-                            .expect("SearchEngine: failed to commit");
-                    }
-                    Event::Remove { key } => {
-                        index_writer
-                            .delete_term(Term::from_field_bytes(key_field, &serialize(&key)));
-                        index_writer
-                            .commit()
-                            .expect("SearchEngine: failed to commit");
-                    }
-                }
-            }
-        });
-
-        let index_reader = index.reader()?;
-        Ok(SearchEngine {
-            tree: tree.to_owned(),
-            index,
-            index_reader,
-            key_field,
-            phantom_key: PhantomData,
-            phantom_value: PhantomData,
+        // Tree<K, V> uses the same encoding as custom_serde::Tree<K, V, OrderedSerDe>.
+        let tree = custom_serde::Tree::from_sled(tree.inner.clone());
+        Ok(Self {
+            inner: custom_serde::search::SearchEngine::new_with_options(
+                path,
+                &tree,
+                schema_builder,
+                commit_policy,
+                f,
+            )?,
         })
     }
 
-    /// Search for all key value pairs matching a query. The query will be
-    /// parsed by the `QueryParser` from `tantivy`. All fields of `tanvity::schema::Type::Str`
-    /// will be queried. This includes all fields created with `TEXT` or `STRING`.
-    /// For more info on what queries can be used:
-    /// https://docs.rs/tantivy/latest/tantivy/query/struct.QueryParser.html
-    pub fn search(
-        &self,
-        query: impl AsRef<str>,
-        limit: usize,
-    ) -> Result<SearchResults<K, V>, SearchError>
-    where
-        K: KV,
-        V: KV,
-    {
-        use tantivy::schema::Type;
-        // Default types are only String like types
-        let query_parser = QueryParser::for_index(
-            &self.index,
-            self.index
-                .schema()
-                .fields()
-                .filter_map(|(field, ty)| {
-                    if matches!(ty.field_type().value_type(), Type::Str) {
-                        Some(field)
-                    } else {
-                        None
-                    }
-                })
-                .collect(),
-        );
-        let query = query_parser.parse_query(query.as_ref())?;
-
-        self.search_with_query(&query, limit)
-    }
-
-    /// Search for all key value pairs matching a custom query.
-    /// See `tantivy` queries for what type of queries can be used.
-    pub fn search_with_query(
-        &self,
-        query: &dyn Query,
-        limit: usize,
-    ) -> Result<SearchResults<K, V>, SearchError>
+    /// Create a new temporary search engine for a tree with [Searchable] values.
+    /// The schema and the documents are derived from the value type.
+    pub fn for_searchable(tree: &Tree<K, V>) -> Result<Self, SearchError>
     where
-        K: KV,
-        V: KV,
-    {
-        let searcher = self.index_reader.searcher();
-        // println!(
-        //     "{}",
-        //     searcher.search(query, &TopDocs::with_limit(limit))?.len()
-        // );
-
-        let mut v = Vec::new();
-        for (score, doc_addr) in self
-            .search_with_query_and_collector(query, &TopDocs::with_limit(limit))?
-            .iter()
-        {
-            let doc = searcher.doc(*doc_addr)?;
-
-            let key_bytes = if let Value::Bytes(bytes) = doc
-                .get_first(self.key_field)
-                .ok_or(SearchError::DocDoesNotExist)?
-            {
-                bytes
-            } else {
-                panic!();
-            };
-            let kv = self.tree.get_kv_from_raw(key_bytes)?;
-            v.push((*score, kv))
-        }
-        Ok(SearchResults(v))
-    }
-
-    /// Search for all key value pairs matching a query and collect them with a custom collector.
-    /// The query will be parsed by the `QueryParser` from `tantivy`. All fields of the schema
-    /// will be queried.
-    /// See `tantivy` for what type of collectors can be used.
-    pub fn search_with_collector<C: Collector>(
-        &self,
-        query: &str,
-        collector: &C,
-    ) -> Result<C::Fruit, SearchError> {
-        let query_parser = QueryParser::for_index(
-            &self.index,
-            self.index
-                .schema()
-                .fields()
-                .map(|(field, _)| field)
-                .collect(),
-        );
-        let query = query_parser.parse_query(query)?;
-
-        self.search_with_query_and_collector(&query, collector)
-    }
-
-    /// Search for all key value pairs matching a custom query and collect them with a custom collector.
-    /// See `tantivy` for what type of queries and collectors can be used.
-    pub fn search_with_query_and_collector<C: Collector>(
-        &self,
-        query: &dyn Query,
-        collector: &C,
-    ) -> Result<C::Fruit, SearchError> {
-        let searcher = self.index_reader.searcher();
-        Ok(searcher.search(query, collector)?)
-    }
-
-    /// Retrieve the key value pair corresponding to a `DocAdress`.
-    /// This can for example be used after using `search_with_collector` with
-    /// a custom collector which yields `DocAdress`es.
-    pub fn doc_address_to_kv(&self, doc_addr: DocAddress) -> Result<Option<(K, V)>, SearchError>
-    where
-        K: KV,
-        V: KV,
+        K: KV + 'static,
+        V: KV + Searchable + 'static,
     {
-        let doc = self.index_reader.searcher().doc(doc_addr)?;
-
-        let key_bytes = if let Value::Bytes(bytes) = doc
-            .get_first(self.key_field)
-            .ok_or(SearchError::DocDoesNotExist)?
-        {
-            bytes
-        } else {
-            panic!();
-        };
-        let kv = self.tree.get_kv_from_raw(key_bytes)?;
-        Ok(kv)
+        Self::for_searchable_with_options::<&str>(None, tree, CommitPolicy::default())
     }
 
-    /// Retrieve the key value pair corresponding to an Iterator over `DocAdress`.
-    /// This can for example be used after using `search_with_collector` with
-    /// a custom collector which yields `DocAdress`es.
-    pub fn doc_adresses_to_kvs<I: Iterator<Item = DocAddress>>(
-        &self,
-        doc_addrs: I,
-    ) -> Result<Vec<Option<(K, V)>>, SearchError>
+    /// Create a new search engine for a tree with [Searchable] values with more options.
+    /// If `path` is `None` a temporary search engine is created.
+    pub fn for_searchable_with_options<P: AsRef<Path> + Clone>(
+        path: Option<P>,
+        tree: &Tree<K, V>,
+        commit_policy: CommitPolicy,
+    ) -> Result<Self, SearchError>
     where
-        K: KV,
-        V: KV,
+        K: KV + 'static,
+        V: KV + Searchable + 'static,
     {
-        let searcher = self.index_reader.searcher();
-        let mut v = Vec::new();
-        for doc_addr in doc_addrs {
-            let doc = searcher.doc(doc_addr)?;
-            let key_bytes = if let Value::Bytes(bytes) = doc
-                .get_first(self.key_field)
-                .ok_or(SearchError::DocDoesNotExist)?
-            {
-                bytes
-            } else {
-                panic!();
-            };
-            let kv = self.tree.get_kv_from_raw(key_bytes)?;
-            v.push(kv);
-        }
-        Ok(v)
+        let tree = custom_serde::Tree::from_sled(tree.inner.clone());
+        Ok(Self {
+            inner: custom_serde::search::SearchEngine::for_searchable_with_options(
+                path,
+                &tree,
+                commit_policy,
+            )?,
+        })
     }
 
-    /// Get the index of the SearchEngine.
-    pub fn index(&self) -> &Index {
-        &self.index
+    /// Returns the underlying [custom_serde::search::SearchEngine].
+    pub fn inner(&self) -> &custom_serde::search::SearchEngine<K, V, OrderedSerDe> {
+        &self.inner
     }
 }
 
-pub struct SearchResults<K, V>(Vec<(Score, Option<(K, V)>)>);
-
-impl<K, V> std::ops::Deref for SearchResults<K, V> {
-    type Target = Vec<(Score, Option<(K, V)>)>;
+impl<K, V> Deref for SearchEngine<K, V> {
+    type Target = custom_serde::search::SearchEngine<K, V, OrderedSerDe>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.inner
     }
 }
 
-impl<K, V> std::ops::DerefMut for SearchResults<K, V> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::{
+        doc,
+        schema::{Schema, TEXT},
+    };
+
+    #[test]
+    fn test_update_replaces_document() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = Tree::<u64, String>::open(&db, "test_tree");
+        tree.insert(&0, &"old apple".to_owned()).unwrap();
+
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let search_engine = SearchEngine::new_temp(
+            &tree,
+            schema_builder,
+            move |_k, v| doc!(text => v.to_owned()),
+        )
+        .unwrap();
+
+        tree.insert(&0, &"new apple".to_owned()).unwrap();
+        tree.insert(&1, &"banana".to_owned()).unwrap();
+        tree.insert(&0, &"newest apple".to_owned()).unwrap();
+        search_engine.wait_until_indexed().unwrap();
+
+        let results = search_engine.search("apple", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            (results[0].key, results[0].value.as_str()),
+            (0, "newest apple")
+        );
+        assert_eq!(search_engine.search("banana", 10).unwrap().len(), 1);
+        assert!(search_engine.search("old", 10).unwrap().is_empty());
     }
 }
-
-#[derive(Debug, thiserror::Error)]
-pub enum SearchError {
-    #[error("Tree error: {0}")]
-    Tree(#[from] sled::Error),
-    #[error("Search error: {0}")]
-    Search(#[from] tantivy::TantivyError),
-    #[error("Error while parsing query: {0}")]
-    QueryParserError(#[from] tantivy::query::QueryParserError),
-    #[error("Document Address doesn't exist")]
-    DocDoesNotExist,
-}
@@ -0,0 +1,570 @@
+//! Derive the tantivy schema and documents of a [SearchEngine][crate::search::SearchEngine]
+//! from the value type of a tree.
+//!
+//! Types implementing [Searchable] declare which of their fields are indexed and
+//! how. [SearchEngine::for_searchable][crate::search::SearchEngine::for_searchable]
+//! then builds the schema and the documents without a hand written `doc!` closure,
+//! and fields can be referred to by their Rust name, e.g. with
+//! [search_in][crate::custom_serde::search::SearchEngine::search_in].
+//!
+//! With the `derive` feature `Searchable` can be derived. Every field annotated with
+//! `#[search(...)]` is indexed as one of:
+//! * `text`: Tokenized full text, see [TEXT][tantivy::schema::TEXT].
+//! * `string`: A single untokenized term, see [STRING][tantivy::schema::STRING].
+//! * `numeric`: An `i64`, `u64` or `f64` field depending on the Rust type.
+//! * `date`: A date, either a [tantivy::DateTime] or with the `chrono` feature a `chrono::DateTime<Utc>`.
+//! * `facet`: A facet, either a path like `/electronics/phones` or a single segment.
+//!
+//! `Option`s and `Vec`s of the above are supported as well.
+//!
+//! # Example
+//! ```
+//! # #[cfg(feature = "derive")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use serde::{Deserialize, Serialize};
+//! use typed_sled::{search::SearchEngine, searchable::Searchable};
+//!
+//! #[derive(Debug, Clone, Serialize, Deserialize, Searchable)]
+//! struct BlogPost {
+//!     #[search(string)]
+//!     author: String,
+//!     #[search(text)]
+//!     title: String,
+//!     #[search(numeric)]
+//!     likes: u64,
+//!     #[search(facet)]
+//!     tags: Vec<String>,
+//!     // Not indexed.
+//!     body: String,
+//! }
+//!
+//! let db = sled::Config::new().temporary(true).open()?;
+//! let tree = typed_sled::Tree::<u64, BlogPost>::open(&db, "unique_id");
+//! tree.insert(&0, &BlogPost {
+//!     author: "Mike".to_owned(),
+//!     title: "The life of the disillusioned".to_owned(),
+//!     likes: 3,
+//!     tags: vec!["/life".to_owned()],
+//!     body: "Long story short, he didn't have fun.".to_owned(),
+//! })?;
+//!
+//! let search_engine = SearchEngine::for_searchable(&tree)?;
+//! assert_eq!(search_engine.search_in(&["title"], "life", 10)?.len(), 1);
+//! assert_eq!(search_engine.search_in(&["author"], "Mike", 10)?.len(), 1);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "derive"))]
+//! # fn main() {}
+//! ```
+use tantivy::schema::{
+    Cardinality, DateOptions, Facet, FacetOptions, Field, NumericOptions, SchemaBuilder, Value,
+    STRING, TEXT,
+};
+use tantivy::{DateTime, Document};
+
+#[cfg(feature = "derive")]
+pub use typed_sled_derive::Searchable;
+
+/// A type whose fields can be indexed by a [SearchEngine][crate::search::SearchEngine].
+///
+/// # Example
+/// Implementing `Searchable` by hand, which is what `#[derive(Searchable)]` generates.
+/// ```
+/// use typed_sled::searchable::{FieldKind, SearchDocument, SearchField, Searchable};
+///
+/// struct Product {
+///     name: String,
+///     price: f64,
+/// }
+///
+/// impl Searchable for Product {
+///     const FIELDS: &'static [SearchField] = &[
+///         SearchField::new("name", FieldKind::Text),
+///         SearchField::new("price", FieldKind::F64),
+///     ];
+///
+///     fn add_fields(&self, document: &mut SearchDocument) {
+///         document.add_text("name", &self.name);
+///         document.add_numeric("price", &self.price);
+///     }
+/// }
+/// ```
+pub trait Searchable {
+    /// The indexed fields.
+    const FIELDS: &'static [SearchField];
+
+    /// Add the values of the indexed fields to `document`.
+    fn add_fields(&self, document: &mut SearchDocument);
+}
+
+/// How a field of a [Searchable] type is indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldKind {
+    /// Tokenized full text.
+    Text,
+    /// A single untokenized term.
+    String,
+    I64,
+    U64,
+    F64,
+    Date,
+    Facet,
+}
+
+/// A field declared by [Searchable::FIELDS].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SearchField {
+    /// The name of the field in the tantivy schema.
+    pub name: &'static str,
+    pub kind: FieldKind,
+    /// Whether a value can have more than one value for this field.
+    pub multi_valued: bool,
+}
+
+impl SearchField {
+    /// A field with at most one value.
+    pub const fn new(name: &'static str, kind: FieldKind) -> Self {
+        Self {
+            name,
+            kind,
+            multi_valued: false,
+        }
+    }
+
+    /// A field which may have more than one value.
+    pub const fn multi_valued(name: &'static str, kind: FieldKind) -> Self {
+        Self {
+            name,
+            kind,
+            multi_valued: true,
+        }
+    }
+}
+
+/// The tantivy fields of a [Searchable] type.
+#[derive(Debug, Clone)]
+pub struct SearchFields {
+    fields: Vec<(SearchField, Field)>,
+}
+
+impl SearchFields {
+    /// Add the fields of `S` to a schema.
+    ///
+    /// Numeric and date fields are indexed and fast fields, so they can be
+    /// used in range queries and aggregations.
+    pub fn new<S: Searchable + ?Sized>(schema_builder: &mut SchemaBuilder) -> Self {
+        let fields = S::FIELDS
+            .iter()
+            .map(|search_field| {
+                let cardinality = if search_field.multi_valued {
+                    Cardinality::MultiValues
+                } else {
+                    Cardinality::SingleValue
+                };
+                let numeric_options = NumericOptions::default()
+                    .set_indexed()
+                    .set_fast(cardinality);
+                let name = search_field.name;
+                let field = match search_field.kind {
+                    FieldKind::Text => schema_builder.add_text_field(name, TEXT),
+                    FieldKind::String => schema_builder.add_text_field(name, STRING),
+                    FieldKind::I64 => schema_builder.add_i64_field(name, numeric_options),
+                    FieldKind::U64 => schema_builder.add_u64_field(name, numeric_options),
+                    FieldKind::F64 => schema_builder.add_f64_field(name, numeric_options),
+                    FieldKind::Date => schema_builder.add_date_field(
+                        name,
+                        DateOptions::default().set_indexed().set_fast(cardinality),
+                    ),
+                    FieldKind::Facet => {
+                        schema_builder.add_facet_field(name, FacetOptions::default())
+                    }
+                };
+                (*search_field, field)
+            })
+            .collect();
+        Self { fields }
+    }
+
+    /// Returns the tantivy field with the given name.
+    pub fn get(&self, name: &str) -> Option<Field> {
+        self.find(name).map(|(_, field)| *field)
+    }
+
+    /// Build the document of a value.
+    pub fn document<S: Searchable + ?Sized>(&self, value: &S) -> Document {
+        let mut document = SearchDocument {
+            fields: self,
+            document: Document::new(),
+        };
+        value.add_fields(&mut document);
+        document.document
+    }
+
+    fn find(&self, name: &str) -> Option<&(SearchField, Field)> {
+        self.fields
+            .iter()
+            .find(|(search_field, _)| search_field.name == name)
+    }
+}
+
+/// The document of a [Searchable] value, see [Searchable::add_fields].
+pub struct SearchDocument<'a> {
+    fields: &'a SearchFields,
+    document: Document,
+}
+
+impl<'a> SearchDocument<'a> {
+    /// Add text to a `Text`, `String` or `Facet` field.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field isn't declared in [Searchable::FIELDS] or has another kind.
+    pub fn add_text<T: TextValue + ?Sized>(&mut self, name: &str, value: &T) {
+        let (kind, field) = self.field(name);
+        let document = &mut self.document;
+        match kind {
+            FieldKind::Text | FieldKind::String => {
+                value.text_values(&mut |text| document.add_text(field, text))
+            }
+            FieldKind::Facet => value.text_values(&mut |text| {
+                let facet = if text.starts_with('/') {
+                    Facet::from_text(text).expect("a path starting with / is a valid facet")
+                } else {
+                    Facet::from_path(Some(text))
+                };
+                document.add_facet(field, facet)
+            }),
+            kind => panic!("can't add text to the {:?} field {:?}", kind, name),
+        }
+    }
+
+    /// Add a number to an `I64`, `U64` or `F64` field.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field isn't declared in [Searchable::FIELDS] or has another kind.
+    pub fn add_numeric<T: NumericValue + ?Sized>(&mut self, name: &str, value: &T) {
+        let (kind, field) = self.field(name);
+        let document = &mut self.document;
+        match kind {
+            FieldKind::I64 | FieldKind::U64 | FieldKind::F64 => {
+                value.numeric_values(&mut |number| {
+                    document.add_field_value(field, number.to_value(kind))
+                })
+            }
+            kind => panic!("can't add a number to the {:?} field {:?}", kind, name),
+        }
+    }
+
+    /// Add a date to a `Date` field.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field isn't declared in [Searchable::FIELDS] or has another kind.
+    pub fn add_date<T: DateValue + ?Sized>(&mut self, name: &str, value: &T) {
+        let (kind, field) = self.field(name);
+        let document = &mut self.document;
+        match kind {
+            FieldKind::Date => value.date_values(&mut |date| document.add_date(field, date)),
+            kind => panic!("can't add a date to the {:?} field {:?}", kind, name),
+        }
+    }
+
+    fn field(&self, name: &str) -> (FieldKind, Field) {
+        match self.fields.find(name) {
+            Some((search_field, field)) => (search_field.kind, *field),
+            None => panic!("the field {:?} isn't declared in Searchable::FIELDS", name),
+        }
+    }
+}
+
+/// A number of a [NumericValue].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    I64(i64),
+    U64(u64),
+    F64(f64),
+}
+
+impl Number {
+    // Numbers are converted like `as` does.
+    fn to_value(self, kind: FieldKind) -> Value {
+        match (self, kind) {
+            (Number::I64(n), FieldKind::U64) => Value::U64(n as u64),
+            (Number::I64(n), FieldKind::F64) => Value::F64(n as f64),
+            (Number::I64(n), _) => Value::I64(n),
+            (Number::U64(n), FieldKind::I64) => Value::I64(n as i64),
+            (Number::U64(n), FieldKind::F64) => Value::F64(n as f64),
+            (Number::U64(n), _) => Value::U64(n),
+            (Number::F64(n), FieldKind::I64) => Value::I64(n as i64),
+            (Number::F64(n), FieldKind::U64) => Value::U64(n as u64),
+            (Number::F64(n), _) => Value::F64(n),
+        }
+    }
+}
+
+/// Types which can be indexed in a `text`, `string` or `facet` field.
+pub trait TextValue {
+    /// `true` for types which may have more than one value.
+    const MULTI_VALUED: bool = false;
+
+    /// Call `f` with every value.
+    fn text_values(&self, f: &mut dyn FnMut(&str));
+}
+
+/// Types which can be indexed in a `numeric` field.
+pub trait NumericValue {
+    /// The kind of field used for the type.
+    const KIND: FieldKind;
+    /// `true` for types which may have more than one value.
+    const MULTI_VALUED: bool = false;
+
+    /// Call `f` with every value.
+    fn numeric_values(&self, f: &mut dyn FnMut(Number));
+}
+
+/// Types which can be indexed in a `date` field.
+pub trait DateValue {
+    /// `true` for types which may have more than one value.
+    const MULTI_VALUED: bool = false;
+
+    /// Call `f` with every value.
+    fn date_values(&self, f: &mut dyn FnMut(DateTime));
+}
+
+impl TextValue for str {
+    fn text_values(&self, f: &mut dyn FnMut(&str)) {
+        f(self)
+    }
+}
+
+impl TextValue for String {
+    fn text_values(&self, f: &mut dyn FnMut(&str)) {
+        f(self)
+    }
+}
+
+macro_rules! impl_numeric_value {
+    ($kind:ident, $variant:ident, $as:ty, $($ty:ty),*) => {
+        $(
+            impl NumericValue for $ty {
+                const KIND: FieldKind = FieldKind::$kind;
+
+                fn numeric_values(&self, f: &mut dyn FnMut(Number)) {
+                    f(Number::$variant(*self as $as))
+                }
+            }
+        )*
+    };
+}
+
+impl_numeric_value!(I64, I64, i64, i8, i16, i32, i64, isize);
+impl_numeric_value!(U64, U64, u64, u8, u16, u32, u64, usize);
+impl_numeric_value!(F64, F64, f64, f32, f64);
+
+impl NumericValue for bool {
+    const KIND: FieldKind = FieldKind::U64;
+
+    fn numeric_values(&self, f: &mut dyn FnMut(Number)) {
+        f(Number::U64(u64::from(*self)))
+    }
+}
+
+impl DateValue for DateTime {
+    fn date_values(&self, f: &mut dyn FnMut(DateTime)) {
+        f(*self)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl DateValue for chrono::DateTime<chrono::Utc> {
+    fn date_values(&self, f: &mut dyn FnMut(DateTime)) {
+        f(DateTime::from_timestamp_micros(self.timestamp_micros()))
+    }
+}
+
+// Forwarding implementations for references, options and collections.
+macro_rules! impl_wrappers {
+    ($trait:ident, $method:ident, $arg:ty $(, $kind:ident)?) => {
+        impl<T: $trait + ?Sized> $trait for &T {
+            $(const $kind: FieldKind = T::$kind;)?
+            const MULTI_VALUED: bool = T::MULTI_VALUED;
+
+            fn $method(&self, f: &mut dyn FnMut($arg)) {
+                (**self).$method(f)
+            }
+        }
+
+        impl<T: $trait + ?Sized> $trait for Box<T> {
+            $(const $kind: FieldKind = T::$kind;)?
+            const MULTI_VALUED: bool = T::MULTI_VALUED;
+
+            fn $method(&self, f: &mut dyn FnMut($arg)) {
+                (**self).$method(f)
+            }
+        }
+
+        impl<T: $trait> $trait for Option<T> {
+            $(const $kind: FieldKind = T::$kind;)?
+            const MULTI_VALUED: bool = T::MULTI_VALUED;
+
+            fn $method(&self, f: &mut dyn FnMut($arg)) {
+                if let Some(value) = self {
+                    value.$method(f)
+                }
+            }
+        }
+
+        impl<T: $trait> $trait for [T] {
+            $(const $kind: FieldKind = T::$kind;)?
+            const MULTI_VALUED: bool = true;
+
+            fn $method(&self, f: &mut dyn FnMut($arg)) {
+                for value in self {
+                    value.$method(f)
+                }
+            }
+        }
+
+        impl<T: $trait> $trait for Vec<T> {
+            $(const $kind: FieldKind = T::$kind;)?
+            const MULTI_VALUED: bool = true;
+
+            fn $method(&self, f: &mut dyn FnMut($arg)) {
+                self.as_slice().$method(f)
+            }
+        }
+    };
+}
+
+impl_wrappers!(TextValue, text_values, &str);
+impl_wrappers!(NumericValue, numeric_values, Number, KIND);
+impl_wrappers!(DateValue, date_values, DateTime);
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use super::*;
+    use crate::search::{FacetRequest, FacetResult, SearchEngine, SearchError};
+    use serde::{Deserialize, Serialize};
+    use tantivy::query::{AllQuery, RangeQuery, TermQuery};
+    use tantivy::schema::IndexRecordOption;
+    use tantivy::Term;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Searchable)]
+    struct Product {
+        #[search(text)]
+        name: String,
+        #[search(string)]
+        brand: Option<String>,
+        #[search(numeric)]
+        price: f64,
+        #[search(numeric)]
+        ratings: Vec<u8>,
+        #[search(facet)]
+        category: String,
+        description: String,
+    }
+
+    fn product(name: &str, brand: Option<&str>, price: f64, category: &str) -> Product {
+        Product {
+            name: name.to_owned(),
+            brand: brand.map(str::to_owned),
+            price,
+            ratings: vec![4, 5],
+            category: category.to_owned(),
+            description: "not indexed".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_derive_searchable() {
+        assert_eq!(
+            Product::FIELDS,
+            &[
+                SearchField::new("name", FieldKind::Text),
+                SearchField::new("brand", FieldKind::String),
+                SearchField::new("price", FieldKind::F64),
+                SearchField::multi_valued("ratings", FieldKind::U64),
+                SearchField::new("category", FieldKind::Facet),
+            ]
+        );
+
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = crate::Tree::<u64, Product>::open(&db, "products");
+        tree.insert(
+            &0,
+            &product("Red Phone", Some("Acme Inc"), 300.0, "/electronics/phones"),
+        )
+        .unwrap();
+        tree.insert(&1, &product("Blue Laptop", None, 1200.0, "electronics"))
+            .unwrap();
+        tree.insert(&2, &product("Red Chair", Some("Acme"), 80.0, "/furniture"))
+            .unwrap();
+        let search_engine = SearchEngine::for_searchable(&tree).unwrap();
+
+        assert_eq!(
+            search_engine.search_in(&["name"], "red", 10).unwrap().len(),
+            2
+        );
+        // String fields aren't tokenized.
+        let brand = search_engine.field("brand").unwrap();
+        let query = TermQuery::new(
+            Term::from_field_text(brand, "Acme Inc"),
+            IndexRecordOption::Basic,
+        );
+        assert_eq!(
+            search_engine.search_with_query(&query, 10).unwrap().len(),
+            1
+        );
+        assert!(search_engine
+            .search_in(&["description"], "indexed", 10)
+            .is_err());
+        assert!(matches!(
+            search_engine.field("description"),
+            Err(SearchError::UnknownField(_))
+        ));
+
+        let price = search_engine.field("price").unwrap();
+        let query = RangeQuery::new_f64(price, 50.0..500.0);
+        assert_eq!(
+            search_engine.search_with_query(&query, 10).unwrap().len(),
+            2
+        );
+
+        let category = search_engine.field("category").unwrap();
+        let query = TermQuery::new(
+            Term::from_facet(category, &Facet::from_text("/electronics").unwrap()),
+            IndexRecordOption::Basic,
+        );
+        // Facets match their children as well.
+        let results = search_engine.search_with_query(&query, 10).unwrap();
+        let mut keys: Vec<u64> = results.iter().map(|r| r.key).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![0, 1]);
+
+        let results = search_engine
+            .search_with_query_and_facets(
+                &AllQuery,
+                0,
+                &[
+                    FacetRequest::facet("category", "/"),
+                    FacetRequest::ranges("price", vec![0.0..100.0, 100.0..1000.0]),
+                    // Every product is counted once per range.
+                    FacetRequest::ranges("ratings", vec![0.0..4.5, 4.0..6.0]),
+                ],
+            )
+            .unwrap();
+        assert_eq!(results.total_hits, 3);
+        assert_eq!(
+            results.facets,
+            vec![
+                FacetResult::Facet(vec![
+                    ("/electronics".to_owned(), 2),
+                    ("/furniture".to_owned(), 1)
+                ]),
+                FacetResult::Ranges(vec![(0.0..100.0, 1), (100.0..1000.0, 1)]),
+                FacetResult::Ranges(vec![(0.0..4.5, 3), (4.0..6.0, 3)]),
+            ]
+        );
+    }
+}
@@ -1,132 +1,484 @@
+use std::any::Any;
+use std::fmt;
 use std::marker::PhantomData;
 
-use sled::transaction::{ConflictableTransactionResult, TransactionResult};
+use sled::IVec;
 
-use crate::{deserialize, serialize, Batch, Tree, KV};
+use crate::{decode_value, encode_key, encode_value, Batch, Error, Tree, KV};
+
+/// An error that can occur while working with a [TransactionalTree].
+///
+/// It can be converted into a [ConflictableTransactionError] with `?` inside of a
+/// transaction closure. It also converts into a `sled::transaction::ConflictableTransactionError`,
+/// in which case serialization and deserialization errors are converted to a
+/// `sled::Error::Io` of kind `InvalidData`, which contains the typed-sled [Error].
+#[derive(Debug, thiserror::Error)]
+pub enum UnabortableTransactionError {
+    /// The transaction conflicted with another transaction and will be retried.
+    #[error("transaction conflict")]
+    Conflict,
+    /// An error of the underlying database or a (de)serialization error.
+    #[error(transparent)]
+    Storage(#[from] Error),
+}
+
+impl From<sled::transaction::UnabortableTransactionError> for UnabortableTransactionError {
+    fn from(error: sled::transaction::UnabortableTransactionError) -> Self {
+        match error {
+            sled::transaction::UnabortableTransactionError::Conflict => Self::Conflict,
+            sled::transaction::UnabortableTransactionError::Storage(e) => {
+                Self::Storage(Error::Sled(e))
+            }
+        }
+    }
+}
+
+impl<E> From<UnabortableTransactionError> for sled::transaction::ConflictableTransactionError<E> {
+    fn from(error: UnabortableTransactionError) -> Self {
+        match error {
+            UnabortableTransactionError::Conflict => Self::Conflict,
+            UnabortableTransactionError::Storage(Error::Sled(e)) => Self::Storage(e),
+            UnabortableTransactionError::Storage(e) => Self::Storage(sled::Error::Io(
+                std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+            )),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, UnabortableTransactionError>;
+
+/// The result of a transaction closure.
+pub type ConflictableTransactionResult<A> = std::result::Result<A, ConflictableTransactionError>;
+
+/// The result of a transaction.
+pub type TransactionResult<A> = std::result::Result<A, TransactionError>;
+
+/// An error returned from a transaction closure.
+///
+/// Errors of a [TransactionalTree] are converted into it with `?`. Use [abort]
+/// to end the transaction with an error of your own.
+#[derive(Debug, thiserror::Error)]
+pub enum ConflictableTransactionError {
+    /// End the transaction without applying its changes, see [abort].
+    #[error(transparent)]
+    Abort(Aborted),
+    /// The transaction conflicted with another transaction and will be retried.
+    #[error("transaction conflict")]
+    Conflict,
+    /// An error of the underlying database or a (de)serialization error,
+    /// which ends the transaction.
+    #[error(transparent)]
+    Storage(#[from] Error),
+}
+
+impl From<UnabortableTransactionError> for ConflictableTransactionError {
+    fn from(error: UnabortableTransactionError) -> Self {
+        match error {
+            UnabortableTransactionError::Conflict => Self::Conflict,
+            UnabortableTransactionError::Storage(e) => Self::Storage(e),
+        }
+    }
+}
+
+impl ConflictableTransactionError {
+    /// Conflicts and sled errors are handled by sled, everything else aborts the
+    /// sled transaction, so that (de)serialization errors are returned as they are.
+    pub(crate) fn into_sled(self) -> sled::transaction::ConflictableTransactionError<Failure> {
+        match self {
+            Self::Abort(aborted) => {
+                sled::transaction::ConflictableTransactionError::Abort(Failure::Abort(aborted))
+            }
+            Self::Conflict => sled::transaction::ConflictableTransactionError::Conflict,
+            Self::Storage(Error::Sled(e)) => {
+                sled::transaction::ConflictableTransactionError::Storage(e)
+            }
+            Self::Storage(e) => {
+                sled::transaction::ConflictableTransactionError::Abort(Failure::Storage(e))
+            }
+        }
+    }
+}
+
+/// The error of a failed transaction.
+#[derive(Debug, thiserror::Error)]
+pub enum TransactionError {
+    /// The transaction was aborted with [abort].
+    #[error(transparent)]
+    Abort(Aborted),
+    /// An error of the underlying database or a (de)serialization error.
+    #[error(transparent)]
+    Storage(#[from] Error),
+}
+
+impl TransactionError {
+    pub(crate) fn from_sled(error: sled::transaction::TransactionError<Failure>) -> Self {
+        match error {
+            sled::transaction::TransactionError::Abort(Failure::Abort(aborted)) => {
+                Self::Abort(aborted)
+            }
+            sled::transaction::TransactionError::Abort(Failure::Storage(e)) => Self::Storage(e),
+            sled::transaction::TransactionError::Storage(e) => Self::Storage(Error::Sled(e)),
+        }
+    }
+}
+
+/// The abort error of a sled transaction run by typed-sled.
+pub(crate) enum Failure {
+    Abort(Aborted),
+    Storage(Error),
+}
+
+/// Abort a transaction with `error`, which can be retrieved from the
+/// [TransactionError::Abort] with [Aborted::downcast].
+///
+/// # Example
+/// ```
+/// use typed_sled::transaction::{abort, TransactionError};
+///
+/// let db = sled::Config::new().temporary(true).open().unwrap();
+/// let tree = typed_sled::Tree::<String, u32>::open(&db, "stock");
+///
+/// let result = tree.transaction(|tree| {
+///     let stock = tree.get(&"apple".to_owned())?.unwrap_or(0);
+///     if stock == 0 {
+///         return abort("out of stock");
+///     }
+///     tree.insert(&"apple".to_owned(), &(stock - 1))?;
+///     Ok(stock - 1)
+/// });
+///
+/// match result {
+///     Err(TransactionError::Abort(aborted)) => {
+///         assert_eq!(aborted.downcast::<&str>().unwrap(), "out of stock")
+///     }
+///     _ => unreachable!(),
+/// }
+/// ```
+pub fn abort<A, E>(error: E) -> ConflictableTransactionResult<A>
+where
+    E: fmt::Debug + Send + Sync + 'static,
+{
+    Err(ConflictableTransactionError::Abort(Aborted::new(error)))
+}
+
+/// The error a transaction was aborted with, see [abort].
+pub struct Aborted(Box<dyn AbortError>);
+
+trait AbortError: fmt::Debug + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<E: fmt::Debug + Send + Sync + 'static> AbortError for E {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Aborted {
+    pub fn new<E: fmt::Debug + Send + Sync + 'static>(error: E) -> Self {
+        Self(Box::new(error))
+    }
+
+    /// Returns `true` if the transaction was aborted with an `E`.
+    pub fn is<E: 'static>(&self) -> bool {
+        (*self.0).as_any().is::<E>()
+    }
+
+    pub fn downcast_ref<E: 'static>(&self) -> Option<&E> {
+        (*self.0).as_any().downcast_ref()
+    }
+
+    /// Get the error the transaction was aborted with, or `self` if it isn't an `E`.
+    pub fn downcast<E: 'static>(self) -> std::result::Result<E, Self> {
+        if self.is::<E>() {
+            Ok(*self.0.into_any().downcast().unwrap())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Debug for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Aborted").field(&self.0).finish()
+    }
+}
+
+impl fmt::Display for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transaction aborted: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for Aborted {}
 
 pub struct TransactionalTree<'a, K, V> {
-    inner: &'a sled::transaction::TransactionalTree,
+    pub(crate) inner: &'a sled::transaction::TransactionalTree,
+    pub(crate) tree: &'a [u8],
     _key: PhantomData<fn() -> K>,
     _value: PhantomData<fn() -> V>,
 }
 
 impl<'a, K, V> TransactionalTree<'a, K, V> {
-    pub(crate) fn new(sled: &'a sled::transaction::TransactionalTree) -> Self {
+    pub(crate) fn new(sled: &'a sled::transaction::TransactionalTree, tree: &'a [u8]) -> Self {
         Self {
             inner: sled,
+            tree,
             _key: PhantomData,
             _value: PhantomData,
         }
     }
 
-    pub fn insert(
-        &self,
-        key: &K,
-        value: &V,
-    ) -> std::result::Result<Option<V>, sled::transaction::UnabortableTransactionError>
+    pub fn insert(&self, key: &K, value: &V) -> Result<Option<V>>
     where
         K: KV,
         V: KV,
     {
-        self.inner
-            .insert(serialize(key), serialize(value))
-            .map(|opt| opt.map(|v| deserialize(&v)))
+        let key_bytes = encode_key(key)?;
+        let old_value = self
+            .inner
+            .insert(key_bytes.as_slice(), encode_value(value)?)?;
+        self.decode_opt_value(&key_bytes, old_value)
     }
 
-    pub fn remove(
-        &self,
-        key: &K,
-    ) -> std::result::Result<Option<V>, sled::transaction::UnabortableTransactionError>
+    pub fn remove(&self, key: &K) -> Result<Option<V>>
     where
         K: KV,
         V: KV,
     {
-        self.inner
-            .remove(serialize(key))
-            .map(|opt| opt.map(|v| deserialize(&v)))
+        let key_bytes = encode_key(key)?;
+        let old_value = self.inner.remove(key_bytes.as_slice())?;
+        self.decode_opt_value(&key_bytes, old_value)
     }
 
-    pub fn get(
-        &self,
-        key: &K,
-    ) -> std::result::Result<Option<V>, sled::transaction::UnabortableTransactionError>
+    pub fn get(&self, key: &K) -> Result<Option<V>>
     where
         K: KV,
         V: KV,
     {
-        self.inner
-            .get(serialize(key))
-            .map(|opt| opt.map(|v| deserialize(&v)))
+        let key_bytes = encode_key(key)?;
+        let value = self.inner.get(&key_bytes)?;
+        self.decode_opt_value(&key_bytes, value)
     }
 
-    pub fn apply_batch(
-        &self,
-        batch: &Batch<K, V>,
-    ) -> std::result::Result<(), sled::transaction::UnabortableTransactionError> {
-        self.inner.apply_batch(&batch.inner)
+    pub fn apply_batch(&self, batch: &Batch<K, V>) -> Result<()> {
+        Ok(self.inner.apply_batch(&batch.inner)?)
     }
 
     pub fn flush(&self) {
         self.inner.flush()
     }
 
-    pub fn generate_id(&self) -> sled::Result<u64> {
-        self.inner.generate_id()
+    pub fn generate_id(&self) -> crate::Result<u64> {
+        Ok(self.inner.generate_id()?)
+    }
+
+    fn decode_opt_value(&self, key_bytes: &[u8], value: Option<IVec>) -> Result<Option<V>>
+    where
+        V: KV,
+    {
+        Ok(value
+            .map(|v| decode_value(self.tree, key_bytes, &v))
+            .transpose()?)
     }
 }
 
-pub trait Transactional<E = ()> {
+/// A reference to a typed tree which can take part in a transaction over several trees,
+/// see [Transactional] and [TransactionBuilder].
+pub trait TransactionTarget {
+    /// The typed view of the tree inside of a transaction.
     type View<'a>;
 
-    fn transaction<F, A>(&self, f: F) -> TransactionResult<A, E>
+    /// The sled tree the view works on.
+    fn sled_tree(&self) -> &sled::Tree;
+
+    /// Wrap `tree`, the transactional version of [sled_tree][TransactionTarget::sled_tree]
+    /// whose name is `name`, in a typed view.
+    fn view<'a>(
+        &self,
+        tree: &'a sled::transaction::TransactionalTree,
+        name: &'a [u8],
+    ) -> Self::View<'a>;
+}
+
+impl<K, V> TransactionTarget for &Tree<K, V> {
+    type View<'a> = TransactionalTree<'a, K, V>;
+
+    fn sled_tree(&self) -> &sled::Tree {
+        &self.inner
+    }
+
+    fn view<'a>(
+        &self,
+        tree: &'a sled::transaction::TransactionalTree,
+        name: &'a [u8],
+    ) -> Self::View<'a> {
+        TransactionalTree::new(tree, name)
+    }
+}
+
+/// A transaction over several trees, which can be of different kinds,
+/// e.g. a [Tree], a [custom_serde::Tree][crate::custom_serde::Tree] and a `KeyGeneratingTree`.
+///
+/// Implemented for tuples of up to 11 [TransactionTarget]s. Use a [TransactionBuilder]
+/// for more trees.
+pub trait Transactional {
+    type View<'a>;
+
+    fn transaction<F, A>(&self, f: F) -> TransactionResult<A>
     where
-        F: for<'a> Fn(Self::View<'a>) -> ConflictableTransactionResult<A, E>;
+        F: for<'a> Fn(Self::View<'a>) -> ConflictableTransactionResult<A>;
 }
 
 macro_rules! impl_transactional {
-  ($($k:ident, $v:ident, $i:tt),+) => {
-      impl<E, $($k, $v),+> Transactional<E> for ($(&Tree<$k, $v>),+) {
+  ($($t:ident, $i:tt),+) => {
+      impl<$($t: TransactionTarget),+> Transactional for ($($t),+) {
           type View<'a> = (
-              $(TransactionalTree<'a, $k, $v>),+
+              $($t::View<'a>),+
           );
 
-          fn transaction<F, A>(&self, f: F) -> TransactionResult<A, E>
+          fn transaction<F, A>(&self, f: F) -> TransactionResult<A>
           where
-              F: for<'a> Fn(Self::View<'a>) -> ConflictableTransactionResult<A, E>,
+              F: for<'a> Fn(Self::View<'a>) -> ConflictableTransactionResult<A>,
           {
               use sled::Transactional;
 
-              ($(&self.$i.inner),+).transaction(|trees| {
-                  f((
-                      $(TransactionalTree::new(&trees.$i)),+
-                  ))
-              })
+              let names = ($(self.$i.sled_tree().name()),+);
+              ($(self.$i.sled_tree()),+)
+                  .transaction(|trees| {
+                      f((
+                          $(self.$i.view(&trees.$i, &names.$i)),+
+                      ))
+                      .map_err(ConflictableTransactionError::into_sled)
+                  })
+                  .map_err(TransactionError::from_sled)
           }
       }
   };
 }
 
-impl_transactional!(K0, V0, 0, K1, V1, 1);
-impl_transactional!(K0, V0, 0, K1, V1, 1, K2, V2, 2);
-impl_transactional!(K0, V0, 0, K1, V1, 1, K2, V2, 2, K3, V3, 3);
-impl_transactional!(K0, V0, 0, K1, V1, 1, K2, V2, 2, K3, V3, 3, K4, V4, 4);
-impl_transactional!(K0, V0, 0, K1, V1, 1, K2, V2, 2, K3, V3, 3, K4, V4, 4, K5, V5, 5);
-impl_transactional!(K0, V0, 0, K1, V1, 1, K2, V2, 2, K3, V3, 3, K4, V4, 4, K5, V5, 5, K6, V6, 6);
-impl_transactional!(
-    K0, V0, 0, K1, V1, 1, K2, V2, 2, K3, V3, 3, K4, V4, 4, K5, V5, 5, K6, V6, 6, K7, V7, 7
-);
-impl_transactional!(
-    K0, V0, 0, K1, V1, 1, K2, V2, 2, K3, V3, 3, K4, V4, 4, K5, V5, 5, K6, V6, 6, K7, V7, 7, K8, V8,
-    8
-);
-impl_transactional!(
-    K0, V0, 0, K1, V1, 1, K2, V2, 2, K3, V3, 3, K4, V4, 4, K5, V5, 5, K6, V6, 6, K7, V7, 7, K8, V8,
-    8, K9, V9, 9
-);
-impl_transactional!(
-    K0, V0, 0, K1, V1, 1, K2, V2, 2, K3, V3, 3, K4, V4, 4, K5, V5, 5, K6, V6, 6, K7, V7, 7, K8, V8,
-    8, K9, V9, 9, K10, V10, 10
-);
+impl_transactional!(T0, 0, T1, 1);
+impl_transactional!(T0, 0, T1, 1, T2, 2);
+impl_transactional!(T0, 0, T1, 1, T2, 2, T3, 3);
+impl_transactional!(T0, 0, T1, 1, T2, 2, T3, 3, T4, 4);
+impl_transactional!(T0, 0, T1, 1, T2, 2, T3, 3, T4, 4, T5, 5);
+impl_transactional!(T0, 0, T1, 1, T2, 2, T3, 3, T4, 4, T5, 5, T6, 6);
+impl_transactional!(T0, 0, T1, 1, T2, 2, T3, 3, T4, 4, T5, 5, T6, 6, T7, 7);
+impl_transactional!(T0, 0, T1, 1, T2, 2, T3, 3, T4, 4, T5, 5, T6, 6, T7, 7, T8, 8);
+impl_transactional!(T0, 0, T1, 1, T2, 2, T3, 3, T4, 4, T5, 5, T6, 6, T7, 7, T8, 8, T9, 9);
+impl_transactional!(T0, 0, T1, 1, T2, 2, T3, 3, T4, 4, T5, 5, T6, 6, T7, 7, T8, 8, T9, 9, T10, 10);
+
+/// Builds a transaction over any number of trees, which can be of different kinds.
+///
+/// # Example
+/// ```
+/// use typed_sled::transaction::TransactionBuilder;
+/// use typed_sled::{custom_serde, Tree};
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let db = sled::Config::new().temporary(true).open().unwrap();
+///     let trees: Vec<Tree<u32, u32>> = (0..16)
+///         .map(|i| Tree::open(&db, format!("tree{}", i)))
+///         .collect();
+///     let lazy = custom_serde::Tree::<u32, u32, custom_serde::serialize::BincodeSerDeLazy>::open(&db, "lazy");
+///
+///     let mut builder = TransactionBuilder::new();
+///     let handles: Vec<_> = trees.iter().map(|tree| builder.add(tree)).collect();
+///     let lazy_handle = builder.add(&lazy);
+///
+///     builder.transaction(|trees| {
+///         for (i, handle) in handles.iter().enumerate() {
+///             trees.get(handle).insert(&0, &(i as u32))?;
+///         }
+///         trees.get(&lazy_handle).insert(&0, &16)?;
+///         Ok(())
+///     })?;
+///
+///     assert_eq!(trees[15].get(&0)?, Some(15));
+///     assert_eq!(lazy.get(&0)?.unwrap().deserialize(), 16);
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TransactionBuilder {
+    trees: Vec<sled::Tree>,
+}
+
+/// A tree added to a [TransactionBuilder], which selects its view inside of the transaction.
+#[derive(Debug, Clone, Copy)]
+pub struct TreeHandle<T> {
+    index: usize,
+    target: T,
+}
+
+impl TransactionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a tree to the transaction, returning the handle to get its view with
+    /// [TransactionalTrees::get]. Adding a tree twice returns a handle to the same tree.
+    pub fn add<T: TransactionTarget>(&mut self, target: T) -> TreeHandle<T> {
+        let name = target.sled_tree().name();
+        let index = match self.trees.iter().position(|tree| tree.name() == name) {
+            Some(index) => index,
+            None => {
+                self.trees.push(target.sled_tree().clone());
+                self.trees.len() - 1
+            }
+        };
+        TreeHandle { index, target }
+    }
+
+    /// Perform a serializable transaction over all added trees.
+    pub fn transaction<F, A>(&self, f: F) -> TransactionResult<A>
+    where
+        F: Fn(&TransactionalTrees) -> ConflictableTransactionResult<A>,
+    {
+        use sled::Transactional;
+
+        let names: Vec<IVec> = self.trees.iter().map(|tree| tree.name()).collect();
+        self.trees
+            .as_slice()
+            .transaction(|trees| {
+                f(&TransactionalTrees {
+                    trees,
+                    names: &names,
+                })
+                .map_err(ConflictableTransactionError::into_sled)
+            })
+            .map_err(TransactionError::from_sled)
+    }
+}
+
+/// The trees of a [TransactionBuilder] inside of the transaction.
+pub struct TransactionalTrees<'a> {
+    trees: &'a [sled::transaction::TransactionalTree],
+    names: &'a [IVec],
+}
+
+impl<'a> TransactionalTrees<'a> {
+    /// Get the view of a tree added to the [TransactionBuilder].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handle was returned by a builder which doesn't contain the
+    /// same tree at the same position.
+    pub fn get<T: TransactionTarget>(&self, handle: &TreeHandle<T>) -> T::View<'a> {
+        let name = self
+            .names
+            .get(handle.index)
+            .filter(|name| **name == handle.target.sled_tree().name())
+            .expect("the tree handle was returned by another TransactionBuilder");
+        handle.target.view(&self.trees[handle.index], name)
+    }
+}
 
 #[test]
 fn test_multiple_tree_transaction() {
@@ -140,12 +492,141 @@ fn test_multiple_tree_transaction() {
             trees.0.insert(&0, &0)?;
             trees.1.insert(&0, &0)?;
             trees.2.insert(&0, &0)?;
-            // Todo: E in ConflitableTransactionResult<A, E> is not inferred
-            // automatically, although Transactional<E = ()> has default E = () type.
-            Ok::<(), sled::transaction::ConflictableTransactionError<()>>(())
+            Ok(())
         })
         .unwrap();
 
-    assert_eq!(tree0.get(&0), Ok(Some(0)));
-    assert_eq!(tree1.get(&0), Ok(Some(0)));
+    assert_eq!(tree0.get(&0).unwrap(), Some(0));
+    assert_eq!(tree1.get(&0).unwrap(), Some(0));
+}
+
+#[cfg(feature = "key-generating")]
+#[test]
+fn test_mixed_tree_transaction() {
+    use crate::custom_serde::{self, serialize::BincodeSerDeLazy};
+    use crate::key_generating::CounterTree;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let orders = CounterTree::<String>::open(&db, "orders");
+    let lines = custom_serde::Tree::<(u64, u32), String, BincodeSerDeLazy>::open(&db, "lines");
+    let inventory = Tree::<String, u32>::open(&db, "inventory");
+    inventory.insert(&"apple".to_owned(), &3).unwrap();
+
+    let order = |amount: u32| {
+        (&orders, &lines, &inventory).transaction(|(orders, lines, inventory)| {
+            let id = orders.next_key()?;
+            orders.insert_with_key(&id, &"alice".to_owned())?;
+            lines.insert(&(id, 0), &"apple".to_owned())?;
+            let stock = inventory.get(&"apple".to_owned())?.unwrap_or(0);
+            if stock < amount {
+                return abort(stock);
+            }
+            inventory.insert(&"apple".to_owned(), &(stock - amount))?;
+            Ok(id)
+        })
+    };
+
+    assert_eq!(order(2).unwrap(), 0);
+    match order(2) {
+        Err(TransactionError::Abort(aborted)) => assert_eq!(aborted.downcast::<u32>().unwrap(), 1),
+        res => panic!("expected an aborted transaction, got {:?}", res),
+    }
+    assert_eq!(order(1).unwrap(), 2);
+
+    assert_eq!(orders.len(), 2);
+    assert!(orders.get(&1).unwrap().is_none());
+    assert_eq!(lines.get(&(2, 0)).unwrap().unwrap().deserialize(), "apple");
+    assert!(lines.get(&(1, 0)).unwrap().is_none());
+    assert_eq!(inventory.get(&"apple".to_owned()).unwrap(), Some(0));
+}
+
+#[test]
+fn test_transaction_builder() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let trees: Vec<Tree<u32, u32>> = (0..12)
+        .map(|i| Tree::open(&db, format!("tree{}", i)))
+        .collect();
+    let other = crate::custom_serde::Tree::<
+        u32,
+        String,
+        crate::custom_serde::serialize::BincodeSerDe,
+    >::open(&db, "other");
+
+    let mut builder = TransactionBuilder::new();
+    let handles: Vec<_> = trees.iter().map(|tree| builder.add(tree)).collect();
+    let other_handle = builder.add(&other);
+    // Adding a tree again returns a handle to the same tree.
+    let first = builder.add(&trees[0]);
+
+    let result = builder.transaction(|views| {
+        for (i, handle) in handles.iter().enumerate() {
+            views.get(handle).insert(&1, &(i as u32))?;
+        }
+        views.get(&other_handle).insert(&1, &"one".to_owned())?;
+        Ok(views.get(&first).get(&1)?)
+    });
+    assert_eq!(result.unwrap(), Some(0));
+    assert_eq!(trees[11].get(&1).unwrap(), Some(11));
+    assert_eq!(other.get(&1).unwrap(), Some("one".to_owned()));
+
+    // Aborted transactions don't change any tree.
+    let result = builder.transaction(|views| {
+        views.get(&handles[5]).insert(&2, &2)?;
+        abort::<(), _>(())
+    });
+    assert!(matches!(result, Err(TransactionError::Abort(_))));
+    assert_eq!(trees[5].get(&2).unwrap(), None);
+}
+
+#[test]
+fn test_transaction_errors() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    Tree::<u32, u32>::open(&db, "tree").insert(&0, &0).unwrap();
+    let tree = Tree::<u32, String>::open(&db, "tree");
+
+    // Deserialization errors end the transaction without applying its changes.
+    let result = tree.transaction(|tree| {
+        tree.insert(&1, &"one".to_owned())?;
+        Ok(tree.get(&0)?)
+    });
+    assert!(matches!(
+        result,
+        Err(TransactionError::Storage(Error::Deserialize { .. }))
+    ));
+    assert_eq!(tree.get(&1).unwrap(), None);
+
+    let result = tree.transaction(|tree| {
+        tree.insert(&1, &"one".to_owned())?;
+        if tree.get(&1)?.is_some() {
+            return abort(String::from("aborted"));
+        }
+        Ok(1)
+    });
+    let aborted = match result {
+        Err(TransactionError::Abort(aborted)) => aborted,
+        res => panic!("expected an aborted transaction, got {:?}", res),
+    };
+    assert!(!aborted.is::<&str>());
+    assert_eq!(aborted.downcast_ref::<String>().unwrap(), "aborted");
+    let aborted = aborted.downcast::<u32>().unwrap_err();
+    assert_eq!(aborted.to_string(), r#"transaction aborted: "aborted""#);
+    assert_eq!(tree.get(&1).unwrap(), None);
+}
+
+#[test]
+fn test_transaction_key_encoding() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let tree = Tree::<String, u32>::open(&db, "tree");
+    tree.insert(&"a".to_owned(), &1).unwrap();
+
+    // Keys written inside and outside of transactions use the same encoding.
+    tree.transaction(|tree| {
+        assert_eq!(tree.get(&"a".to_owned())?, Some(1));
+        tree.insert(&"b".to_owned(), &2)?;
+        tree.remove(&"a".to_owned())?;
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(tree.get(&"a".to_owned()).unwrap(), None);
+    assert_eq!(tree.get(&"b".to_owned()).unwrap(), Some(2));
 }
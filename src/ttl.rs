@@ -0,0 +1,487 @@
+//! A [Tree] whose entries can expire after a time-to-live.
+//!
+//! The deadlines are stored in two companion sled trees, one mapping keys to
+//! their deadline and one ordered by deadline, which are updated in the same
+//! transaction as the tree itself. Expired entries are hidden by [TtlTree::get]
+//! and [TtlTree::iter] and removed by [TtlTree::purge_expired], either called
+//! directly or periodically by a [Reaper]. Purging removes the entries from the
+//! tree, so subscribers see them as normal removals.
+//!
+//! # Example
+//! ```
+//! use std::time::Duration;
+//! use typed_sled::ttl::TtlTree;
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let db = sled::Config::new().temporary(true).open().unwrap();
+//!
+//!     let sessions = TtlTree::<u64, String>::try_open(&db, "sessions")?;
+//!     sessions.insert_with_ttl(&1, &"ferris".to_owned(), Duration::from_secs(3600))?;
+//!     sessions.insert_with_ttl(&2, &"corro".to_owned(), Duration::ZERO)?;
+//!     // Entries without a time-to-live never expire.
+//!     sessions.insert(&3, &"admin".to_owned())?;
+//!
+//!     assert_eq!(sessions.get(&2)?, None);
+//!     assert_eq!(sessions.iter().count(), 2);
+//!     assert_eq!(sessions.purge_expired()?, 1);
+//!
+//!     // Purge expired entries every minute until the reaper is dropped.
+//!     let reaper = sessions.start_reaper(Duration::from_secs(60));
+//!     # drop(reaper);
+//!     Ok(())
+//! }
+//! ```
+use crate::{now_millis, Error, Result, Subscriber, Tree, KV};
+use sled::transaction::{ConflictableTransactionResult, TransactionError, TransactionalTree};
+use sled::{IVec, Transactional};
+use std::convert::TryInto;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The number of expired entries removed per transaction by [TtlTree::purge_expired].
+pub const PURGE_BATCH_SIZE: usize = 128;
+
+/// The name of the sled tree mapping the keys of `tree` to their deadline.
+pub fn expiry_tree_name(tree: &str) -> String {
+    format!("{}__typed_sled_ttl_expiry", tree)
+}
+
+/// The name of the sled tree storing the keys of `tree` ordered by deadline.
+pub fn deadline_tree_name(tree: &str) -> String {
+    format!("{}__typed_sled_ttl_deadlines", tree)
+}
+
+/// A [Tree] whose entries can be inserted with a time-to-live.
+///
+/// All writes have to go through the `TtlTree`, writing to the underlying
+/// tree directly leaves stale deadlines behind.
+pub struct TtlTree<K, V> {
+    tree: Tree<K, V>,
+    // Key -> deadline
+    expiry: sled::Tree,
+    // Deadline followed by the key -> empty
+    deadlines: sled::Tree,
+}
+
+impl<K, V> Clone for TtlTree<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            expiry: self.expiry.clone(),
+            deadlines: self.deadlines.clone(),
+        }
+    }
+}
+
+impl<K, V> TtlTree<K, V> {
+    /// Open the tree together with its deadline trees.
+    ///
+    /// # Panics
+    ///
+    /// Panics if sled fails to open the trees. Use [try_open][TtlTree::try_open]
+    /// to handle the error instead.
+    pub fn open<T: AsRef<str>>(db: &sled::Db, id: T) -> Self {
+        Self::try_open(db, id).unwrap()
+    }
+
+    /// Open the tree like [open][TtlTree::open], but return an error
+    /// instead of panicking if sled fails to open the trees.
+    pub fn try_open<T: AsRef<str>>(db: &sled::Db, id: T) -> Result<Self> {
+        Ok(Self {
+            tree: Tree::try_open(db, id.as_ref())?,
+            expiry: db.open_tree(expiry_tree_name(id.as_ref()))?,
+            deadlines: db.open_tree(deadline_tree_name(id.as_ref()))?,
+        })
+    }
+
+    /// Returns the underlying tree, which includes expired entries that haven't been purged yet.
+    /// Writing to it directly leaves stale deadlines behind.
+    pub fn tree(&self) -> &Tree<K, V> {
+        &self.tree
+    }
+
+    /// Insert a key to a new value which never expires, returning the last value if it was set
+    /// and hasn't expired.
+    pub fn insert(&self, key: &K, value: &V) -> Result<Option<V>>
+    where
+        K: KV,
+        V: KV,
+    {
+        self.write(key, Some(value), None)
+    }
+
+    /// Insert a key to a new value which expires after `ttl`, returning the last value if it was set
+    /// and hasn't expired.
+    pub fn insert_with_ttl(&self, key: &K, value: &V, ttl: Duration) -> Result<Option<V>>
+    where
+        K: KV,
+        V: KV,
+    {
+        let deadline = now_millis().saturating_add(ttl.as_millis().try_into().unwrap_or(u64::MAX));
+        self.write(key, Some(value), Some(deadline))
+    }
+
+    /// Retrieve a value from the tree if it exists and hasn't expired.
+    pub fn get(&self, key: &K) -> Result<Option<V>>
+    where
+        K: KV,
+        V: KV,
+    {
+        let key_bytes = crate::encode_key(key)?;
+        if self.is_expired(&key_bytes, now_millis())? {
+            return Ok(None);
+        }
+        self.tree
+            .inner
+            .get(&key_bytes)?
+            .map(|v| crate::decode_value(&self.tree.name(), &key_bytes, &v))
+            .transpose()
+    }
+
+    /// Returns `true` if the tree contains a value for the key which hasn't expired.
+    pub fn contains_key(&self, key: &K) -> Result<bool>
+    where
+        K: KV,
+    {
+        let key_bytes = crate::encode_key(key)?;
+        Ok(!self.is_expired(&key_bytes, now_millis())?
+            && self.tree.inner.contains_key(&key_bytes)?)
+    }
+
+    /// Returns the deadline of an entry, `None` if the entry doesn't exist,
+    /// never expires or has expired.
+    pub fn expires_at(&self, key: &K) -> Result<Option<SystemTime>>
+    where
+        K: KV,
+    {
+        let key_bytes = crate::encode_key(key)?;
+        let deadline = match self.expiry.get(&key_bytes)? {
+            Some(deadline) => decode_deadline(&deadline),
+            None => return Ok(None),
+        };
+        if deadline <= now_millis() || !self.tree.inner.contains_key(&key_bytes)? {
+            return Ok(None);
+        }
+        Ok(Some(UNIX_EPOCH + Duration::from_millis(deadline)))
+    }
+
+    /// Delete a value, returning the old value if it existed and hasn't expired.
+    pub fn remove(&self, key: &K) -> Result<Option<V>>
+    where
+        K: KV,
+        V: KV,
+    {
+        self.write(key, None, None)
+    }
+
+    /// Create a double-ended iterator over the tuples of keys and
+    /// values in this tree, skipping expired entries.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Result<(K, V)>> + '_
+    where
+        K: KV,
+        V: KV,
+    {
+        let now = now_millis();
+        let name = self.tree.name();
+        self.tree.inner.iter().filter_map(move |res| {
+            let (key_bytes, value_bytes) = match res {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e.into())),
+            };
+            match self.is_expired(&key_bytes, now) {
+                Ok(true) => None,
+                Ok(false) => Some(crate::decode_kv(&name, &key_bytes, &value_bytes)),
+                Err(e) => Some(Err(e)),
+            }
+        })
+    }
+
+    /// Subscribe to all changes of keys starting with `prefix`, see [Tree::watch_prefix].
+    /// Purged entries show up as [Event::Remove][crate::Event::Remove].
+    pub fn watch_prefix(&self, prefix: &K) -> Result<Subscriber<K, V>>
+    where
+        K: KV,
+    {
+        self.tree.watch_prefix(prefix)
+    }
+
+    /// Subscribe to all changes, see [Tree::watch_all].
+    /// Purged entries show up as [Event::Remove][crate::Event::Remove].
+    pub fn watch_all(&self) -> Subscriber<K, V>
+    where
+        K: KV,
+    {
+        self.tree.watch_all()
+    }
+
+    /// Remove all expired entries, [PURGE_BATCH_SIZE] entries per transaction,
+    /// returning the number of removed entries.
+    pub fn purge_expired(&self) -> Result<usize> {
+        let now = now_millis();
+        // All deadline entries up to and including `now`.
+        let end = now.saturating_add(1).to_be_bytes();
+        let mut purged = 0;
+        loop {
+            let batch = self
+                .deadlines
+                .range(..end)
+                .keys()
+                .take(PURGE_BATCH_SIZE)
+                .collect::<sled::Result<Vec<IVec>>>()?;
+            purged += self.transaction(|tree, expiry, deadlines| {
+                let mut purged = 0;
+                for entry in &batch {
+                    let (deadline, key_bytes) = entry.split_at(8);
+                    deadlines.remove(entry)?;
+                    // Inserting again changes or clears the deadline of a key.
+                    if expiry.get(key_bytes)?.as_deref() == Some(deadline) {
+                        expiry.remove(key_bytes)?;
+                        if tree.remove(key_bytes)?.is_some() {
+                            purged += 1;
+                        }
+                    }
+                }
+                Ok(purged)
+            })?;
+            if batch.len() < PURGE_BATCH_SIZE {
+                return Ok(purged);
+            }
+        }
+    }
+
+    /// Spawn a thread which calls [purge_expired][TtlTree::purge_expired] every `interval`
+    /// until the returned [Reaper] is dropped.
+    pub fn start_reaper(&self, interval: Duration) -> Reaper
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let (errors_sender, errors) = mpsc::channel();
+        let tree = self.clone();
+        let thread = thread::spawn(move || loop {
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Err(e) = tree.purge_expired() {
+                        let _ = errors_sender.send(e);
+                    }
+                }
+                // Dropping the sender stops the reaper.
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+        });
+        Reaper {
+            stop: Some(stop),
+            errors: Mutex::new(errors),
+            thread: Some(thread),
+        }
+    }
+
+    fn is_expired(&self, key_bytes: &[u8], now: u64) -> Result<bool> {
+        Ok(self
+            .expiry
+            .get(key_bytes)?
+            .is_some_and(|deadline| decode_deadline(&deadline) <= now))
+    }
+
+    // Writes or removes a single entry and replaces its deadline.
+    fn write(&self, key: &K, value: Option<&V>, deadline: Option<u64>) -> Result<Option<V>>
+    where
+        K: KV,
+        V: KV,
+    {
+        let key_bytes = crate::encode_key(key)?;
+        let value_bytes = value.map(crate::encode_value).transpose()?;
+        let now = now_millis();
+        let old_value = self.transaction(|tree, expiry, deadlines| {
+            let old_value = match &value_bytes {
+                Some(value_bytes) => tree.insert(key_bytes.as_slice(), value_bytes.as_slice())?,
+                None => tree.remove(key_bytes.as_slice())?,
+            };
+            let old_deadline = match deadline {
+                Some(deadline) => {
+                    deadlines.insert(deadline_entry(deadline, &key_bytes), &[])?;
+                    expiry.insert(key_bytes.as_slice(), &deadline.to_be_bytes())?
+                }
+                None => expiry.remove(key_bytes.as_slice())?,
+            };
+            let old_deadline = old_deadline.map(|deadline| decode_deadline(&deadline));
+            if let Some(old_deadline) = old_deadline {
+                if Some(old_deadline) != deadline {
+                    deadlines.remove(deadline_entry(old_deadline, &key_bytes))?;
+                }
+            }
+            // Expired values are gone as far as the caller is concerned.
+            match old_deadline {
+                Some(old_deadline) if old_deadline <= now => Ok(None),
+                _ => Ok(old_value),
+            }
+        })?;
+        old_value
+            .map(|v| crate::decode_value(&self.tree.name(), &key_bytes, &v))
+            .transpose()
+    }
+
+    // Runs `f` in a transaction over the tree and its deadline trees.
+    fn transaction<A, F>(&self, f: F) -> Result<A>
+    where
+        F: Fn(
+            &TransactionalTree,
+            &TransactionalTree,
+            &TransactionalTree,
+        ) -> ConflictableTransactionResult<A, Error>,
+    {
+        (&self.tree.inner, &self.expiry, &self.deadlines)
+            .transaction(|(tree, expiry, deadlines)| f(tree, expiry, deadlines))
+            .map_err(|e| match e {
+                TransactionError::Abort(e) => e,
+                TransactionError::Storage(e) => e.into(),
+            })
+    }
+}
+
+/// A thread purging the expired entries of a [TtlTree] periodically,
+/// see [TtlTree::start_reaper]. Dropping it stops the thread.
+pub struct Reaper {
+    stop: Option<Sender<()>>,
+    errors: Mutex<Receiver<Error>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Reaper {
+    /// Returns the errors the reaper ran into since the last call.
+    ///
+    /// The reaper keeps running after an error and retries on the next run.
+    pub fn errors(&self) -> Vec<Error> {
+        self.errors
+            .lock()
+            .expect("Reaper: poisoned lock")
+            .try_iter()
+            .collect()
+    }
+
+    /// Stop the reaper and wait for it to finish.
+    pub fn stop(self) {
+        // Dropping stops and joins the thread.
+        drop(self)
+    }
+}
+
+impl Drop for Reaper {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Deadlines are stored big-endian so the deadline tree is ordered by deadline.
+fn deadline_entry(deadline: u64, key_bytes: &[u8]) -> Vec<u8> {
+    let mut entry = deadline.to_be_bytes().to_vec();
+    entry.extend_from_slice(key_bytes);
+    entry
+}
+
+fn decode_deadline(bytes: &[u8]) -> u64 {
+    let mut deadline = [0; 8];
+    deadline.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Event;
+
+    #[test]
+    fn test_expiry() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = TtlTree::<u32, String>::open(&db, "sessions");
+
+        tree.insert_with_ttl(&1, &"a".to_owned(), Duration::from_secs(3600))
+            .unwrap();
+        tree.insert_with_ttl(&2, &"b".to_owned(), Duration::ZERO)
+            .unwrap();
+        tree.insert(&3, &"c".to_owned()).unwrap();
+
+        assert_eq!(tree.get(&1).unwrap(), Some("a".to_owned()));
+        assert_eq!(tree.get(&2).unwrap(), None);
+        assert!(!tree.contains_key(&2).unwrap());
+        assert!(tree.expires_at(&1).unwrap().unwrap() > SystemTime::now());
+        assert_eq!(tree.expires_at(&2).unwrap(), None);
+        assert_eq!(tree.expires_at(&3).unwrap(), None);
+        let keys: Vec<u32> = tree.iter().map(|res| res.unwrap().0).collect();
+        assert_eq!(keys, vec![1, 3]);
+        // Expired entries stay in the tree until they are purged.
+        assert_eq!(tree.tree().len(), 3);
+
+        // Expired values aren't returned when they are overwritten.
+        assert_eq!(tree.insert(&2, &"b".to_owned()).unwrap(), None);
+        assert_eq!(tree.get(&2).unwrap(), Some("b".to_owned()));
+        assert_eq!(tree.purge_expired().unwrap(), 0);
+        assert_eq!(tree.tree().len(), 3);
+
+        // Only the latest deadline counts.
+        tree.insert_with_ttl(&3, &"c".to_owned(), Duration::ZERO)
+            .unwrap();
+        tree.insert_with_ttl(&3, &"c".to_owned(), Duration::from_secs(3600))
+            .unwrap();
+        tree.insert_with_ttl(&1, &"a".to_owned(), Duration::ZERO)
+            .unwrap();
+        assert_eq!(tree.remove(&1).unwrap(), None);
+        assert_eq!(tree.purge_expired().unwrap(), 0);
+        assert_eq!(tree.get(&3).unwrap(), Some("c".to_owned()));
+        assert!(tree.deadlines.iter().count() == 1 && tree.expiry.len() == 1);
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = TtlTree::<u32, u32>::open(&db, "cache");
+        let mut subscriber = tree.watch_all();
+
+        let n = PURGE_BATCH_SIZE as u32 * 2 + 1;
+        for i in 0..n {
+            tree.insert_with_ttl(&i, &i, Duration::ZERO).unwrap();
+        }
+        tree.insert_with_ttl(&n, &n, Duration::from_secs(3600))
+            .unwrap();
+        for _ in 0..=n {
+            assert!(matches!(subscriber.next(), Some(Ok(Event::Insert { .. }))));
+        }
+
+        assert_eq!(tree.purge_expired().unwrap(), n as usize);
+        assert_eq!(tree.tree().len(), 1);
+        assert_eq!(tree.deadlines.len(), 1);
+        assert_eq!(tree.expiry.len(), 1);
+        // Purged entries are normal removals for subscribers.
+        let mut removed: Vec<u32> = (0..n)
+            .map(|_| match subscriber.next() {
+                Some(Ok(Event::Remove { key })) => key,
+                event => panic!("unexpected event {:?}", event.map(|e| e.is_ok())),
+            })
+            .collect();
+        removed.sort_unstable();
+        assert_eq!(removed, (0..n).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_reaper() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = TtlTree::<u32, u32>::open(&db, "cache");
+        let reaper = tree.start_reaper(Duration::from_millis(10));
+
+        tree.insert_with_ttl(&0, &0, Duration::from_millis(20))
+            .unwrap();
+        let start = std::time::Instant::now();
+        while !tree.tree().is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(10), "not purged");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(reaper.errors().is_empty());
+        reaper.stop();
+    }
+}
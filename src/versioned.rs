@@ -0,0 +1,461 @@
+//! Schema versioning with upgrades on read.
+//!
+//! Values wrapped in [Versioned] are stored together with a version tag.
+//! When a value with an older version is read, it is deserialized as the type
+//! of that version and upgraded along the chain of [Version::Previous] types,
+//! so a tree always hands back the latest type.
+//!
+//! # Example
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use typed_sled::versioned::{Version, VersionedTree};
+//!
+//! #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+//! struct UserV1 {
+//!     name: String,
+//! }
+//!
+//! impl Version for UserV1 {
+//!     const VERSION: u32 = 1;
+//!     // The first version is its own previous version.
+//!     type Previous = Self;
+//! }
+//!
+//! #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+//! struct User {
+//!     name: String,
+//!     age: Option<u8>,
+//! }
+//!
+//! impl Version for User {
+//!     const VERSION: u32 = 2;
+//!     type Previous = UserV1;
+//! }
+//!
+//! impl From<UserV1> for User {
+//!     fn from(user: UserV1) -> Self {
+//!         User {
+//!             name: user.name,
+//!             age: None,
+//!         }
+//!     }
+//! }
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let db = sled::Config::new().temporary(true).open().unwrap();
+//!
+//!     let old_tree = VersionedTree::<u32, UserV1>::open(&db, "users");
+//!     old_tree.insert(&1, &UserV1 { name: "Ferris".to_owned() })?;
+//!
+//!     // Persist upgraded values when reading them.
+//!     let tree = VersionedTree::<u32, User>::open(&db, "users").write_back(true);
+//!     let user = User { name: "Ferris".to_owned(), age: None };
+//!     assert_eq!(tree.get(&1)?, Some(user));
+//!     assert_eq!(tree.inner().get(&1)?.unwrap().stored_version(), User::VERSION);
+//!     Ok(())
+//! }
+//! ```
+use crate::{Result, Tree, KV};
+use core::fmt;
+use core::marker::PhantomData;
+use serde::de::{self, SeqAccess};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::any::type_name;
+use std::ops::{Deref, DerefMut};
+
+/// Implement on every version of a value type to make it usable with [Versioned].
+///
+/// Versions form a chain through [Previous][Version::Previous]: reading a value
+/// stored with an older version deserializes it as that version and converts it
+/// with `Into` until the requested version is reached.
+/// The oldest version sets `Previous = Self`.
+pub trait Version: KV {
+    /// The version tag written in front of the value.
+    /// Has to be greater than the version of `Previous`.
+    const VERSION: u32;
+
+    /// The previous version of this type.
+    type Previous: Version + Into<Self>;
+}
+
+/// A value that is serialized together with its version tag.
+///
+/// Deserializing upgrades values stored with an older version to `V`.
+#[derive(Debug, Clone)]
+pub struct Versioned<V> {
+    value: V,
+    stored_version: u32,
+}
+
+impl<V: Version> Versioned<V> {
+    /// Wrap a value of the latest version.
+    pub fn new(value: V) -> Self {
+        Self {
+            value,
+            stored_version: V::VERSION,
+        }
+    }
+
+    /// The version the value was stored with.
+    pub fn stored_version(&self) -> u32 {
+        self.stored_version
+    }
+
+    /// Returns `true` if the value was stored with an older version and
+    /// has been upgraded during deserialization.
+    pub fn is_upgraded(&self) -> bool {
+        self.stored_version != V::VERSION
+    }
+
+    /// Returns the wrapped value.
+    pub fn into_inner(self) -> V {
+        self.value
+    }
+}
+
+impl<V> Deref for Versioned<V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<V> DerefMut for Versioned<V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+impl<V: PartialEq> PartialEq for Versioned<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<V: Version> Serialize for Versioned<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        VersionedRef(&self.value).serialize(serializer)
+    }
+}
+
+// Serializes a borrowed value like a `Versioned<V>`, without copying it.
+struct VersionedRef<'a, V>(&'a V);
+
+impl<V: Version> Serialize for VersionedRef<'_, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        // Values are always written with the latest version.
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&V::VERSION)?;
+        tuple.serialize_element(self.0)?;
+        tuple.end()
+    }
+}
+
+impl<'de, V: Version> Deserialize<'de> for Versioned<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        deserializer.deserialize_tuple(2, VersionedVisitor(PhantomData))
+    }
+}
+
+struct VersionedVisitor<V>(PhantomData<fn() -> V>);
+
+impl<'de, V: Version> de::Visitor<'de> for VersionedVisitor<V> {
+    type Value = Versioned<V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a version tag followed by a {}", type_name::<V>())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> core::result::Result<Self::Value, A::Error> {
+        let stored_version: u32 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let value = next_element_of_version::<V, A>(stored_version, &mut seq)?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(Versioned {
+            value,
+            stored_version,
+        })
+    }
+}
+
+// Walks down the chain of previous versions until `version` is found.
+fn next_element_of_version<'de, V: Version, A: SeqAccess<'de>>(
+    version: u32,
+    seq: &mut A,
+) -> core::result::Result<Option<V>, A::Error> {
+    if version == V::VERSION {
+        seq.next_element()
+    } else if version < V::VERSION && V::Previous::VERSION < V::VERSION {
+        Ok(next_element_of_version::<V::Previous, A>(version, seq)?.map(Into::into))
+    } else {
+        Err(de::Error::custom(format!(
+            "unknown version {} of {}",
+            version,
+            type_name::<V>()
+        )))
+    }
+}
+
+/// A [Tree] storing [Versioned] values, which hands back the latest version of `V`.
+///
+/// With [write_back][VersionedTree::write_back] enabled, values that were
+/// upgraded on read are written back in their upgraded form.
+/// All other methods of the underlying tree are available through `Deref`.
+#[derive(Debug)]
+pub struct VersionedTree<K, V> {
+    inner: Tree<K, Versioned<V>>,
+    write_back: bool,
+}
+
+impl<K, V> Clone for VersionedTree<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            write_back: self.write_back,
+        }
+    }
+}
+
+impl<K, V> VersionedTree<K, V> {
+    /// Initialize a typed tree. The id identifies the tree to be opened from the db.
+    ///
+    /// # Panics
+    ///
+    /// Panics if sled fails to open the tree. Use [try_open][Self::try_open]
+    /// to handle the error instead.
+    pub fn open<T: AsRef<str>>(db: &sled::Db, id: T) -> Self {
+        Self::try_open(db, id).unwrap()
+    }
+
+    /// Initialize a typed tree like [open][Self::open], but return an
+    /// error instead of panicking if sled fails to open the tree.
+    pub fn try_open<T: AsRef<str>>(db: &sled::Db, id: T) -> Result<Self> {
+        Ok(Self {
+            inner: Tree::try_open(db, id)?,
+            write_back: false,
+        })
+    }
+
+    /// Enable or disable writing upgraded values back to the tree when they are read.
+    /// Disabled by default.
+    pub fn write_back(mut self, write_back: bool) -> Self {
+        self.write_back = write_back;
+        self
+    }
+
+    /// Returns the underlying tree.
+    pub fn inner(&self) -> &Tree<K, Versioned<V>> {
+        &self.inner
+    }
+
+    /// Insert a key to a new value, returning the last value if it was set.
+    pub fn insert(&self, key: &K, value: &V) -> Result<Option<V>>
+    where
+        K: KV,
+        V: Version,
+    {
+        let key_bytes = crate::encode_key(key)?;
+        let old_value = self
+            .inner
+            .inner
+            .insert(&key_bytes, crate::encode_value(&VersionedRef(value))?)?;
+        Ok(self
+            .inner
+            .decode_opt_value(&key_bytes, old_value)?
+            .map(Versioned::into_inner))
+    }
+
+    /// Retrieve a value from the Tree if it exists.
+    pub fn get(&self, key: &K) -> Result<Option<V>>
+    where
+        K: KV,
+        V: Version,
+    {
+        let key_bytes = crate::encode_key(key)?;
+        match self.inner.inner.get(&key_bytes)? {
+            Some(value_bytes) => Ok(Some(self.decode(&key_bytes, &value_bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete a value, returning the old value if it existed.
+    pub fn remove(&self, key: &K) -> Result<Option<V>>
+    where
+        K: KV,
+        V: Version,
+    {
+        Ok(self.inner.remove(key)?.map(Versioned::into_inner))
+    }
+
+    /// Create a double-ended iterator over the tuples of keys and
+    /// values in this tree, upgrading the values if necessary.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Result<(K, V)>> + '_
+    where
+        K: KV,
+        V: Version,
+    {
+        let name = self.inner.name();
+        self.inner.inner.iter().map(move |res| {
+            let (key_bytes, value_bytes) = res?;
+            let key = crate::decode_key(&name, &key_bytes)?;
+            Ok((key, self.decode(&key_bytes, &value_bytes)?))
+        })
+    }
+
+    /// Upgrade and rewrite all values stored with an older version.
+    /// Returns the number of upgraded values.
+    pub fn upgrade_all(&self) -> Result<usize>
+    where
+        V: Version,
+    {
+        let mut upgraded = 0;
+        for res in self.inner.inner.iter() {
+            let (key_bytes, value_bytes) = res?;
+            if self.upgrade(&key_bytes, &value_bytes)?.1 {
+                upgraded += 1;
+            }
+        }
+        Ok(upgraded)
+    }
+
+    fn decode(&self, key_bytes: &[u8], value_bytes: &[u8]) -> Result<V>
+    where
+        V: Version,
+    {
+        if self.write_back {
+            return Ok(self.upgrade(key_bytes, value_bytes)?.0);
+        }
+        let versioned: Versioned<V> =
+            crate::decode_value(&self.inner.name(), key_bytes, value_bytes)?;
+        Ok(versioned.into_inner())
+    }
+
+    // Decodes a value and writes it back if it was upgraded. Compare and swap
+    // makes sure concurrent writes aren't overwritten with the upgraded old value.
+    fn upgrade(&self, key_bytes: &[u8], value_bytes: &[u8]) -> Result<(V, bool)>
+    where
+        V: Version,
+    {
+        let versioned: Versioned<V> =
+            crate::decode_value(&self.inner.name(), key_bytes, value_bytes)?;
+        if !versioned.is_upgraded() {
+            return Ok((versioned.into_inner(), false));
+        }
+        let new_bytes = crate::encode_value(&versioned)?;
+        let swapped = self
+            .inner
+            .inner
+            .compare_and_swap(key_bytes, Some(value_bytes), Some(new_bytes))?
+            .is_ok();
+        Ok((versioned.into_inner(), swapped))
+    }
+}
+
+impl<K, V> Deref for VersionedTree<K, V> {
+    type Target = Tree<K, Versioned<V>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    struct V1(u32);
+
+    impl Version for V1 {
+        const VERSION: u32 = 1;
+        type Previous = Self;
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    struct V2(u64, bool);
+
+    impl Version for V2 {
+        const VERSION: u32 = 2;
+        type Previous = V1;
+    }
+
+    impl From<V1> for V2 {
+        fn from(v: V1) -> Self {
+            V2(v.0.into(), false)
+        }
+    }
+
+    // Values don't need to implement Clone.
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct V3(String);
+
+    impl Version for V3 {
+        const VERSION: u32 = 3;
+        type Previous = V2;
+    }
+
+    impl From<V2> for V3 {
+        fn from(v: V2) -> Self {
+            V3(format!("{}-{}", v.0, v.1))
+        }
+    }
+
+    #[test]
+    fn test_upgrade_chain() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+
+        VersionedTree::<u32, V1>::open(&db, "test_tree")
+            .insert(&1, &V1(10))
+            .unwrap();
+        VersionedTree::<u32, V2>::open(&db, "test_tree")
+            .insert(&2, &V2(20, true))
+            .unwrap();
+
+        let tree = VersionedTree::<u32, V3>::open(&db, "test_tree");
+        assert_eq!(tree.insert(&3, &V3("3".to_owned())).unwrap(), None);
+        assert_eq!(
+            tree.insert(&3, &V3("30".to_owned())).unwrap(),
+            Some(V3("3".to_owned()))
+        );
+        let values: Vec<_> = tree.iter().map(|r| r.unwrap()).collect();
+        assert_eq!(
+            values,
+            vec![
+                (1, V3("10-false".to_owned())),
+                (2, V3("20-true".to_owned())),
+                (3, V3("30".to_owned())),
+            ]
+        );
+        // Without write back nothing changes on disk.
+        assert_eq!(tree.inner().get(&1).unwrap().unwrap().stored_version(), 1);
+        assert_eq!(tree.upgrade_all().unwrap(), 2);
+        assert_eq!(tree.inner().get(&1).unwrap().unwrap().stored_version(), 3);
+        assert_eq!(tree.inner().get(&2).unwrap().unwrap().stored_version(), 3);
+
+        // Values newer than the requested version can't be read.
+        let old_tree = VersionedTree::<u32, V2>::open(&db, "test_tree");
+        assert!(matches!(
+            old_tree.get(&1),
+            Err(crate::Error::Deserialize { .. })
+        ));
+    }
+
+    #[test]
+    fn test_write_back() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+
+        VersionedTree::<u32, V1>::open(&db, "test_tree")
+            .insert(&1, &V1(10))
+            .unwrap();
+
+        let tree = VersionedTree::<u32, V2>::open(&db, "test_tree").write_back(true);
+        assert_eq!(tree.get(&1).unwrap(), Some(V2(10, false)));
+        assert_eq!(tree.inner().get(&1).unwrap().unwrap().stored_version(), 2);
+    }
+}
@@ -0,0 +1,141 @@
+//! Derive macros for [typed-sled](https://docs.rs/typed-sled).
+//!
+//! Use them through the `derive` feature of typed-sled instead of depending on this crate directly.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields};
+
+/// Derive `typed_sled::searchable::Searchable`.
+///
+/// Fields annotated with `#[search(text)]`, `#[search(string)]`, `#[search(numeric)]`,
+/// `#[search(date)]` or `#[search(facet)]` are indexed, all other fields are ignored.
+/// See the `typed_sled::searchable` module for details.
+#[proc_macro_derive(Searchable, attributes(search))]
+pub fn derive_searchable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    searchable(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+enum Kind {
+    Text,
+    String,
+    Numeric,
+    Date,
+    Facet,
+}
+
+fn searchable(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "Searchable can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "Searchable can only be derived for structs",
+            ))
+        }
+    };
+
+    let krate = quote!(::typed_sled::searchable);
+    let mut search_fields = Vec::new();
+    let mut add_fields = Vec::new();
+    for field in fields {
+        let kind = match search_kind(&field.attrs)? {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let ident = field.ident.as_ref().expect("named fields have an ident");
+        let name = ident.to_string();
+        let ty = &field.ty;
+        let (trait_name, kind, add) = match kind {
+            Kind::Text => (
+                quote!(TextValue),
+                quote!(#krate::FieldKind::Text),
+                quote!(add_text),
+            ),
+            Kind::String => (
+                quote!(TextValue),
+                quote!(#krate::FieldKind::String),
+                quote!(add_text),
+            ),
+            Kind::Facet => (
+                quote!(TextValue),
+                quote!(#krate::FieldKind::Facet),
+                quote!(add_text),
+            ),
+            Kind::Date => (
+                quote!(DateValue),
+                quote!(#krate::FieldKind::Date),
+                quote!(add_date),
+            ),
+            Kind::Numeric => (
+                quote!(NumericValue),
+                quote!(<#ty as #krate::NumericValue>::KIND),
+                quote!(add_numeric),
+            ),
+        };
+        search_fields.push(quote! {
+            #krate::SearchField {
+                name: #name,
+                kind: #kind,
+                multi_valued: <#ty as #krate::#trait_name>::MULTI_VALUED,
+            }
+        });
+        add_fields.push(quote! {
+            document.#add(#name, &self.#ident);
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #krate::Searchable for #ident #ty_generics #where_clause {
+            const FIELDS: &'static [#krate::SearchField] = &[#(#search_fields),*];
+
+            fn add_fields(&self, document: &mut #krate::SearchDocument) {
+                #(#add_fields)*
+            }
+        }
+    })
+}
+
+fn search_kind(attrs: &[syn::Attribute]) -> Result<Option<Kind>, Error> {
+    let mut kind = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("search")) {
+        attr.parse_nested_meta(|meta| {
+            let parsed = if meta.path.is_ident("text") {
+                Kind::Text
+            } else if meta.path.is_ident("string") {
+                Kind::String
+            } else if meta.path.is_ident("numeric") {
+                Kind::Numeric
+            } else if meta.path.is_ident("date") {
+                Kind::Date
+            } else if meta.path.is_ident("facet") {
+                Kind::Facet
+            } else {
+                return Err(
+                    meta.error("expected one of `text`, `string`, `numeric`, `date` or `facet`")
+                );
+            };
+            if kind.replace(parsed).is_some() {
+                return Err(Error::new(
+                    attr.span(),
+                    "a field can only be indexed in one way",
+                ));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(kind)
+}